@@ -0,0 +1,250 @@
+//! A command line tool for the servers admin interface.
+//! It sends one request to a server running on this machine,
+//! and prints the response.
+
+use std::{
+	fs,
+	path::PathBuf,
+	process::ExitCode,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::{Parser, Subcommand};
+use foundation::{
+	admin::{AdminAuth, AdminRequest, AdminResponse, ClientStatus, ServerStats},
+	networking::{
+		json::{read_message, write_message},
+		ADMIN_PORT,
+	},
+};
+use tokio::net::TcpStream;
+
+/// # Args
+/// Command line arguments for the admin tool.
+#[derive(Parser)]
+#[command(name = "chatkit-admin", about = "controls a running chat server")]
+struct Args {
+	/// the servers admin port
+	#[arg(short, long, default_value_t = ADMIN_PORT)]
+	port: u16,
+
+	/// the file the server wrote its admin token to
+	#[arg(short, long, default_value = "admin.token")]
+	token_file: PathBuf,
+
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// lists connected clients
+	Clients,
+	/// sends a notice to everyone
+	Notice {
+		message: Vec<String>,
+	},
+	/// disconnects a user, by name or uuid
+	Kick {
+		user: String,
+		reason: Vec<String>,
+	},
+	/// bans a username, uuid or ip address
+	Ban {
+		target: String,
+		/// how long the ban lasts, such as 30m or 7d
+		#[arg(short, long)]
+		duration: Option<String>,
+		reason: Vec<String>,
+	},
+	/// reads the servers config file again
+	Reload,
+	/// shows what the server is doing
+	Stats,
+	/// stops the server
+	Shutdown,
+	/// lists loaded plugins
+	Plugins,
+	/// loads a library from the plugins directory
+	LoadPlugin {
+		file: String,
+	},
+	EnablePlugin {
+		id: String,
+	},
+	DisablePlugin {
+		id: String,
+	},
+	PausePlugin {
+		id: String,
+	},
+	ResumePlugin {
+		id: String,
+	},
+	ReloadPlugin {
+		id: String,
+	},
+	UnloadPlugin {
+		id: String,
+	},
+}
+
+impl Command {
+	fn into_request(self) -> AdminRequest {
+		match self {
+			Self::Clients => AdminRequest::ListClients,
+			Self::Notice { message } => AdminRequest::Broadcast {
+				message: message.join(" "),
+			},
+			Self::Kick { user, reason } => AdminRequest::Kick {
+				user,
+				reason: reason.join(" "),
+			},
+			Self::Ban {
+				target,
+				duration,
+				reason,
+			} => AdminRequest::Ban {
+				target,
+				duration,
+				reason: reason.join(" "),
+			},
+			Self::Reload => AdminRequest::ReloadConfig,
+			Self::Stats => AdminRequest::Stats,
+			Self::Shutdown => AdminRequest::Shutdown,
+			Self::Plugins => AdminRequest::ListPlugins,
+			Self::LoadPlugin { file } => AdminRequest::LoadPlugin { file },
+			Self::EnablePlugin { id } => AdminRequest::EnablePlugin { id },
+			Self::DisablePlugin { id } => AdminRequest::DisablePlugin { id },
+			Self::PausePlugin { id } => AdminRequest::PausePlugin { id },
+			Self::ResumePlugin { id } => AdminRequest::ResumePlugin { id },
+			Self::ReloadPlugin { id } => AdminRequest::ReloadPlugin { id },
+			Self::UnloadPlugin { id } => AdminRequest::UnloadPlugin { id },
+		}
+	}
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+	let args = Args::parse();
+
+	let token = match fs::read_to_string(&args.token_file) {
+		Ok(token) => token.trim().to_string(),
+		Err(e) => {
+			eprintln!("can't read {}: {}", args.token_file.display(), e);
+			return ExitCode::FAILURE;
+		}
+	};
+
+	let mut stream = match TcpStream::connect(("127.0.0.1", args.port)).await {
+		Ok(stream) => stream,
+		Err(e) => {
+			eprintln!("can't reach the server on port {}: {}", args.port, e);
+			return ExitCode::FAILURE;
+		}
+	};
+
+	write_message(&mut stream, AdminAuth { token }).await;
+	match read_message::<_, AdminResponse>(&mut stream).await {
+		Ok(AdminResponse::Ok) => {}
+		Ok(AdminResponse::Error { reason }) => {
+			eprintln!("error: {}", reason);
+			return ExitCode::FAILURE;
+		}
+		Ok(_) | Err(_) => {
+			eprintln!("the server didn't accept the token");
+			return ExitCode::FAILURE;
+		}
+	}
+
+	write_message(&mut stream, args.command.into_request()).await;
+	let response = match read_message::<_, AdminResponse>(&mut stream).await {
+		Ok(response) => response,
+		Err(e) => {
+			eprintln!("no response from the server: {}", e);
+			return ExitCode::FAILURE;
+		}
+	};
+
+	match response {
+		AdminResponse::Ok => println!("ok"),
+		AdminResponse::Error { reason } => {
+			eprintln!("error: {}", reason);
+			return ExitCode::FAILURE;
+		}
+		AdminResponse::Plugins { plugins } => {
+			if plugins.is_empty() {
+				println!("no plugins loaded");
+			}
+			for plugin in plugins {
+				println!(
+					"{} {} ({}) {} - {}",
+					plugin.details.id,
+					plugin.details.version,
+					plugin.details.display_name,
+					plugin.state,
+					plugin.path
+				);
+			}
+		}
+		AdminResponse::Clients { clients } => print_clients(clients),
+		AdminResponse::Stats { stats } => print_stats(stats),
+	}
+	ExitCode::SUCCESS
+}
+
+fn print_clients(clients: Vec<ClientStatus>) {
+	if clients.is_empty() {
+		println!("no one is connected");
+	}
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |d| d.as_secs());
+
+	for client in clients {
+		println!(
+			"{} {} {} {} connected {} ago, {} session(s)",
+			client.name,
+			client.uuid,
+			client.address,
+			client.role,
+			format_secs(now.saturating_sub(client.connected_at)),
+			client.sessions
+		);
+	}
+}
+
+fn print_stats(stats: ServerStats) {
+	println!("uptime:          {}", format_secs(stats.uptime_secs));
+	println!("clients:         {}", stats.clients);
+	println!("sessions:        {}", stats.sessions);
+	println!("remote clients:  {}", stats.remote_clients);
+	println!("bots:            {}", stats.bots);
+	println!("global messages: {}", stats.global_messages);
+	println!("plugins:         {}", stats.plugins);
+	println!("bans:            {}", stats.bans);
+	println!("accounts:        {}", stats.accounts);
+}
+
+/// Shows a number of seconds in its largest two units, such as "2h 5m".
+fn format_secs(secs: u64) -> String {
+	let units = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+	let parts: Vec<String> = units
+		.iter()
+		.scan(secs, |left, (unit, size)| {
+			let count = *left / size;
+			*left %= size;
+			Some((count, unit))
+		})
+		.skip_while(|(count, _)| *count == 0)
+		.take(2)
+		.filter(|(count, _)| *count != 0)
+		.map(|(count, unit)| format!("{}{}", count, unit))
+		.collect();
+
+	if parts.is_empty() {
+		"0s".to_string()
+	} else {
+		parts.join(" ")
+	}
+}
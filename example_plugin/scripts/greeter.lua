@@ -0,0 +1,25 @@
+-- An example greeter script.
+-- Copy it and greeter.toml into the servers plugins directory,
+-- then approve its permissions in the servers config.
+
+chat.register_command("seen", "shows how many people have joined, and are here")
+
+function on_client_joined(event)
+	local seen = (store.get("seen") or 0) + 1
+	store.set("seen", seen)
+
+	chat.send_private(event.uuid, "welcome " .. event.username .. ", you are visitor " .. seen)
+end
+
+function on_global_message(event)
+	if event.content:find("^%s*$") then
+		return false
+	end
+end
+
+function on_command(command)
+	if command.name == "seen" then
+		local here = #chat.list_clients()
+		return (store.get("seen") or 0) .. " people have joined, " .. here .. " are here"
+	end
+end
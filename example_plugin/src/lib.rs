@@ -0,0 +1,113 @@
+//! An example plugin, which logs its lifecycle and counts how long it has run.
+//! It also logs joins, vetoes blank global messages,
+//! and answers `/uptime` with how long it has been running.
+//!
+//! Build with `cargo build -p example_plugin`,
+//! then copy the library from the target directory into the servers plugins directory,
+//! along with `manifest.toml` renamed to match the library.
+
+use std::sync::{
+	atomic::{AtomicU64, Ordering},
+	Mutex,
+};
+
+use foundation::{
+	event::{Event, EventFilter, EventKind, EventResult},
+	plugin::{async_trait, CommandInvocation, IPlugin, PluginApi, PluginDetails},
+};
+use log::{info, warn};
+
+#[derive(Default)]
+pub struct ExamplePlugin {
+	runs: AtomicU64,
+	api: Mutex<Option<PluginApi>>,
+}
+
+impl std::fmt::Debug for ExamplePlugin {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("ExamplePlugin")
+			.field("runs", &self.runs)
+			.finish_non_exhaustive()
+	}
+}
+
+impl ExamplePlugin {
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+#[async_trait]
+impl IPlugin for ExamplePlugin {
+	fn details(&self) -> PluginDetails {
+		PluginDetails {
+			id: "io.github.michael-bailey.example".into(),
+			display_name: "Example Plugin".into(),
+			version: env!("CARGO_PKG_VERSION").into(),
+			contacts: vec!["mickyb18a@gmail.com".into()],
+		}
+	}
+
+	fn init(&self, api: PluginApi) {
+		info!("initialised as {}", api.bot().name);
+		*self.api.lock().unwrap() = Some(api);
+	}
+
+	async fn run(&self) {
+		let runs = self.runs.fetch_add(1, Ordering::Relaxed) + 1;
+
+		// commands are registered on the first run, as init can't wait
+		if runs == 1 {
+			let api = self.api.lock().unwrap().clone();
+			if let Some(api) = api {
+				let description = "how long the example plugin has been running";
+				if let Err(e) = api
+					.register_command("uptime".into(), description.into())
+					.await
+				{
+					warn!("couldn't register /uptime: {}", e);
+				}
+			}
+		}
+
+		if runs.is_multiple_of(60) {
+			info!("running for {} minutes", runs / 60);
+		}
+	}
+
+	fn deinit(&self) {
+		info!(
+			"deinitialised after {} runs",
+			self.runs.load(Ordering::Relaxed)
+		);
+	}
+
+	fn event_filter(&self) -> EventFilter {
+		EventFilter::Only(vec![EventKind::ClientJoined, EventKind::GlobalMessage])
+	}
+
+	async fn on_event(&self, event: &Event) -> EventResult {
+		match event {
+			Event::ClientJoined { username, .. } => {
+				info!("{} joined", username);
+				EventResult::Continue
+			}
+			Event::GlobalMessage { content, .. } if content.trim().is_empty() => {
+				EventResult::Veto
+			}
+			_ => EventResult::Continue,
+		}
+	}
+
+	async fn on_command(&self, command: &CommandInvocation) -> Option<String> {
+		match command.name.as_str() {
+			"uptime" => Some(format!(
+				"the example plugin has been running for {} seconds",
+				self.runs.load(Ordering::Relaxed)
+			)),
+			_ => None,
+		}
+	}
+}
+
+foundation::declare_plugin!(ExamplePlugin::new);
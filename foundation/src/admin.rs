@@ -0,0 +1,125 @@
+//! Messages for the servers admin interface.
+//!
+//! Admins connect to the servers admin port on localhost,
+//! send an [AdminAuth] with the token from the servers token file,
+//! then send requests as lines of json, each answered by a response.
+
+use serde::{Deserialize, Serialize};
+
+use crate::plugin::PluginDetails;
+
+/// # AdminAuth
+/// Sent before any request, answered with Ok or an Error.
+/// The token is written to a file only the servers user can read,
+/// so other users on the machine can't use the admin interface.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AdminAuth {
+	pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum AdminRequest {
+	ListPlugins,
+	/// loads a library from the plugins directory, by file name.
+	LoadPlugin {
+		file: String,
+	},
+	/// starts a disabled plugin, running its init again.
+	EnablePlugin {
+		id: String,
+	},
+	/// stops a plugin, running its deinit, but leaves it loaded.
+	DisablePlugin {
+		id: String,
+	},
+	PausePlugin {
+		id: String,
+	},
+	ResumePlugin {
+		id: String,
+	},
+	/// unloads the plugin, then loads its library again.
+	ReloadPlugin {
+		id: String,
+	},
+	UnloadPlugin {
+		id: String,
+	},
+	/// lists the clients connected to this server.
+	ListClients,
+	/// sends a global message from the server to everyone.
+	Broadcast {
+		message: String,
+	},
+	/// disconnects a user, by uuid or name.
+	Kick {
+		user: String,
+		#[serde(default)]
+		reason: String,
+	},
+	/// bans a uuid, username or ip address.
+	Ban {
+		target: String,
+		/// such as "30m" or "2d", the ban is permanent if not given.
+		#[serde(default)]
+		duration: Option<String>,
+		#[serde(default)]
+		reason: String,
+	},
+	/// reads the config file again, applying what can change while running.
+	ReloadConfig,
+	Stats,
+	Shutdown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum AdminResponse {
+	Ok,
+	Error { reason: String },
+	Plugins { plugins: Vec<PluginStatus> },
+	Clients { clients: Vec<ClientStatus> },
+	Stats { stats: ServerStats },
+}
+
+/// # PluginStatus
+/// A loaded plugin, as shown to admins.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PluginStatus {
+	pub details: PluginDetails,
+	pub path: String,
+	/// Running, Paused or Stopped.
+	pub state: String,
+}
+
+/// # ClientStatus
+/// A connected client, as shown to admins.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientStatus {
+	pub uuid: String,
+	pub name: String,
+	pub address: String,
+	pub role: String,
+	/// the unix time the client connected.
+	pub connected_at: u64,
+	/// how many devices the user is connected from.
+	pub sessions: usize,
+}
+
+/// # ServerStats
+/// What the server is doing, as shown to admins.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ServerStats {
+	pub uptime_secs: u64,
+	/// users connected to this server.
+	pub clients: usize,
+	pub sessions: usize,
+	/// users connected to other servers in the mesh.
+	pub remote_clients: usize,
+	pub bots: usize,
+	pub global_messages: usize,
+	pub plugins: usize,
+	pub bans: usize,
+	pub accounts: usize,
+}
@@ -0,0 +1,79 @@
+use std::{
+	collections::HashMap,
+	convert::TryFrom,
+	io,
+	net::{IpAddr, Ipv4Addr, SocketAddr},
+	time::Duration,
+};
+
+use protocol::prelude::{DiscoveryRequest, DiscoveryResponse, Info};
+use tokio::{
+	net::UdpSocket,
+	time::{timeout_at, Instant},
+};
+
+use crate::networking::{
+	discovery::{decode_datagram, encode_datagram},
+	DISCOVERY_PORT,
+};
+
+/// # DiscoveredServer
+/// A server on the local network that answered a discovery request.
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+	pub ip: IpAddr,
+	pub info: Info,
+	/// the port each protocol is listened for on, keyed by protocol name.
+	pub ports: HashMap<String, u32>,
+}
+
+impl DiscoveredServer {
+	/// The address to connect to with the given protocol,
+	/// if the server offers it.
+	pub fn get_address(&self, protocol: &str) -> Option<SocketAddr> {
+		let port = u16::try_from(*self.ports.get(protocol)?).ok()?;
+		Some(SocketAddr::new(self.ip, port))
+	}
+}
+
+/// Broadcasts a discovery request on the local network,
+/// and collects every server that answers before the timeout.
+pub async fn discover_servers(
+	timeout: Duration,
+) -> io::Result<Vec<DiscoveredServer>> {
+	let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+	socket.set_broadcast(true)?;
+
+	let request = encode_datagram(&DiscoveryRequest {});
+	socket
+		.send_to(&request, (Ipv4Addr::BROADCAST, DISCOVERY_PORT))
+		.await?;
+
+	let deadline = Instant::now() + timeout;
+	let mut servers: Vec<DiscoveredServer> = Vec::new();
+	let mut buffer = vec![0; u16::MAX as usize];
+
+	while let Ok(received) =
+		timeout_at(deadline, socket.recv_from(&mut buffer)).await
+	{
+		let (length, addr) = received?;
+
+		let Ok(response) = decode_datagram::<DiscoveryResponse>(&buffer[..length])
+		else {
+			continue;
+		};
+
+		// a server may hear the request more than once
+		if servers.iter().any(|s| s.ip == addr.ip()) {
+			continue;
+		}
+
+		servers.push(DiscoveredServer {
+			ip: addr.ip(),
+			info: response.info.unwrap_or_default(),
+			ports: response.ports,
+		});
+	}
+
+	Ok(servers)
+}
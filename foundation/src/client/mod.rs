@@ -2,6 +2,7 @@ use tokio::task::JoinHandle;
 
 use crate::client::server_writer_connection::ServerWriterConnection;
 
+pub mod discovery;
 pub mod network_connection;
 pub mod server_reader_connection;
 pub mod server_writer_connection;
@@ -1,4 +1,4 @@
-use std::{io, net::SocketAddr};
+use std::{io, net::SocketAddr, sync::Arc};
 
 use protocol::prelude::{
 	network_client_message,
@@ -10,7 +10,11 @@ use protocol::prelude::{
 	NetworkServerMessage,
 	Request,
 };
-use tokio::{io::split, net::TcpStream};
+use tokio::{
+	io::{split, AsyncWriteExt},
+	net::TcpStream,
+	sync::Mutex,
+};
 use uuid::Uuid;
 
 use crate::{
@@ -18,7 +22,11 @@ use crate::{
 		server_reader_connection::ServerReaderConnection,
 		server_writer_connection::ServerWriterConnection,
 	},
-	networking::protobuf::{read_message, write_message},
+	networking::{
+		handshake::{self, PROTOCOL_VERSION},
+		protobuf::{read_message, write_message},
+		PROTOBUF_PREAMBLE,
+	},
 };
 
 /// # NetworkConnection
@@ -28,17 +36,35 @@ use crate::{
 /// you can then either get info or connect to the server
 pub struct NetworkConnection {
 	pub(super) stream: TcpStream,
+	/// the optional features the server advertised.
+	server_capabilities: Vec<String>,
 }
 
 impl NetworkConnection {
 	pub async fn connect(address: SocketAddr) -> io::Result<Self> {
-		let mut stream = TcpStream::connect(address).await.unwrap();
+		let stream = TcpStream::connect(address).await?;
+		Self::from_stream(stream).await
+	}
+
+	/// Connects to a servers unified port.
+	/// The protobuf preamble is sent first, so the server doesn't need to guess.
+	pub async fn connect_unified(address: SocketAddr) -> io::Result<Self> {
+		let mut stream = TcpStream::connect(address).await?;
+		stream.write_all(&PROTOBUF_PREAMBLE).await?;
+		Self::from_stream(stream).await
+	}
 
+	async fn from_stream(mut stream: TcpStream) -> io::Result<Self> {
 		let msg =
 			read_message::<NetworkServerMessage, TcpStream>(&mut stream).await?;
 
 		let NetworkServerMessage {
-			message: Some(network_server_message::Message::Request(Request {})),
+			message:
+				Some(network_server_message::Message::Request(Request {
+					min_version,
+					max_version,
+					capabilities,
+				})),
 		} = msg
 		else {
 			return Err(io::Error::new(
@@ -47,7 +73,28 @@ impl NetworkConnection {
 			));
 		};
 
-		Ok(Self { stream })
+		// servers from before versioning don't advertise any
+		if max_version != 0
+			&& !(min_version..=max_version).contains(&PROTOCOL_VERSION)
+		{
+			return Err(io::Error::new(
+				io::ErrorKind::Unsupported,
+				format!(
+					"server supports protocol versions {} to {}, this client speaks {}",
+					min_version, max_version, PROTOCOL_VERSION
+				),
+			));
+		}
+
+		Ok(Self {
+			stream,
+			server_capabilities: capabilities,
+		})
+	}
+
+	/// The optional features the server supports.
+	pub fn server_capabilities(&self) -> &[String] {
+		&self.server_capabilities
 	}
 
 	/// Will consume the connection, and fetch the servers info.
@@ -77,17 +124,27 @@ impl NetworkConnection {
 	}
 
 	/// consumes this struct and returns a tuple of the sernding and receiving ahlfs of teh connected conneciton
+	///
+	/// The returned session token can be passed to a later connect,
+	/// to resume the session after the connection drops.
 	pub async fn send_connect(
 		mut self,
 		uuid: Uuid,
 		username: String,
-	) -> io::Result<(ServerWriterConnection, ServerReaderConnection)> {
+		session_token: Option<Uuid>,
+	) -> io::Result<(Uuid, ServerWriterConnection, ServerReaderConnection)> {
 		_ = write_message(
 			&mut self.stream,
 			NetworkClientMessage {
 				message: Some(network_client_message::Message::Connect(Connect {
 					username,
 					uuid: uuid.to_string(),
+					session_token: session_token
+						.map(|t| t.to_string())
+						.unwrap_or_default(),
+					version: PROTOCOL_VERSION,
+					capabilities: handshake::capabilities(),
+					..Default::default()
 				})),
 			},
 		)
@@ -96,17 +153,28 @@ impl NetworkConnection {
 		let message =
 			read_message::<NetworkServerMessage, TcpStream>(&mut self.stream).await?;
 
-		let NetworkServerMessage {
-			message: Some(network_server_message::Message::Connected(_)),
-		} = message
-		else {
-			return Err(io::Error::new(
-				io::ErrorKind::InvalidData,
-				"sent connect got different message back or failed to connect",
-			));
+		let connected = match message.message {
+			Some(network_server_message::Message::Connected(connected)) => connected,
+			Some(network_server_message::Message::Rejected(rejected)) => {
+				return Err(io::Error::new(
+					io::ErrorKind::ConnectionRefused,
+					format!("server rejected connection: {}", rejected.reason),
+				));
+			}
+			_ => {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidData,
+					"sent connect got different message back or failed to connect",
+				));
+			}
 		};
 
-		Ok(self.into())
+		let session_token = connected.session_token.parse().map_err(|_| {
+			io::Error::new(io::ErrorKind::InvalidData, "invalid session token")
+		})?;
+
+		let (writer, reader) = self.into();
+		Ok((session_token, writer, reader))
 	}
 }
 
@@ -115,9 +183,10 @@ impl From<NetworkConnection>
 {
 	fn from(value: NetworkConnection) -> Self {
 		let (read_half, write_half) = split(value.stream);
+		let write_half = Arc::new(Mutex::new(write_half));
 		(
-			ServerWriterConnection::new(write_half),
-			ServerReaderConnection::new(read_half),
+			ServerWriterConnection::new(write_half.clone()),
+			ServerReaderConnection::new(read_half, write_half),
 		)
 	}
 }
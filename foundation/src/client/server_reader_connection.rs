@@ -1,26 +1,62 @@
-use std::io;
+use std::{io, sync::Arc};
 
-use protocol::prelude::ConnectedServerMessage;
-use tokio::{io::ReadHalf, net::TcpStream};
+use protocol::prelude::{
+	connected_client_message,
+	connected_server_message,
+	ConnectedClientMessage,
+	ConnectedServerMessage,
+	Ping,
+	Pong,
+};
+use tokio::{
+	io::{ReadHalf, WriteHalf},
+	net::TcpStream,
+	sync::Mutex,
+};
 
-use crate::networking::protobuf::read_message;
+use crate::networking::protobuf::{read_message, write_message};
 
 pub struct ServerReaderConnection {
 	reader: ReadHalf<TcpStream>,
+	writer: Arc<Mutex<WriteHalf<TcpStream>>>,
 }
 
 impl ServerReaderConnection {
-	pub(crate) fn new(read_half: ReadHalf<TcpStream>) -> Self {
-		Self { reader: read_half }
+	pub(crate) fn new(
+		read_half: ReadHalf<TcpStream>,
+		writer: Arc<Mutex<WriteHalf<TcpStream>>>,
+	) -> Self {
+		Self {
+			reader: read_half,
+			writer,
+		}
 	}
 
-	// move to other one
+	/// Reads the next message from the server.
+	/// Pings are answered here and never returned to the caller.
 	pub async fn get_message(&mut self) -> io::Result<ConnectedServerMessage> {
-		let message = read_message::<ConnectedServerMessage, ReadHalf<TcpStream>>(
-			&mut self.reader,
-		)
-		.await
-		.unwrap();
-		Ok(message)
+		loop {
+			let message =
+				read_message::<ConnectedServerMessage, ReadHalf<TcpStream>>(
+					&mut self.reader,
+				)
+				.await?;
+
+			let ConnectedServerMessage {
+				message: Some(connected_server_message::Message::Ping(Ping {})),
+			} = message
+			else {
+				return Ok(message);
+			};
+
+			self.send_pong().await?;
+		}
+	}
+
+	async fn send_pong(&mut self) -> io::Result<()> {
+		let message = ConnectedClientMessage {
+			message: Some(connected_client_message::Message::Pong(Pong {})),
+		};
+		write_message(&mut *self.writer.lock().await, message).await
 	}
 }
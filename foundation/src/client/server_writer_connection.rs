@@ -1,12 +1,14 @@
-use tokio::{io::WriteHalf, net::TcpStream};
+use std::sync::Arc;
+
+use tokio::{io::WriteHalf, net::TcpStream, sync::Mutex};
 
 #[allow(dead_code)]
 pub struct ServerWriterConnection {
-	writer: WriteHalf<TcpStream>,
+	writer: Arc<Mutex<WriteHalf<TcpStream>>>,
 }
 
 impl ServerWriterConnection {
-	pub(crate) fn new(writer: WriteHalf<TcpStream>) -> Self {
+	pub(crate) fn new(writer: Arc<Mutex<WriteHalf<TcpStream>>>) -> Self {
 		Self { writer }
 	}
 
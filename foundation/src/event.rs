@@ -0,0 +1,121 @@
+//! Events published by the server,
+//! which plugins and server subsystems can subscribe to.
+
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// # Event
+/// Something that happened on the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Event {
+	/// a socket connected, before its request is read.
+	ConnectionAccepted {
+		addr: SocketAddr,
+	},
+	/// a user connected, before they are announced to others.
+	ClientJoined {
+		uuid: Uuid,
+		username: String,
+		addr: SocketAddr,
+	},
+	ClientLeft {
+		uuid: Uuid,
+	},
+	GlobalMessage {
+		from: Uuid,
+		content: String,
+	},
+	PrivateMessage {
+		from: Uuid,
+		to: Uuid,
+		content: String,
+	},
+	Shutdown,
+}
+
+impl Event {
+	pub fn kind(&self) -> EventKind {
+		match self {
+			Self::ConnectionAccepted { .. } => EventKind::ConnectionAccepted,
+			Self::ClientJoined { .. } => EventKind::ClientJoined,
+			Self::ClientLeft { .. } => EventKind::ClientLeft,
+			Self::GlobalMessage { .. } => EventKind::GlobalMessage,
+			Self::PrivateMessage { .. } => EventKind::PrivateMessage,
+			Self::Shutdown => EventKind::Shutdown,
+		}
+	}
+
+	/// Events reporting something that already happened can't be vetoed.
+	pub fn is_vetoable(&self) -> bool {
+		!matches!(self, Self::ClientLeft { .. } | Self::Shutdown)
+	}
+}
+
+/// # EventKind
+/// The kind of an event, used to filter subscriptions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EventKind {
+	ConnectionAccepted,
+	ClientJoined,
+	ClientLeft,
+	GlobalMessage,
+	PrivateMessage,
+	Shutdown,
+}
+
+impl EventKind {
+	pub const ALL: [EventKind; 6] = [
+		Self::ConnectionAccepted,
+		Self::ClientJoined,
+		Self::ClientLeft,
+		Self::GlobalMessage,
+		Self::PrivateMessage,
+		Self::Shutdown,
+	];
+}
+
+/// # EventFilter
+/// Picks the events a subscriber is given.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventFilter {
+	All,
+	Only(Vec<EventKind>),
+}
+
+impl EventFilter {
+	pub fn none() -> Self {
+		Self::Only(Vec::new())
+	}
+
+	pub fn matches(&self, event: &Event) -> bool {
+		match self {
+			Self::All => true,
+			Self::Only(kinds) => kinds.contains(&event.kind()),
+		}
+	}
+}
+
+/// # EventResult
+/// A handlers reply to an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventResult {
+	/// let the event through unchanged.
+	Continue,
+	/// stop the event, if it can be vetoed.
+	Veto,
+	/// replace the event with another of the same kind.
+	Rewrite(Event),
+}
+
+/// # IEventHandler
+/// Implemented by anything subscribed to the servers events.
+/// Handlers are run in the order they subscribed,
+/// each seeing the event as rewritten by those before it.
+#[async_trait]
+pub trait IEventHandler: Send + Sync {
+	async fn on_event(&self, event: &Event) -> EventResult;
+}
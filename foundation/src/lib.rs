@@ -1,7 +1,10 @@
+pub mod admin;
 pub mod client;
+pub mod event;
 pub mod messages;
 pub mod models;
 pub mod networking;
+pub mod plugin;
 pub mod prelude;
 
 use serde::{Deserialize, Serialize};
@@ -14,6 +17,7 @@ use uuid::Uuid;
  * username:      the users user name.
  * address:       the ip address of the connected user.
  * public_key:    the public key used when sending messages to the user.
+ * role:          the users role on their server, empty for bots.
  */
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct ClientDetails {
@@ -21,4 +25,6 @@ pub struct ClientDetails {
 	pub username: String,
 	pub address: String,
 	pub public_key: Option<Vec<u8>>,
+	#[serde(default)]
+	pub role: String,
 }
@@ -14,6 +14,9 @@ pub enum ClientStreamIn {
 	SendMessage { to: Uuid, content: String },
 	SendGlobalMessage { content: String },
 
+	// reply to the servers keepalive ping
+	Pong,
+
 	Disconnect,
 }
 
@@ -25,18 +28,49 @@ pub enum ClientStreamOut {
 	Connected,
 
 	// get reequest messages
-	ConnectedClients { clients: Vec<ClientDetails> },
-	GlobalChatMessages { messages: Vec<Message> },
+	ConnectedClients {
+		clients: Vec<ClientDetails>,
+	},
+	GlobalChatMessages {
+		messages: Vec<Message>,
+	},
 
 	// event messges
-	UserMessage { from: Uuid, content: String },
-	GlobalMessage { from: Uuid, content: String },
+	UserMessage {
+		from: Uuid,
+		#[serde(default)]
+		to: Uuid,
+		content: String,
+	},
+	GlobalMessage {
+		from: Uuid,
+		content: String,
+	},
+
+	ClientConnected {
+		id: Uuid,
+		username: String,
+		#[serde(default)]
+		role: String,
+	},
+	ClientRemoved {
+		id: Uuid,
+	},
+	ClientRenamed {
+		id: Uuid,
+		username: String,
+	},
 
-	ClientConnected { id: Uuid, username: String },
-	ClientRemoved { id: Uuid },
+	Disconnected {
+		#[serde(default)]
+		reason: String,
+	},
 
-	Disconnected,
+	// keepalive, clients must reply with a pong
+	Ping,
 
 	// error cases
-	Error { msg: String },
+	Error {
+		msg: String,
+	},
 }
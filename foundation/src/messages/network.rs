@@ -10,6 +10,20 @@ pub enum NetworkSockIn {
 		uuid: Uuid,
 		username: String,
 		address: String,
+		#[serde(default)]
+		session_token: Option<Uuid>,
+		/// protocol version spoken by the client, 0 if it predates versioning.
+		#[serde(default)]
+		version: u32,
+		#[serde(default)]
+		capabilities: Vec<String>,
+		/// logs in to the account with the username, if set.
+		/// The uuid is ignored, the accounts uuid is used instead.
+		#[serde(default)]
+		password: Option<String>,
+		/// creates an account with the username and password, then logs in to it.
+		#[serde(default)]
+		register: bool,
 	},
 }
 
@@ -17,13 +31,58 @@ pub enum NetworkSockIn {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum NetworkSockOut {
-	Request,
+	/// Sent first on every connection, advertising what the server supports.
+	Request {
+		#[serde(default)]
+		min_version: u32,
+		#[serde(default)]
+		max_version: u32,
+		#[serde(default)]
+		capabilities: Vec<String>,
+	},
 
 	GotInfo {
 		server_name: String,
 		server_owner: String,
+		#[serde(default)]
+		description: String,
+		#[serde(default)]
+		server_version: String,
+		#[serde(default)]
+		connected_users: u32,
+		/// 0 if there is no limit.
+		#[serde(default)]
+		max_users: u32,
+		#[serde(default)]
+		protocols: Vec<String>,
+		#[serde(default)]
+		capabilities: Vec<String>,
+		#[serde(default)]
+		min_version: u32,
+		#[serde(default)]
+		max_version: u32,
+		#[serde(default)]
+		authentication_required: bool,
+	},
+	Connected {
+		session_token: Uuid,
+		#[serde(default)]
+		resumed: bool,
+		#[serde(default)]
+		version: u32,
+		#[serde(default)]
+		capabilities: Vec<String>,
+		/// the uuid the client is connected as, which is the accounts if logged in.
+		#[serde(default)]
+		uuid: Uuid,
+	},
+
+	/// Sent instead of Connected if the client can't be accepted.
+	Rejected {
+		reason: String,
+		min_version: u32,
+		max_version: u32,
 	},
-	Connected,
 
 	Error,
 }
@@ -31,18 +90,100 @@ pub enum NetworkSockOut {
 impl PartialEq for NetworkSockOut {
 	fn eq(&self, other: &Self) -> bool {
 		match (self, other) {
-			(NetworkSockOut::Request, NetworkSockOut::Request) => true,
+			(
+				NetworkSockOut::Request {
+					min_version,
+					max_version,
+					capabilities,
+				},
+				NetworkSockOut::Request {
+					min_version: min_other,
+					max_version: max_other,
+					capabilities: capabilities_other,
+				},
+			) => {
+				min_version == min_other
+					&& max_version == max_other
+					&& capabilities == capabilities_other
+			}
 			(
 				NetworkSockOut::GotInfo {
 					server_name,
 					server_owner,
+					description,
+					server_version,
+					connected_users,
+					max_users,
+					protocols,
+					capabilities,
+					min_version,
+					max_version,
+					authentication_required,
 				},
 				NetworkSockOut::GotInfo {
 					server_owner: owner_other,
 					server_name: name_other,
+					description: description_other,
+					server_version: server_version_other,
+					connected_users: users_other,
+					max_users: max_users_other,
+					protocols: protocols_other,
+					capabilities: capabilities_other,
+					min_version: min_other,
+					max_version: max_other,
+					authentication_required: auth_other,
+				},
+			) => {
+				server_name == name_other
+					&& server_owner == owner_other
+					&& description == description_other
+					&& server_version == server_version_other
+					&& connected_users == users_other
+					&& max_users == max_users_other
+					&& protocols == protocols_other
+					&& capabilities == capabilities_other
+					&& min_version == min_other
+					&& max_version == max_other
+					&& authentication_required == auth_other
+			}
+			(
+				NetworkSockOut::Connected {
+					session_token,
+					resumed,
+					version,
+					capabilities,
+					uuid,
+				},
+				NetworkSockOut::Connected {
+					session_token: token_other,
+					resumed: resumed_other,
+					version: version_other,
+					capabilities: capabilities_other,
+					uuid: uuid_other,
+				},
+			) => {
+				session_token == token_other
+					&& resumed == resumed_other
+					&& version == version_other
+					&& capabilities == capabilities_other
+					&& uuid == uuid_other
+			}
+			(
+				NetworkSockOut::Rejected {
+					reason,
+					min_version,
+					max_version,
+				},
+				NetworkSockOut::Rejected {
+					reason: reason_other,
+					min_version: min_other,
+					max_version: max_other,
 				},
-			) => server_name == name_other && server_owner == owner_other,
-			(NetworkSockOut::Connected, NetworkSockOut::Connected) => true,
+			) => {
+				reason == reason_other
+					&& min_version == min_other
+					&& max_version == max_other
+			}
 			_ => false,
 		}
 	}
@@ -0,0 +1,33 @@
+use std::io;
+
+use prost::{bytes::BufMut, Message};
+
+use crate::networking::DISCOVERY_PREAMBLE;
+
+/// Encodes a discovery message into a single datagram, after the preamble.
+pub fn encode_datagram<T: Message>(message: &T) -> Vec<u8> {
+	let mut buffer =
+		Vec::with_capacity(DISCOVERY_PREAMBLE.len() + message.encoded_len());
+	buffer.put_slice(&DISCOVERY_PREAMBLE);
+	// encoding into a vec can't run out of space
+	_ = message.encode(&mut buffer);
+	buffer
+}
+
+/// Decodes a discovery datagram,
+/// failing if it doesn't start with the preamble.
+pub fn decode_datagram<T: Message + Default>(datagram: &[u8]) -> io::Result<T> {
+	let Some(body) = datagram.strip_prefix(&DISCOVERY_PREAMBLE) else {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"datagram is missing the discovery preamble",
+		));
+	};
+
+	T::decode(body).map_err(|err| {
+		io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("message decoding failed: {:?}", err),
+		)
+	})
+}
@@ -0,0 +1,106 @@
+//! Version and capability negotiation,
+//! done while the server is handling a clients request.
+
+/// The protocol version spoken by this build.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The oldest protocol version this build still accepts.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Clients from before versioning don't send a version,
+/// so they are taken to speak the first one.
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// The first version with capabilities, older clients get none of them.
+pub const CAPABILITIES_VERSION: u32 = 2;
+
+/// The server pings the client, which must answer with a pong.
+pub const HEARTBEAT: &str = "heartbeat";
+
+/// A dropped session is kept, so the client can resume it with its token.
+pub const SESSION_RESUME: &str = "session_resume";
+
+/// The optional features supported by this build.
+pub const CAPABILITIES: &[&str] = &[HEARTBEAT, SESSION_RESUME];
+
+/// Returns the version a client asked for,
+/// or the reason it can't be accepted.
+///
+/// A version of 0 means the client didn't send one.
+pub fn check_version(version: u32) -> Result<u32, String> {
+	let version = match version {
+		0 => LEGACY_PROTOCOL_VERSION,
+		v => v,
+	};
+
+	if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+		Ok(version)
+	} else {
+		Err(format!(
+			"unsupported protocol version {}, supported versions are {} to {}",
+			version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+		))
+	}
+}
+
+/// Returns the capabilities supported by both sides,
+/// for a client speaking the version.
+/// Unknown capabilities are ignored.
+pub fn negotiate_capabilities(
+	version: u32,
+	requested: &[String],
+) -> Vec<String> {
+	if version < CAPABILITIES_VERSION {
+		return Vec::new();
+	}
+
+	requested
+		.iter()
+		.filter(|c| CAPABILITIES.contains(&c.as_str()))
+		.cloned()
+		.collect()
+}
+
+/// The capabilities of this build, as sent over the network.
+pub fn capabilities() -> Vec<String> {
+	CAPABILITIES.iter().map(|c| c.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn missing_version_is_legacy() {
+		assert_eq!(check_version(0), Ok(LEGACY_PROTOCOL_VERSION));
+	}
+
+	#[test]
+	fn supported_versions_are_accepted() {
+		for version in MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION {
+			assert_eq!(check_version(version), Ok(version));
+		}
+	}
+
+	#[test]
+	fn newer_versions_are_refused() {
+		assert!(check_version(PROTOCOL_VERSION + 1).is_err());
+	}
+
+	#[test]
+	fn unknown_capabilities_are_dropped() {
+		let requested = vec![HEARTBEAT.to_string(), "telepathy".to_string()];
+		assert_eq!(
+			negotiate_capabilities(PROTOCOL_VERSION, &requested),
+			vec![HEARTBEAT.to_string()]
+		);
+	}
+
+	#[test]
+	fn legacy_clients_get_no_capabilities() {
+		assert!(
+			negotiate_capabilities(LEGACY_PROTOCOL_VERSION, &capabilities())
+				.is_empty()
+		);
+	}
+}
@@ -1,7 +1,21 @@
-use std::io;
+use std::{io, net::SocketAddr};
 
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::{
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+	net::TcpStream,
+};
+
+use crate::networking::JSON_PREAMBLE;
+
+/// Connects to a servers unified port.
+/// The json preamble is sent first, as the server can't tell json
+/// from protobuf while the client waits for its request.
+pub async fn connect_unified(address: SocketAddr) -> io::Result<TcpStream> {
+	let mut stream = TcpStream::connect(address).await?;
+	stream.write_all(&JSON_PREAMBLE).await?;
+	Ok(stream)
+}
 
 pub async fn write_message<S, M>(stream: &mut S, message: M)
 where
@@ -11,16 +25,22 @@ where
 	let mut message = serde_json::to_string(&message).unwrap();
 	message.push('\n');
 	_ = stream.write(message.as_bytes()).await;
+	// buffered streams (such as websockets) need flushing to send the message
+	_ = stream.flush().await;
 }
 
-// todo: Handle error properly
 pub async fn read_message<S, M>(stream: &mut S) -> io::Result<M>
 where
 	S: AsyncRead + AsyncReadExt + Unpin,
 	M: DeserializeOwned,
 {
 	let string = read_line(stream).await?;
-	Ok(serde_json::from_str(&string).unwrap())
+	serde_json::from_str(&string).map_err(|e| {
+		io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("message decoding failed: {}", e),
+		)
+	})
 }
 
 #[allow(clippy::redundant_guards, clippy::needless_range_loop)]
@@ -33,6 +53,10 @@ where
 	let mut result = Vec::new();
 	loop {
 		let n = match stream.read(&mut buf).await {
+			// the stream closed between messages
+			Ok(0) if result.is_empty() => {
+				return Err(io::Error::from(io::ErrorKind::UnexpectedEof))
+			}
 			Ok(n) if n == 0 => return Ok(String::from_utf8(result).unwrap()),
 			Ok(n) => n,
 			Err(e) => return Err(e),
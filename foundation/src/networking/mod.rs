@@ -1,2 +1,27 @@
+pub mod discovery;
+pub mod handshake;
 pub mod json;
 pub mod protobuf;
+
+/// Sent by a client before anything else on the servers unified port,
+/// to pick the json protocol.
+/// Json clients must send it, as they wait for the servers request,
+/// and clients that send nothing are taken to be protobuf.
+/// The last byte is the preamble version.
+pub const JSON_PREAMBLE: [u8; 4] = *b"CKJ\x01";
+
+/// Sent by a client before anything else on the servers unified port,
+/// to pick the protobuf protocol explicitly.
+/// The last byte is the preamble version.
+pub const PROTOBUF_PREAMBLE: [u8; 4] = *b"CKP\x01";
+
+/// The udp port servers listen for discovery requests on.
+pub const DISCOVERY_PORT: u16 = 6610;
+
+/// The localhost tcp port servers listen for admin requests on.
+pub const ADMIN_PORT: u16 = 6700;
+
+/// Starts every discovery datagram,
+/// so unrelated broadcasts on the port are ignored.
+/// The last byte is the discovery version.
+pub const DISCOVERY_PREAMBLE: [u8; 4] = *b"CKD\x01";
@@ -13,6 +13,8 @@ where
 {
 	let message = encode_message::<T>(&message)?;
 	stream.write_all(&message).await?;
+	// buffered streams (such as websockets) need flushing to send the message
+	stream.flush().await?;
 	Ok(())
 }
 
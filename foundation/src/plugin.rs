@@ -0,0 +1,207 @@
+//! The interface between the server and dynamically loaded plugins.
+//!
+//! Plugins are rust libraries built as a `cdylib`,
+//! which declare their plugin with [declare_plugin].
+//! As the interface is rust rather than C,
+//! a plugin must be built with the same compiler and foundation version as the server.
+//! The server checks [PLUGIN_API_VERSION] before loading anything else,
+//! and refuses plugins built against a different version.
+//!
+//! Plugins have their own copy of every crate they depend on,
+//! so can't rely on the servers tokio runtime for timers or io.
+//! Their copy of `log` is given the servers logger when they are loaded,
+//! so the `log` macros can be used as in the server.
+
+use std::{
+	fmt::{self, Debug},
+	sync::Arc,
+};
+
+pub use async_trait::async_trait;
+pub use log;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+	event::{Event, EventFilter, EventResult},
+	prelude::{ClientDetails, GlobalMessage},
+};
+
+/// The version of the plugin interface.
+/// Bumped whenever [IPlugin] or the types it uses change.
+pub const PLUGIN_API_VERSION: u32 = 3;
+
+/// The symbol exporting the plugins api version.
+pub const API_VERSION_SYMBOL: &[u8] = b"plugin_api_version";
+
+/// The symbol exporting the plugin constructor.
+pub const GET_PLUGIN_SYMBOL: &[u8] = b"get_plugin";
+
+/// The symbol exporting the function that sets the plugins logger.
+pub const SET_LOGGER_SYMBOL: &[u8] = b"set_logger";
+
+/// # Plugin
+/// Type alias for plugin objects.
+pub type Plugin = Arc<dyn IPlugin>;
+
+/// # ApiVersionFn
+/// The type of the function exporting the plugins api version.
+pub type ApiVersionFn = extern "C" fn() -> u32;
+
+/// # GetPluginFn
+/// The type of the function constructing the plugin.
+pub type GetPluginFn = fn() -> Plugin;
+
+/// # SetLoggerFn
+/// The type of the function giving the plugin the servers logger and level.
+pub type SetLoggerFn = fn(&'static dyn log::Log, log::LevelFilter);
+
+/// # PluginApi
+/// Type alias for the handle plugins use to act on the server.
+pub type PluginApi = Arc<dyn IPluginApi>;
+
+/// # PluginDetails
+/// Describes a plugin to the server and its admins.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PluginDetails {
+	/// unique name for the plugin, such as "com.example.greeter".
+	pub id: String,
+	pub display_name: String,
+	pub version: String,
+	pub contacts: Vec<String>,
+}
+
+/// # IPlugin
+/// This trait defines an interface for plugins to implement.
+///
+/// ## Methods
+/// - details: This returns the details about the plugin.
+/// - init: Defines the initialisation routine for the plugin,
+///   and gives it the handle used to act on the server.
+/// - run: Called about once a second while the plugin is running.
+/// - deinit: Defines the deinitalisation routine for the plugin
+/// - event_filter: The events the plugin is given, none by default.
+/// - on_event: Handles an event, and may veto or rewrite it.
+/// - on_command: Handles a command the plugin registered,
+///   returning a reply for the user who ran it.
+#[async_trait]
+pub trait IPlugin: Send + Sync + Debug {
+	fn details(&self) -> PluginDetails;
+
+	fn init(&self, _api: PluginApi) {}
+	async fn run(&self) {}
+	fn deinit(&self) {}
+
+	fn event_filter(&self) -> EventFilter {
+		EventFilter::none()
+	}
+
+	async fn on_event(&self, _event: &Event) -> EventResult {
+		EventResult::Continue
+	}
+
+	async fn on_command(&self, _command: &CommandInvocation) -> Option<String> {
+		None
+	}
+}
+
+/// # CommandInvocation
+/// A user running a command, such as `/roll 2d6`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandInvocation {
+	/// the command name, without the leading slash.
+	pub name: String,
+	/// everything after the name, trimmed.
+	pub args: String,
+	pub from: Uuid,
+}
+
+/// # PluginApiError
+/// Reasons a plugin api call failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PluginApiError {
+	/// the plugin wasn't granted the access the call needs.
+	PermissionDenied(String),
+	/// the server didn't answer in time, or is shutting down.
+	Unavailable,
+}
+
+impl fmt::Display for PluginApiError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::PermissionDenied(reason) => {
+				write!(f, "permission denied: {}", reason)
+			}
+			Self::Unavailable => write!(f, "the server is unavailable"),
+		}
+	}
+}
+
+impl std::error::Error for PluginApiError {}
+
+/// # IPluginApi
+/// Lets a plugin act on the server, within the permissions it was granted.
+///
+/// Messages from the plugin are sent as its bot,
+/// which is listed as a client once it first speaks.
+///
+/// Queries are answered by the part of the server publishing most events,
+/// so a query made while handling one of them times out.
+#[async_trait]
+pub trait IPluginApi: Send + Sync {
+	/// The identity the plugins messages are sent as.
+	fn bot(&self) -> ClientDetails;
+
+	async fn list_clients(&self) -> Result<Vec<ClientDetails>, PluginApiError>;
+	async fn get_history(&self) -> Result<Vec<GlobalMessage>, PluginApiError>;
+
+	async fn send_global_message(
+		&self,
+		content: String,
+	) -> Result<(), PluginApiError>;
+	async fn send_private_message(
+		&self,
+		to: Uuid,
+		content: String,
+	) -> Result<(), PluginApiError>;
+	async fn disconnect_user(&self, uuid: Uuid) -> Result<(), PluginApiError>;
+
+	/// Registers a command users can run by sending `/name` as a global message.
+	/// The plugins [IPlugin::on_command] is called when it's run.
+	async fn register_command(
+		&self,
+		name: String,
+		description: String,
+	) -> Result<(), PluginApiError>;
+}
+
+/// Exports the symbols the server needs to load a plugin.
+/// Takes a function or closure that constructs the plugin.
+///
+/// ```ignore
+/// foundation::declare_plugin!(Greeter::new);
+/// ```
+#[macro_export]
+macro_rules! declare_plugin {
+	($constructor:expr) => {
+		#[no_mangle]
+		pub extern "C" fn plugin_api_version() -> u32 {
+			$crate::plugin::PLUGIN_API_VERSION
+		}
+
+		#[no_mangle]
+		pub fn get_plugin() -> $crate::plugin::Plugin {
+			::std::sync::Arc::new($constructor())
+		}
+
+		#[no_mangle]
+		pub fn set_logger(
+			logger: &'static dyn $crate::plugin::log::Log,
+			level: $crate::plugin::log::LevelFilter,
+		) {
+			if $crate::plugin::log::set_logger(logger).is_ok() {
+				$crate::plugin::log::set_max_level(level);
+			}
+		}
+	};
+}
@@ -0,0 +1,108 @@
+use std::{
+	collections::HashMap,
+	fs,
+	io,
+	path::{Path, PathBuf},
+};
+
+use log::error;
+use uuid::Uuid;
+
+use crate::account::Account;
+
+/// # AccountStore
+/// Every registered account, saved as json whenever one changes.
+#[derive(Debug)]
+pub struct AccountStore {
+	path: PathBuf,
+	accounts: HashMap<Uuid, Account>,
+}
+
+impl AccountStore {
+	/// Loads the accounts from the path.
+	/// If the file doesn't exist there are no accounts.
+	pub fn load(path: &Path) -> Result<Self, String> {
+		let accounts = match fs::read_to_string(path) {
+			Ok(json) => serde_json::from_str(&json)
+				.map_err(|e| format!("invalid accounts {}: {}", path.display(), e))?,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+			Err(e) => return Err(format!("can't read {}: {}", path.display(), e)),
+		};
+
+		Ok(Self {
+			path: path.to_path_buf(),
+			accounts,
+		})
+	}
+
+	pub fn get(&self, uuid: Uuid) -> Option<&Account> {
+		self.accounts.get(&uuid)
+	}
+
+	/// Finds the account with the username, ignoring case.
+	pub fn find(&self, username: &str) -> Option<&Account> {
+		self
+			.accounts
+			.values()
+			.find(|a| a.username.eq_ignore_ascii_case(username))
+	}
+
+	/// Adds the account, or replaces it if it already exists.
+	pub fn insert(&mut self, account: Account) {
+		self.accounts.insert(account.uuid, account);
+		self.save();
+	}
+
+	pub fn rename(&mut self, uuid: Uuid, username: String) {
+		if let Some(account) = self.accounts.get_mut(&uuid) {
+			account.username = username;
+			self.save();
+		}
+	}
+
+	/// Returns false if there was no account.
+	pub fn remove(&mut self, uuid: Uuid) -> bool {
+		let removed = self.accounts.remove(&uuid).is_some();
+		if removed {
+			self.save();
+		}
+		removed
+	}
+
+	pub fn count(&self) -> usize {
+		self.accounts.len()
+	}
+
+	fn save(&self) {
+		let result = serde_json::to_string_pretty(&self.accounts)
+			.map_err(|e| e.to_string())
+			.and_then(|json| fs::write(&self.path, json).map_err(|e| e.to_string()));
+
+		if let Err(e) = result {
+			error!(path:? = self.path; "failed to save accounts: {}", e);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env::temp_dir;
+
+	use super::*;
+
+	#[test]
+	fn missing_files_have_no_accounts() {
+		let path = temp_dir().join(format!("{}.json", Uuid::new_v4()));
+		let accounts = AccountStore::load(&path).unwrap();
+		assert_eq!(accounts.count(), 0);
+	}
+
+	#[test]
+	fn invalid_files_are_refused() {
+		let path = temp_dir().join(format!("{}.json", Uuid::new_v4()));
+		fs::write(&path, "{ not json").unwrap();
+		let result = AccountStore::load(&path);
+		_ = fs::remove_file(&path);
+		assert!(result.is_err());
+	}
+}
@@ -0,0 +1,150 @@
+//! Registered accounts, giving users a name and uuid they keep across devices.
+//!
+//! Passwords are never stored, only a salted pbkdf2 hash of them.
+//! Hashing is slow on purpose, so it's done off the connection managers task.
+
+mod account_store;
+
+pub use account_store::AccountStore;
+use openssl::{base64, hash::MessageDigest, memcmp, pkcs5, rand};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::moderation::unix_time;
+
+const ITERATIONS: usize = 100_000;
+const SALT_LENGTH: usize = 16;
+const HASH_LENGTH: usize = 32;
+
+/// # Credentials
+/// The password a client connected with.
+pub struct Credentials {
+	/// cleared from memory when dropped.
+	pub password: Zeroizing<String>,
+	/// true to create an account, rather than log in to one.
+	pub register: bool,
+}
+
+/// # AccountChange
+/// A change a user asked for, made once their password is checked.
+pub enum AccountChange {
+	/// the account with its new password.
+	SetPassword(Account),
+	Delete,
+}
+
+/// # Account
+/// A registered user.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Account {
+	pub uuid: Uuid,
+	pub username: String,
+	/// base64 encoded.
+	salt: String,
+	/// base64 encoded.
+	hash: String,
+	iterations: usize,
+	/// the unix time the account was made.
+	pub created: u64,
+}
+
+impl Account {
+	/// Creates an account with a new uuid.
+	/// This hashes the password, so blocks for a while.
+	pub fn new(username: String, password: &str) -> Result<Self, String> {
+		let mut account = Self {
+			uuid: Uuid::new_v4(),
+			username,
+			salt: String::new(),
+			hash: String::new(),
+			iterations: ITERATIONS,
+			created: unix_time(),
+		};
+		account.set_password(password)?;
+		Ok(account)
+	}
+
+	/// Replaces the password, with a new salt.
+	/// This hashes the password, so blocks for a while.
+	pub fn set_password(&mut self, password: &str) -> Result<(), String> {
+		let mut salt = [0; SALT_LENGTH];
+		rand::rand_bytes(&mut salt).map_err(|e| e.to_string())?;
+		let hash = hash(password, &salt, ITERATIONS)?;
+
+		self.salt = base64::encode_block(&salt);
+		self.hash = base64::encode_block(&hash);
+		self.iterations = ITERATIONS;
+		Ok(())
+	}
+
+	/// Checks the password matches.
+	/// This hashes the password, so blocks for a while.
+	pub fn verify(&self, password: &str) -> bool {
+		let (Ok(salt), Ok(expected)) = (
+			base64::decode_block(&self.salt),
+			base64::decode_block(&self.hash),
+		) else {
+			return false;
+		};
+		let Ok(hash) = hash(password, &salt, self.iterations) else {
+			return false;
+		};
+		hash.len() == expected.len() && memcmp::eq(&hash, &expected)
+	}
+}
+
+/// Hashes the password as if checking it against an account.
+/// Used when there's no such account, so how long a log in takes
+/// doesn't show which usernames have one.
+pub fn verify_missing(password: &str) {
+	_ = hash(password, &[0; SALT_LENGTH], ITERATIONS);
+}
+
+fn hash(
+	password: &str,
+	salt: &[u8],
+	iterations: usize,
+) -> Result<[u8; HASH_LENGTH], String> {
+	let mut hash = [0; HASH_LENGTH];
+	pkcs5::pbkdf2_hmac(
+		password.as_bytes(),
+		salt,
+		iterations,
+		MessageDigest::sha256(),
+		&mut hash,
+	)
+	.map_err(|e| e.to_string())?;
+	Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn only_the_right_password_verifies() {
+		let mut account = Account::new("alice".into(), "hunter22").unwrap();
+		assert!(account.verify("hunter22"));
+		assert!(!account.verify("hunter23"));
+
+		account.set_password("correct horse").unwrap();
+		assert!(account.verify("correct horse"));
+		assert!(!account.verify("hunter22"));
+	}
+
+	#[test]
+	fn passwords_are_salted() {
+		let a = Account::new("a".into(), "hunter22").unwrap();
+		let b = Account::new("b".into(), "hunter22").unwrap();
+		assert_ne!(a.salt, b.salt);
+		assert_ne!(a.hash, b.hash);
+	}
+
+	#[test]
+	fn corrupt_hashes_dont_verify() {
+		let mut account = Account::new("alice".into(), "hunter22").unwrap();
+		account.hash = "not base64!".into();
+		assert!(!account.verify("hunter22"));
+	}
+}
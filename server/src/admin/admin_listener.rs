@@ -0,0 +1,171 @@
+use std::{
+	fs::OpenOptions,
+	io::{self, Write},
+	net::{Ipv4Addr, SocketAddr},
+	path::Path,
+	sync::Arc,
+	time::Duration,
+};
+
+use foundation::{
+	admin::{AdminAuth, AdminRequest, AdminResponse},
+	networking::json::{read_message, write_message},
+};
+use log::{debug, error, info, trace, warn};
+use openssl::{base64, memcmp, rand};
+use tokio::{
+	net::{TcpListener, TcpStream},
+	sync::{mpsc::UnboundedSender, oneshot},
+	task::JoinHandle,
+	time::timeout,
+};
+
+use crate::{config::AdminConfig, server_va::ServerMessages};
+
+/// how long an admin has to send the token.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+const TOKEN_LENGTH: usize = 32;
+
+/// # AdminListener
+/// Accepts admin connections on localhost,
+/// passing each request to the server and writing back its response.
+/// Admins must first send the token the listener wrote to its token file.
+pub struct AdminListener {
+	listener: TcpListener,
+	sender: UnboundedSender<ServerMessages>,
+	token: Arc<String>,
+}
+
+impl AdminListener {
+	pub async fn new(
+		config: &AdminConfig,
+		sender: UnboundedSender<ServerMessages>,
+	) -> io::Result<Self> {
+		debug!("setting up listener");
+		let listener =
+			TcpListener::bind((Ipv4Addr::LOCALHOST, config.port)).await?;
+
+		let token = Arc::new(new_token()?);
+		write_token(&config.token_file, &token)?;
+		info!(path:% = config.token_file.display(); "wrote admin token");
+
+		Ok(Self {
+			listener,
+			sender,
+			token,
+		})
+	}
+
+	pub async fn run(&self) {
+		loop {
+			trace!("waiting for admin");
+			let Ok((stream, addr)) = self.listener.accept().await else {
+				warn!("accept failed");
+				continue;
+			};
+
+			tokio::spawn(run_admin(
+				stream,
+				addr,
+				self.token.clone(),
+				self.sender.clone(),
+			));
+		}
+	}
+
+	pub fn start_run(
+		config: AdminConfig,
+		sender: UnboundedSender<ServerMessages>,
+	) -> JoinHandle<()> {
+		tokio::spawn(async move {
+			match AdminListener::new(&config, sender).await {
+				Ok(listener) => listener.run().await,
+				Err(e) => error!("failed to bind: {}", e),
+			}
+		})
+	}
+}
+
+/// Answers requests from one admin until they disconnect,
+/// once they've sent the token.
+async fn run_admin(
+	mut stream: TcpStream,
+	addr: SocketAddr,
+	token: Arc<String>,
+	sender: UnboundedSender<ServerMessages>,
+) {
+	let auth = timeout(AUTH_TIMEOUT, read_message::<_, AdminAuth>(&mut stream));
+	let authenticated = match auth.await {
+		Ok(Ok(auth)) => {
+			auth.token.len() == token.len()
+				&& memcmp::eq(auth.token.as_bytes(), token.as_bytes())
+		}
+		_ => false,
+	};
+	if !authenticated {
+		warn!(addr:% = addr; "admin sent the wrong token");
+		let response = AdminResponse::Error {
+			reason: "wrong admin token".into(),
+		};
+		write_message(&mut stream, response).await;
+		return;
+	}
+	write_message(&mut stream, AdminResponse::Ok).await;
+	info!(addr:% = addr; "admin connected");
+
+	loop {
+		let request = match read_message::<_, AdminRequest>(&mut stream).await {
+			Ok(request) => request,
+			Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+				let response = AdminResponse::Error {
+					reason: e.to_string(),
+				};
+				write_message(&mut stream, response).await;
+				continue;
+			}
+			Err(_) => break,
+		};
+
+		info!(addr:% = addr; "admin requested {:?}", request);
+		let (reply, response) = oneshot::channel();
+		if sender
+			.send(ServerMessages::Admin { request, reply })
+			.is_err()
+		{
+			break;
+		}
+		let Ok(response) = response.await else {
+			break;
+		};
+
+		write_message(&mut stream, response).await;
+	}
+
+	info!(addr:% = addr; "admin disconnected");
+}
+
+fn new_token() -> io::Result<String> {
+	let mut token = [0; TOKEN_LENGTH];
+	rand::rand_bytes(&mut token).map_err(io::Error::other)?;
+	Ok(base64::encode_block(&token))
+}
+
+/// Writes the token where only the servers user can read it.
+fn write_token(path: &Path, token: &str) -> io::Result<()> {
+	let mut options = OpenOptions::new();
+	options.write(true).create(true).truncate(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+
+	let mut file = options.open(path)?;
+	// the mode only applies to new files, so an old file is fixed as well
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+	}
+	file.write_all(token.as_bytes())
+}
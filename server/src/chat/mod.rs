@@ -1,4 +1,7 @@
 use foundation::prelude::GlobalMessage;
+use log::{debug, trace};
+
+use crate::logging;
 
 pub struct ChatManager {
 	messages: Vec<GlobalMessage>,
@@ -12,12 +15,21 @@ impl ChatManager {
 	}
 
 	pub fn add_message(&mut self, message: GlobalMessage) {
-		println!("[ChatManager] added new global message {:?}", message);
+		debug!(
+			id:% = message.uuid,
+			from:% = message.from,
+			content:% = logging::body(&message.content);
+			"added global message"
+		);
 		self.messages.push(message);
 	}
 
+	pub fn message_count(&self) -> usize {
+		self.messages.len()
+	}
+
 	pub fn get_messages(&mut self) -> Vec<GlobalMessage> {
-		println!("[ChatManager] got all messages");
+		trace!("got all messages");
 		self.messages.clone()
 	}
 }
@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+	command::{Command, CommandHandler},
+	config::CommandConfig,
+	role::{Permission, Role},
+};
+
+/// # CommandRegistry
+/// Every command users can run, keyed by name.
+///
+/// The config can change who may run any command,
+/// including those registered by plugins.
+#[derive(Debug)]
+pub struct CommandRegistry {
+	commands: BTreeMap<String, Command>,
+	disabled: HashSet<String>,
+	permissions: HashMap<String, Permission>,
+}
+
+impl CommandRegistry {
+	pub fn new(config: CommandConfig) -> Self {
+		let mut registry = Self {
+			commands: BTreeMap::new(),
+			disabled: config.disabled.into_iter().collect(),
+			permissions: config.permissions,
+		};
+
+		use CommandHandler::*;
+		use Permission::{ManageRoles, Moderate, PostGlobal, PrivateMessage};
+		let builtins = [
+			(
+				"me",
+				"<action>",
+				"describes what you are doing",
+				Some(PostGlobal),
+				Me,
+			),
+			("nick", "<name>", "changes your name", None, Nick),
+			(
+				"msg",
+				"<user> <message>",
+				"sends a private message",
+				Some(PrivateMessage),
+				Msg,
+			),
+			("who", "", "lists who is online", None, Who),
+			(
+				"help",
+				"[command]",
+				"lists the commands you can run, or describes one",
+				None,
+				Help,
+			),
+			(
+				"kick",
+				"<user> [reason]",
+				"disconnects a user",
+				Some(Moderate),
+				Kick,
+			),
+			(
+				"ban",
+				"<user|uuid|ip> [duration] [reason]",
+				"stops a user connecting, for a duration such as 2h, or for good",
+				Some(Moderate),
+				Ban,
+			),
+			(
+				"unban",
+				"<user|uuid|ip>",
+				"lifts a ban",
+				Some(Moderate),
+				Unban,
+			),
+			(
+				"mute",
+				"<user> [duration] [reason]",
+				"stops a user sending global messages",
+				Some(Moderate),
+				Mute,
+			),
+			("unmute", "<user>", "lifts a mute", Some(Moderate), Unmute),
+			(
+				"role",
+				"<user> [role]",
+				"shows or changes a users role",
+				Some(ManageRoles),
+				CommandHandler::Role,
+			),
+			(
+				"password",
+				"<current> <new>",
+				"changes your accounts password",
+				None,
+				Password,
+			),
+			(
+				"unregister",
+				"<password>",
+				"deletes your account",
+				None,
+				Unregister,
+			),
+		];
+		for (name, usage, description, permission, handler) in builtins {
+			registry.commands.insert(
+				name.into(),
+				Command {
+					name: name.into(),
+					usage: usage.into(),
+					description: description.into(),
+					permission,
+					handler,
+				},
+			);
+		}
+
+		registry
+	}
+
+	/// Adds a plugins command.
+	/// A plugin may replace its own commands, but not anyone else's.
+	pub fn register_plugin_command(
+		&mut self,
+		plugin_id: String,
+		name: String,
+		description: String,
+	) -> Result<(), String> {
+		if let Some(existing) = self.commands.get(&name) {
+			if existing.handler != CommandHandler::Plugin(plugin_id.clone()) {
+				return Err(format!("/{} is already registered", name));
+			}
+		}
+
+		self.commands.insert(
+			name.clone(),
+			Command {
+				name,
+				usage: String::new(),
+				description,
+				permission: None,
+				handler: CommandHandler::Plugin(plugin_id),
+			},
+		);
+		Ok(())
+	}
+
+	/// Applies a reloaded config, keeping the commands plugins registered.
+	pub fn configure(&mut self, config: CommandConfig) {
+		self.disabled = config.disabled.into_iter().collect();
+		self.permissions = config.permissions;
+	}
+
+	pub fn remove_plugin_commands(&mut self, plugin_id: &str) {
+		self.commands.retain(|_, c| match &c.handler {
+			CommandHandler::Plugin(id) => id != plugin_id,
+			_ => true,
+		});
+	}
+
+	pub fn get(&self, name: &str) -> Option<&Command> {
+		self.commands.get(name)
+	}
+
+	/// What a user needs to run the command, as set in the config or by the command.
+	pub fn permission(&self, command: &Command) -> Option<Permission> {
+		self
+			.permissions
+			.get(&command.name)
+			.copied()
+			.or(command.permission)
+	}
+
+	pub fn allows(&self, command: &Command, role: Role) -> bool {
+		!self.disabled.contains(&command.name)
+			&& self.permission(command).is_none_or(|p| role.has(p))
+	}
+
+	/// The commands a user with the role may run, in name order.
+	pub fn available(&self, role: Role) -> impl Iterator<Item = &Command> {
+		self.commands.values().filter(move |c| self.allows(c, role))
+	}
+}
@@ -0,0 +1,90 @@
+//! Slash commands typed into the global chat.
+//!
+//! Messages starting with `/` are run as commands instead of being broadcast,
+//! and are answered with a private message from the server.
+//! Built in commands and those registered by plugins share one registry,
+//! so they are listed and permitted the same way.
+
+mod command_registry;
+
+pub use command_registry::CommandRegistry;
+use uuid::Uuid;
+
+use crate::role::Permission;
+
+/// the sender of the servers replies to commands.
+pub const SYSTEM_UUID: Uuid = Uuid::nil();
+
+/// # CommandHandler
+/// What runs a command.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CommandHandler {
+	Me,
+	Nick,
+	Msg,
+	Who,
+	Help,
+	Kick,
+	Ban,
+	Unban,
+	Mute,
+	Unmute,
+	Role,
+	Password,
+	Unregister,
+	/// passed to the plugin with this id.
+	Plugin(String),
+}
+
+/// # Command
+/// A command in the registry.
+#[derive(Debug, Clone)]
+pub struct Command {
+	pub name: String,
+	/// the arguments the command takes, such as "<user> <message>".
+	pub usage: String,
+	pub description: String,
+	/// what a user needs to run the command, if anything.
+	pub permission: Option<Permission>,
+	pub handler: CommandHandler,
+}
+
+impl Command {
+	/// How the command is shown in help, such as "/msg <user> <message>".
+	pub fn signature(&self) -> String {
+		if self.usage.is_empty() {
+			format!("/{}", self.name)
+		} else {
+			format!("/{} {}", self.name, self.usage)
+		}
+	}
+}
+
+/// Splits a message into a command name and its arguments,
+/// if it is a command.
+/// Messages starting with `//` are sent as messages, with one slash removed.
+pub fn parse(content: &str) -> Option<(&str, &str)> {
+	let command = content.strip_prefix('/')?;
+	if command.starts_with('/') {
+		return None;
+	}
+
+	let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+	Some((name, args.trim()))
+}
+
+/// Splits the first word from the rest of the arguments.
+pub fn split_word(args: &str) -> (&str, &str) {
+	match args.split_once(' ') {
+		Some((word, rest)) => (word, rest.trim()),
+		None => (args, ""),
+	}
+}
+
+/// The message sent by a user starting it with `//`.
+pub fn unescape(content: String) -> String {
+	match content.strip_prefix("//") {
+		Some(content) => format!("/{}", content),
+		None => content,
+	}
+}
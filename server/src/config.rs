@@ -0,0 +1,517 @@
+use std::{
+	collections::HashMap,
+	fs,
+	io,
+	net::{IpAddr, Ipv4Addr},
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use foundation::networking::{ADMIN_PORT, DISCOVERY_PORT};
+use log::LevelFilter;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+	metrics::METRICS_PORT,
+	network::connection_limiter::IpNetwork,
+	plugin::PluginPermissions,
+	role::{Permission, Role},
+};
+
+/// # ServerConfig
+/// Settings loaded from the servers toml config file.
+/// Any missing section or field falls back to its default value.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+	pub info: InfoConfig,
+	pub heartbeat: HeartbeatConfig,
+	pub session: SessionConfig,
+	pub discovery: DiscoveryConfig,
+	pub federation: FederationConfig,
+	pub plugins: PluginConfig,
+	pub admin: AdminConfig,
+	pub commands: CommandConfig,
+	pub moderation: ModerationConfig,
+	pub roles: RoleConfig,
+	pub usernames: UsernameConfig,
+	pub accounts: AccountConfig,
+	pub log: LogConfig,
+	pub metrics: MetricsConfig,
+	pub connections: ConnectionConfig,
+}
+
+impl ServerConfig {
+	/// Reads the config from the given path.
+	/// If the file doesn't exist the default config is used.
+	pub fn load(path: &Path) -> io::Result<Self> {
+		if !path.exists() {
+			return Ok(Self::default());
+		}
+
+		let contents = fs::read_to_string(path)?;
+		toml::from_str(&contents)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+	}
+}
+
+/// # InfoConfig
+/// Describes the server to clients asking for its info.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InfoConfig {
+	pub name: String,
+	pub owner: String,
+	/// shown to clients before they connect, such as a message of the day.
+	pub description: String,
+	/// the most users that can be connected at once, 0 for no limit.
+	pub max_users: u32,
+}
+
+impl Default for InfoConfig {
+	fn default() -> Self {
+		Self {
+			name: "test server".into(),
+			owner: "mickyb18a@gmail.com".into(),
+			description: String::new(),
+			max_users: 0,
+		}
+	}
+}
+
+/// # HeartbeatConfig
+/// Controls how often clients are pinged,
+/// and how many pongs they may miss before being disconnected.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+	/// 0 disables the heartbeat.
+	pub interval_secs: u64,
+	pub max_missed_pongs: u32,
+}
+
+impl HeartbeatConfig {
+	pub fn interval(&self) -> Duration {
+		Duration::from_secs(self.interval_secs)
+	}
+
+	pub fn enabled(&self) -> bool {
+		self.interval_secs != 0
+	}
+}
+
+impl Default for HeartbeatConfig {
+	fn default() -> Self {
+		Self {
+			interval_secs: 15,
+			max_missed_pongs: 3,
+		}
+	}
+}
+
+/// # SessionConfig
+/// Controls how long a dropped clients session is kept,
+/// and how many missed events are queued for it in that time.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+	pub grace_period_secs: u64,
+	pub max_pending_events: usize,
+}
+
+impl SessionConfig {
+	pub fn grace_period(&self) -> Duration {
+		Duration::from_secs(self.grace_period_secs)
+	}
+}
+
+impl Default for SessionConfig {
+	fn default() -> Self {
+		Self {
+			grace_period_secs: 30,
+			max_pending_events: 500,
+		}
+	}
+}
+
+/// # ConnectionConfig
+/// Controls who may connect, how many connections they may hold,
+/// and how long they have to finish the handshake.
+/// A limit of 0 means there is none.
+///
+/// ```toml
+/// [connections]
+/// max_per_ip = 4
+/// allow = ["10.0.0.0/8", "127.0.0.1"]
+/// deny = ["10.0.0.13"]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConnectionConfig {
+	pub handshake_timeout_secs: u64,
+	pub max_connections: usize,
+	pub max_per_ip: usize,
+	/// if not empty, only these addresses may connect.
+	pub allow: Vec<IpNetwork>,
+	/// checked before the allow list.
+	pub deny: Vec<IpNetwork>,
+}
+
+impl ConnectionConfig {
+	pub fn handshake_timeout(&self) -> Duration {
+		Duration::from_secs(self.handshake_timeout_secs)
+	}
+}
+
+impl Default for ConnectionConfig {
+	fn default() -> Self {
+		Self {
+			handshake_timeout_secs: 10,
+			max_connections: 1024,
+			max_per_ip: 16,
+			allow: Vec::new(),
+			deny: Vec::new(),
+		}
+	}
+}
+
+/// # DiscoveryConfig
+/// Controls whether the server answers discovery requests
+/// from clients on the local network.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+	pub enabled: bool,
+	pub port: u16,
+}
+
+impl Default for DiscoveryConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			port: DISCOVERY_PORT,
+		}
+	}
+}
+
+/// # FederationConfig
+/// Controls linking with other servers,
+/// so clients on different servers can talk to each other.
+///
+/// Every server in the mesh must share the same secret,
+/// as linked servers are trusted with their users messages.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FederationConfig {
+	pub enabled: bool,
+	/// identifies this server in the mesh, random unless set.
+	pub server_id: Uuid,
+	/// proves a server belongs to the mesh, required to enable federation.
+	pub secret: String,
+	/// the address other servers link to.
+	pub bind_address: IpAddr,
+	/// the port other servers link to.
+	pub port: u16,
+	/// addresses of the servers to link to, such as "10.0.0.2:6600".
+	pub peers: Vec<String>,
+	pub reconnect_secs: u64,
+}
+
+impl FederationConfig {
+	pub fn reconnect_delay(&self) -> Duration {
+		Duration::from_secs(self.reconnect_secs)
+	}
+}
+
+impl Default for FederationConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			server_id: Uuid::new_v4(),
+			secret: String::new(),
+			bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+			port: 6600,
+			peers: Vec::new(),
+			reconnect_secs: 5,
+		}
+	}
+}
+
+/// # PluginConfig
+/// Controls where plugins are loaded from,
+/// and the permissions each plugin is approved for.
+///
+/// ```toml
+/// [plugins.approved."greeter.lua"]
+/// server = "ReadWrite"
+/// ```
+///
+/// Plugins are given what their manifest asks for, up to what is approved here.
+///
+/// Permissions only confine scripts.
+/// Native libraries run inside the server with its full privileges,
+/// so they can ignore their permissions, and should only be installed if trusted.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PluginConfig {
+	pub enabled: bool,
+	pub directory: PathBuf,
+	/// keyed by the file name of the plugin in the directory.
+	pub approved: HashMap<String, PluginPermissions>,
+	pub scripts: ScriptConfig,
+}
+
+impl Default for PluginConfig {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			directory: PathBuf::from("plugins"),
+			approved: HashMap::new(),
+			scripts: ScriptConfig::default(),
+		}
+	}
+}
+
+/// # ScriptConfig
+/// Limits for plugins written as scripts.
+/// Scripts can't touch the filesystem unless imports are allowed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ScriptConfig {
+	/// the most operations a script can run per event or command.
+	pub max_operations: u64,
+	pub max_string_size: usize,
+	/// the most items in a single array or map.
+	pub max_collection_size: usize,
+	/// the most memory a lua script can allocate, in bytes.
+	pub max_memory: usize,
+	/// the most values a lua script can keep in its store.
+	pub max_store_entries: usize,
+	/// the most a lua scripts store can hold, in bytes of json.
+	pub max_store_bytes: usize,
+	/// lets scripts import other scripts from the plugins directory.
+	pub allow_imports: bool,
+}
+
+impl Default for ScriptConfig {
+	fn default() -> Self {
+		Self {
+			max_operations: 100_000,
+			max_string_size: 64 * 1024,
+			max_collection_size: 10_000,
+			max_memory: 16 * 1024 * 1024,
+			max_store_entries: 1000,
+			max_store_bytes: 1024 * 1024,
+			allow_imports: false,
+		}
+	}
+}
+
+/// # AdminConfig
+/// Controls the admin interface, which only listens on localhost.
+/// A new token is written to the token file each time the server starts,
+/// readable only by the servers user, and admins must send it to connect.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+	pub enabled: bool,
+	pub port: u16,
+	pub token_file: PathBuf,
+}
+
+impl Default for AdminConfig {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			port: ADMIN_PORT,
+			token_file: PathBuf::from("admin.token"),
+		}
+	}
+}
+
+/// # MetricsConfig
+/// Controls the metrics endpoint, which only listens on localhost.
+/// Metrics are served at `/metrics` in the prometheus text format.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+	pub enabled: bool,
+	pub port: u16,
+}
+
+impl Default for MetricsConfig {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			port: METRICS_PORT,
+		}
+	}
+}
+
+/// # CommandConfig
+/// Controls who may run each slash command.
+///
+/// ```toml
+/// [commands]
+/// disabled = ["me"]
+///
+/// [commands.permissions]
+/// nick = "Moderate"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CommandConfig {
+	/// commands no one may run.
+	pub disabled: Vec<String>,
+	/// keyed by command name, replacing the permission the command needs.
+	pub permissions: HashMap<String, Permission>,
+}
+
+/// # ModerationConfig
+/// Where bans and the record of moderators actions are kept.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ModerationConfig {
+	pub bans_file: PathBuf,
+	/// moderators actions are appended here, one json object per line.
+	pub audit_log: PathBuf,
+}
+
+impl Default for ModerationConfig {
+	fn default() -> Self {
+		Self {
+			bans_file: PathBuf::from("bans.json"),
+			audit_log: PathBuf::from("audit.log"),
+		}
+	}
+}
+
+/// # RoleConfig
+/// Controls the role users are given.
+///
+/// ```toml
+/// [roles]
+/// default = "Member"
+///
+/// [roles.users]
+/// "6a1e0b07-5c1d-4f3a-9e2b-7d4c8f10a2b3" = "Owner"
+/// ```
+///
+/// Roles given with `/role` are saved to the file,
+/// but can't change those set here.
+///
+/// Anyone can connect with a uuid, so roles above member
+/// are only given to users logged in to an account.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RoleConfig {
+	/// the role of anyone not given one.
+	pub default: Role,
+	pub file: PathBuf,
+	/// keyed by user uuid.
+	pub users: HashMap<Uuid, Role>,
+}
+
+impl Default for RoleConfig {
+	fn default() -> Self {
+		Self {
+			default: Role::Member,
+			file: PathBuf::from("roles.json"),
+			users: HashMap::new(),
+		}
+	}
+}
+
+/// # UsernameConfig
+/// Rules for the names users connect with, or change to.
+///
+/// Names may use letters, numbers, spaces, and `_`, `-` or `.`,
+/// but can't start or end with a space.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UsernameConfig {
+	pub min_length: usize,
+	pub max_length: usize,
+	/// names no one may use, ignoring case.
+	/// The servers own name is always reserved.
+	pub reserved: Vec<String>,
+	/// stops two users having the same name, ignoring case.
+	pub unique: bool,
+}
+
+impl Default for UsernameConfig {
+	fn default() -> Self {
+		Self {
+			min_length: 1,
+			max_length: 32,
+			reserved: vec!["admin".into(), "server".into(), "system".into()],
+			unique: true,
+		}
+	}
+}
+
+/// # AccountConfig
+/// Controls registered accounts.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AccountConfig {
+	pub file: PathBuf,
+	/// whether clients can create accounts when connecting.
+	pub registration: bool,
+	/// turns away clients that don't log in to an account.
+	pub required: bool,
+	pub min_password_length: usize,
+}
+
+impl Default for AccountConfig {
+	fn default() -> Self {
+		Self {
+			file: PathBuf::from("accounts.json"),
+			registration: true,
+			required: false,
+			min_password_length: 8,
+		}
+	}
+}
+
+/// # LogConfig
+/// Controls what the server logs, and how.
+///
+/// ```toml
+/// [log]
+/// level = "info"
+/// format = "json"
+///
+/// [log.targets]
+/// "server::network" = "debug"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+	pub level: LevelFilter,
+	pub format: LogFormat,
+	/// keyed by module path, such as "server::federation",
+	/// replacing the level for that module and those inside it.
+	pub targets: HashMap<String, LevelFilter>,
+	/// logs the content of chat messages, which is redacted otherwise.
+	pub message_bodies: bool,
+}
+
+impl Default for LogConfig {
+	fn default() -> Self {
+		Self {
+			level: LevelFilter::Info,
+			format: LogFormat::Plain,
+			targets: HashMap::new(),
+			message_bodies: false,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+	Plain,
+	Json,
+}
@@ -1,20 +1,37 @@
 use std::net::SocketAddr;
 
+use foundation::prelude::ClientDetails;
 use uuid::Uuid;
 
+use crate::{moderation::unix_time, role::Role};
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ClientInfo {
 	uuid: Uuid,
 	username: String,
 	addr: SocketAddr,
+	role: Role,
+	/// true if the client logged in to the account with its uuid.
+	authenticated: bool,
+	/// the unix time the client connected.
+	connected_at: u64,
 }
 
 impl ClientInfo {
-	pub fn new(uuid: Uuid, username: String, addr: SocketAddr) -> Self {
+	pub fn new(
+		uuid: Uuid,
+		username: String,
+		addr: SocketAddr,
+		role: Role,
+		authenticated: bool,
+	) -> Self {
 		Self {
 			uuid,
 			username,
 			addr,
+			role,
+			authenticated,
+			connected_at: unix_time(),
 		}
 	}
 
@@ -26,7 +43,37 @@ impl ClientInfo {
 		self.username.clone()
 	}
 
+	pub fn set_username(&mut self, username: String) {
+		self.username = username;
+	}
+
 	pub fn get_addr(&self) -> SocketAddr {
 		self.addr
 	}
+
+	pub fn get_role(&self) -> Role {
+		self.role
+	}
+
+	pub fn set_role(&mut self, role: Role) {
+		self.role = role;
+	}
+
+	pub fn is_authenticated(&self) -> bool {
+		self.authenticated
+	}
+
+	pub fn get_connected_at(&self) -> u64 {
+		self.connected_at
+	}
+
+	/// How the client is shown to other clients.
+	pub fn details(&self) -> ClientDetails {
+		ClientDetails {
+			uuid: self.uuid.to_string(),
+			name: self.username.clone(),
+			address: self.addr.to_string(),
+			role: self.role.to_string(),
+		}
+	}
 }
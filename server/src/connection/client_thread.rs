@@ -1,83 +1,240 @@
+use std::{
+	sync::{
+		atomic::{AtomicBool, AtomicU32, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
 use foundation::prelude::{ClientDetails, GlobalMessage, PrivateMessage};
-use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle};
+use futures::future::BoxFuture;
+use log::{debug, info, warn};
+use tokio::{
+	sync::{mpsc::UnboundedSender, Mutex},
+	task::JoinHandle,
+	time::{interval, timeout},
+};
 use uuid::Uuid;
 
 use crate::{
+	config::HeartbeatConfig,
 	connection::{
 		client_info::ClientInfo,
 		connection_manager::ConnectionManagerMessage,
 	},
+	metrics::METRICS,
 	network::{ClientWriter, NetworkConnection},
 };
 
+/// how long a write to a client may take, including waiting for the writer.
+/// A client that can't keep up is disconnected,
+/// so it can't hold up the connection manager or its heartbeat.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// the queue depth label for writes waiting on or holding a clients writer.
+const SENDING_QUEUE: &str = "sending";
+
 pub struct ClientThread {
 	read_task: JoinHandle<()>,
-	writer: Box<dyn ClientWriter>,
+	/// not started if the client doesn't support the heartbeat.
+	heartbeat_task: Option<JoinHandle<()>>,
+	missed_pongs: Arc<AtomicU32>,
+	writer: Arc<SharedWriter>,
+	/// counted against while the thread runs.
+	protocol: &'static str,
 }
 
 impl ClientThread {
 	pub async fn new_run(
 		uuid: Uuid,
+		session_token: Uuid,
+		resumed: bool,
 		conn: Box<dyn NetworkConnection>,
 		connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+		heartbeat: Option<HeartbeatConfig>,
 	) -> Self {
-		println!("[ClientThread] creating thread");
-		let (writer, reader) = conn.send_connected(uuid).await;
+		debug!(uuid:% = uuid; "creating thread");
+		let protocol = conn.protocol();
+		METRICS.connections.inc(protocol);
+		let (writer, reader) =
+			conn.send_connected(uuid, session_token, resumed).await;
+		let writer = Arc::new(SharedWriter {
+			writer: Mutex::new(writer),
+			closed: AtomicBool::new(false),
+			uuid,
+			session: session_token,
+			channel: connection_manager_sender.clone(),
+		});
+		let missed_pongs = Arc::new(AtomicU32::new(0));
 
-		println!("[ClientThread] creating tasks");
+		debug!(uuid:% = uuid; "creating tasks");
 		ClientThread {
 			read_task: reader.start_run(uuid, connection_manager_sender.clone()),
+			heartbeat_task: heartbeat.map(|heartbeat| {
+				Self::start_heartbeat(writer.clone(), missed_pongs.clone(), heartbeat)
+			}),
+			missed_pongs,
 			writer,
+			protocol,
 		}
 	}
 
+	/// Pings the client on the configured interval.
+	/// If too many pings go unanswered,
+	/// the client is reported to the connection manager as disconnected.
+	fn start_heartbeat(
+		writer: Arc<SharedWriter>,
+		missed_pongs: Arc<AtomicU32>,
+		config: HeartbeatConfig,
+	) -> JoinHandle<()> {
+		tokio::spawn(async move {
+			let mut interval = interval(config.interval());
+			// the first tick completes immediately
+			interval.tick().await;
+
+			loop {
+				interval.tick().await;
+
+				let missed = missed_pongs.fetch_add(1, Ordering::SeqCst);
+				if missed >= config.max_missed_pongs {
+					info!(
+						uuid:% = writer.uuid;
+						"missed {} pongs, disconnecting", missed
+					);
+					writer.close();
+					return;
+				}
+
+				METRICS.messages_sent.inc("ping");
+				if !writer.write(|w| w.send_ping()).await {
+					return;
+				}
+			}
+		})
+	}
+
+	pub fn received_pong(&self) {
+		self.missed_pongs.store(0, Ordering::SeqCst);
+	}
+
 	pub async fn send_clients(&mut self, clients: Vec<ClientDetails>) {
-		self.writer.send_clients(clients).await
+		METRICS.messages_sent.inc("clients");
+		self.writer.write(|w| w.send_clients(clients)).await;
 	}
 
 	pub async fn send_client_joined(&mut self, details: ClientDetails) {
-		self.writer.send_client_joined(details).await;
+		METRICS.messages_sent.inc("client_joined");
+		self.writer.write(|w| w.send_client_joined(details)).await;
 	}
 	pub async fn send_client_left(&mut self, uuid: Uuid) {
-		self.writer.send_client_left(uuid).await
+		METRICS.messages_sent.inc("client_left");
+		self.writer.write(|w| w.send_client_left(uuid)).await;
+	}
+	pub async fn send_client_renamed(&mut self, uuid: Uuid, username: String) {
+		METRICS.messages_sent.inc("client_renamed");
+		self
+			.writer
+			.write(|w| w.send_client_renamed(uuid, username))
+			.await;
 	}
 
 	// todo: link this in with message storage
 	pub(crate) async fn send_global_message(&mut self, message: GlobalMessage) {
-		self.writer.send_global_message(message).await;
+		METRICS.messages_sent.inc("global_message");
+		self.writer.write(|w| w.send_global_message(message)).await;
 	}
 
 	pub(crate) async fn send_global_messages(
 		&mut self,
 		messages: Vec<GlobalMessage>,
 	) {
-		self.writer.send_global_messages(messages).await;
+		METRICS.messages_sent.inc("global_messages");
+		self
+			.writer
+			.write(|w| w.send_global_messages(messages))
+			.await;
 	}
 
-	pub(crate) async fn send_disconnected(&mut self) {
-		self.writer.send_disconnect().await
+	pub(crate) async fn send_disconnected(&mut self, reason: String) {
+		METRICS.messages_sent.inc("disconnect");
+		self.writer.write(|w| w.send_disconnect(reason)).await;
 	}
 
 	pub(crate) async fn send_private_message(
 		&mut self,
 		from: Uuid,
+		to: Uuid,
 		uuid: Uuid,
 		content: String,
 	) {
-		self
-			.writer
-			.send_private_message(PrivateMessage {
-				uuid: uuid.to_string(),
-				from: from.to_string(),
-				content,
-			})
-			.await;
+		METRICS.messages_sent.inc("private_message");
+		let message = PrivateMessage {
+			uuid: uuid.to_string(),
+			from: from.to_string(),
+			to: to.to_string(),
+			content,
+		};
+		self.writer.write(|w| w.send_private_message(message)).await;
 	}
 }
 
 impl Drop for ClientThread {
 	fn drop(&mut self) {
+		METRICS.connections.dec(self.protocol);
 		self.read_task.abort();
+		if let Some(heartbeat_task) = &self.heartbeat_task {
+			heartbeat_task.abort();
+		}
+	}
+}
+
+/// # SharedWriter
+/// The clients writer, shared by the connection manager and the heartbeat.
+/// Writes that time out close it, as a write cut short
+/// may have left part of a message on the stream.
+struct SharedWriter {
+	writer: Mutex<Box<dyn ClientWriter>>,
+	closed: AtomicBool,
+	uuid: Uuid,
+	session: Uuid,
+	channel: UnboundedSender<ConnectionManagerMessage>,
+}
+
+impl SharedWriter {
+	/// Runs the write, unless the writer is closed.
+	/// Returns false if the write didn't happen.
+	async fn write<F>(&self, write: F) -> bool
+	where
+		F: FnOnce(&mut Box<dyn ClientWriter>) -> BoxFuture<'_, ()>,
+	{
+		if self.closed.load(Ordering::SeqCst) {
+			return false;
+		}
+
+		METRICS.queue_depth.inc(SENDING_QUEUE);
+		let written = timeout(WRITE_TIMEOUT, async {
+			write(&mut *self.writer.lock().await).await
+		})
+		.await;
+		METRICS.queue_depth.dec(SENDING_QUEUE);
+
+		if written.is_err() {
+			warn!(uuid:% = self.uuid; "write timed out, disconnecting");
+			self.close();
+			return false;
+		}
+		true
+	}
+
+	/// Stops writing to the client,
+	/// and reports the session as disconnected to the connection manager.
+	fn close(&self) {
+		if !self.closed.swap(true, Ordering::SeqCst) {
+			_ = self.channel.send(ConnectionManagerMessage::Disconnected {
+				uuid: self.uuid,
+				session: self.session,
+			});
+		}
 	}
 }
 
@@ -1,151 +1,1673 @@
-use std::{collections::HashMap, net::SocketAddr};
+use std::{
+	collections::HashMap,
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
-use foundation::prelude::{ClientDetails, GlobalMessage};
+use foundation::{
+	admin::{ClientStatus, ServerStats},
+	event::Event,
+	networking::handshake::{
+		self,
+		HEARTBEAT,
+		MIN_PROTOCOL_VERSION,
+		PROTOCOL_VERSION,
+		SESSION_RESUME,
+	},
+	plugin::CommandInvocation,
+	prelude::{ClientDetails, GlobalMessage, Info, PrivateMessage},
+};
+use log::{debug, error, info, warn};
 use tokio::sync::{
 	mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+	oneshot,
 	Mutex,
 };
 use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::{
+	account::{self, Account, AccountChange, AccountStore, Credentials},
+	command::{self, CommandHandler, CommandRegistry, SYSTEM_UUID},
+	config::{
+		AccountConfig,
+		HeartbeatConfig,
+		InfoConfig,
+		ServerConfig,
+		SessionConfig,
+		UsernameConfig,
+	},
+	connection::{
+		client_info::ClientInfo,
+		client_thread::ClientThread,
+		session::{Session, SessionEvent},
+		username,
+	},
+	event_bus::EventBus,
+	federation::peer_manager::PeerManagerMessage,
+	logging,
+	metrics::METRICS,
+	moderation::{
+		format_duration,
+		parse_duration,
+		AuditAction,
+		AuditLog,
+		Ban,
+		BanList,
+		BanTarget,
+		MuteList,
+	},
+	network::{ConnectRequest, NetworkConnection, PROTOCOLS},
+	role::{Permission, Role, RoleStore},
+	server_va::ServerMessages,
+};
+
+/// The same for unknown usernames and wrong passwords,
+/// so clients can't tell which accounts exist.
+const WRONG_CREDENTIALS: &str = "wrong username or password";
+
+/// How admins are named in the audit log.
+const ADMIN_NAME: &str = "admin";
+
+pub struct ConnectionManager {
+	receiver: Mutex<UnboundedReceiver<ConnectionManagerMessage>>,
+	sender: UnboundedSender<ConnectionManagerMessage>,
+	server_sender: UnboundedSender<ServerMessages>,
+	event_bus: Arc<EventBus>,
+	client_map: HashMap<Uuid, ClientInfo>,
+	/// each users sessions, keyed by session token.
+	/// A user may be connected from several devices at once.
+	sessions: HashMap<Uuid, HashMap<Uuid, Session>>,
+	/// clients connected to other servers in the mesh.
+	remote_clients: HashMap<Uuid, ClientDetails>,
+	/// set if this server is part of a mesh.
+	peer_sender: Option<UnboundedSender<PeerManagerMessage>>,
+	/// plugin bots, listed as clients once they first speak.
+	bots: HashMap<Uuid, ClientDetails>,
+	/// the slash commands users can run.
+	commands: CommandRegistry,
+	bans: BanList,
+	mutes: MuteList,
+	audit_log: AuditLog,
+	roles: RoleStore,
+	accounts: AccountStore,
+	account_config: AccountConfig,
+	info: InfoConfig,
+	heartbeat: HeartbeatConfig,
+	session_config: SessionConfig,
+	usernames: UsernameConfig,
+}
+
+impl ConnectionManager {
+	/// Takes the parts of the config it needs.
+	/// Fails if one of the files it keeps is invalid,
+	/// so it isn't overwritten with an empty one.
+	pub fn new(
+		server_sender: UnboundedSender<ServerMessages>,
+		event_bus: Arc<EventBus>,
+		config: &ServerConfig,
+	) -> Result<Self, String> {
+		let (tx, rx) = unbounded_channel();
+		Ok(Self {
+			client_map: HashMap::new(),
+			sessions: HashMap::new(),
+			remote_clients: HashMap::new(),
+			peer_sender: None,
+			bots: HashMap::new(),
+			commands: CommandRegistry::new(config.commands.clone()),
+			bans: BanList::load(&config.moderation.bans_file)?,
+			mutes: MuteList::default(),
+			audit_log: AuditLog::new(&config.moderation.audit_log),
+			roles: RoleStore::load(&config.roles)?,
+			accounts: AccountStore::load(&config.accounts.file)?,
+			account_config: config.accounts.clone(),
+			info: config.info.clone(),
+			heartbeat: config.heartbeat.clone(),
+			session_config: config.session.clone(),
+			usernames: config.usernames.clone(),
+			server_sender,
+			event_bus,
+			receiver: Mutex::new(rx),
+			sender: tx,
+		})
+	}
+
+	/// Links the connection manager to the mesh,
+	/// so local events are shared with other servers.
+	pub fn set_peer_sender(
+		&mut self,
+		sender: UnboundedSender<PeerManagerMessage>,
+	) {
+		self.peer_sender = Some(sender);
+	}
+
+	pub async fn run(&mut self) {
+		loop {
+			let mut lock = self.receiver.lock().await;
+			let msg = lock.recv().await;
+			drop(lock);
+
+			match msg {
+				Some(ConnectionManagerMessage::AddClient { conn, request }) => {
+					self.add_client(conn, request).await
+				}
+				Some(ConnectionManagerMessage::Authenticated {
+					conn,
+					request,
+					account,
+					registered,
+				}) => self.authenticated(conn, request, account, registered).await,
+				Some(ConnectionManagerMessage::AccountChecked { uuid, change }) => {
+					self.account_checked(uuid, change).await
+				}
+
+				Some(ConnectionManagerMessage::SendInfo { conn }) => {
+					conn.send_info(self.get_info()).await
+				}
+				Some(ConnectionManagerMessage::GetInfo { reply }) => {
+					_ = reply.send(self.get_info());
+				}
+
+				Some(ConnectionManagerMessage::Disconnected { uuid, session }) => {
+					self.suspend_session(uuid, session).await
+				}
+				Some(ConnectionManagerMessage::SessionExpired { uuid, session }) => {
+					self.expire_session(uuid, session).await
+				}
+				Some(ConnectionManagerMessage::BroadcastGlobalMessage {
+					from,
+					content,
+				}) => {
+					self.broadcast_global_message(from, content).await;
+				}
+				Some(ConnectionManagerMessage::SendClientsTo { uuid, session }) => {
+					self.send_clients_to(uuid, session).await;
+				}
+				Some(ConnectionManagerMessage::SendGlobalMessages {
+					uuid,
+					session,
+				}) => {
+					self.send_global_messages(uuid, session).await;
+				}
+
+				Some(ConnectionManagerMessage::SendGlobalMessagesTo {
+					uuid,
+					session,
+					messages,
+				}) => {
+					self.send_global_messages_to(uuid, session, messages).await;
+				}
+
+				Some(ConnectionManagerMessage::SendPrivateMessage {
+					uuid,
+					from,
+					session,
+					to,
+					content,
+				}) => {
+					METRICS.messages_received.inc("private_message");
+					if self.check(from, Permission::PrivateMessage).await {
+						self
+							.send_private_message(to, from, session, uuid, content)
+							.await;
+					}
+				}
+				Some(ConnectionManagerMessage::Disconnect { uuid, session }) => {
+					self.disconnect(uuid, session).await
+				}
+				Some(ConnectionManagerMessage::ReceivedPong { uuid, session }) => {
+					self.received_pong(uuid, session)
+				}
+
+				Some(ConnectionManagerMessage::RemoteClientJoined(details)) => {
+					self.remote_client_joined(details).await
+				}
+				Some(ConnectionManagerMessage::RemoteClientUpdated(details)) => {
+					self.remote_client_updated(details).await
+				}
+				Some(ConnectionManagerMessage::RemoteClientLeft(uuid)) => {
+					self.remote_client_left(uuid).await
+				}
+				Some(ConnectionManagerMessage::RemoteGlobalMessage(message)) => {
+					self.remote_global_message(message).await
+				}
+				Some(ConnectionManagerMessage::RemotePrivateMessage(message)) => {
+					self.remote_private_message(message).await
+				}
+
+				Some(ConnectionManagerMessage::ListClients { reply }) => {
+					_ = reply.send(self.client_list());
+				}
+				Some(ConnectionManagerMessage::BotGlobalMessage { bot, content }) => {
+					self.bot_global_message(bot, content).await
+				}
+				Some(ConnectionManagerMessage::BotPrivateMessage {
+					bot,
+					to,
+					content,
+				}) => self.bot_private_message(bot, to, content).await,
+				Some(ConnectionManagerMessage::DisconnectUser { uuid }) => {
+					self
+						.disconnect_user(uuid, "disconnected by the server".into())
+						.await
+				}
+				Some(ConnectionManagerMessage::RegisterCommand {
+					plugin_id,
+					name,
+					description,
+				}) => self.register_command(plugin_id, name, description),
+				Some(ConnectionManagerMessage::PluginUnloaded { plugin_id, bot }) => {
+					self.plugin_unloaded(plugin_id, bot).await
+				}
+
+				Some(ConnectionManagerMessage::ListClientStatuses { reply }) => {
+					_ = reply.send(self.client_statuses());
+				}
+				Some(ConnectionManagerMessage::Notice { content }) => {
+					self.notice(content).await
+				}
+				Some(ConnectionManagerMessage::AdminKick {
+					user,
+					reason,
+					reply,
+				}) => {
+					_ = reply.send(self.admin_kick(&user, &reason).await);
+				}
+				Some(ConnectionManagerMessage::AdminBan {
+					target,
+					duration,
+					reason,
+					reply,
+				}) => {
+					let target = BanTarget::parse(&target);
+					self
+						.ban_target(ADMIN_NAME.into(), target, duration, &reason)
+						.await;
+					_ = reply.send(());
+				}
+				Some(ConnectionManagerMessage::ReloadConfig(config)) => {
+					self.reload_config(*config).await
+				}
+				Some(ConnectionManagerMessage::GetStats { reply }) => {
+					_ = reply.send(self.stats());
+				}
+				None => todo!(),
+			}
+		}
+	}
+
+	/// Checks who the client is, then connects them.
+	/// Logging in and registering hash the password on a blocking thread,
+	/// so the client is connected once that's done.
+	async fn add_client(
+		&mut self,
+		conn: Box<dyn NetworkConnection>,
+		mut request: ConnectRequest,
+	) {
+		// the session token shows who the client is
+		let resuming = request
+			.session_token
+			.is_some_and(|token| self.get_session(request.uuid, token).is_some());
+
+		match request.credentials.take() {
+			_ if resuming => self.connect_client(conn, request, false).await,
+			Some(credentials) if credentials.register => {
+				self.register(conn, request, credentials).await
+			}
+			Some(credentials) => self.log_in(conn, request, credentials).await,
+			None if self.account_config.required => {
+				conn
+					.send_rejected("this server requires an account".into())
+					.await
+			}
+			None if self.accounts.get(request.uuid).is_some() => {
+				conn
+					.send_rejected("log in to use this account".into())
+					.await
+			}
+			None => self.connect_client(conn, request, false).await,
+		}
+	}
+
+	/// Creates an account with a new uuid, then connects the client as it.
+	async fn register(
+		&mut self,
+		conn: Box<dyn NetworkConnection>,
+		request: ConnectRequest,
+		credentials: Credentials,
+	) {
+		if !self.account_config.registration {
+			conn.send_rejected("registration is disabled".into()).await;
+			return;
+		}
+		if let Err(reason) = self.check_username(request.uuid, &request.username) {
+			conn.send_rejected(reason).await;
+			return;
+		}
+		let min_length = self.account_config.min_password_length;
+		if credentials.password.chars().count() < min_length {
+			let reason =
+				format!("passwords must be at least {} characters", min_length);
+			conn.send_rejected(reason).await;
+			return;
+		}
+
+		let username = request.username.clone();
+		let sender = self.sender.clone();
+		tokio::spawn(async move {
+			let account = tokio::task::spawn_blocking(move || {
+				Account::new(username, &credentials.password)
+			})
+			.await;
+
+			match account {
+				Ok(Ok(account)) => {
+					_ = sender.send(ConnectionManagerMessage::Authenticated {
+						conn,
+						request,
+						account,
+						registered: true,
+					});
+				}
+				_ => {
+					conn
+						.send_rejected("failed to create the account".into())
+						.await
+				}
+			}
+		});
+	}
+
+	async fn log_in(
+		&mut self,
+		conn: Box<dyn NetworkConnection>,
+		request: ConnectRequest,
+		credentials: Credentials,
+	) {
+		let account = self.accounts.find(&request.username).cloned();
+
+		let sender = self.sender.clone();
+		tokio::spawn(async move {
+			let account = tokio::task::spawn_blocking(move || match account {
+				Some(account) => {
+					account.verify(&credentials.password).then_some(account)
+				}
+				None => {
+					account::verify_missing(&credentials.password);
+					None
+				}
+			})
+			.await;
+
+			match account {
+				Ok(Some(account)) => {
+					_ = sender.send(ConnectionManagerMessage::Authenticated {
+						conn,
+						request,
+						account,
+						registered: false,
+					});
+				}
+				_ => conn.send_rejected(WRONG_CREDENTIALS.into()).await,
+			}
+		});
+	}
+
+	/// Connects a client whose password was checked, as their account.
+	async fn authenticated(
+		&mut self,
+		conn: Box<dyn NetworkConnection>,
+		mut request: ConnectRequest,
+		account: Account,
+		registered: bool,
+	) {
+		if registered {
+			// the name may have been taken while the password was hashed
+			if let Err(reason) = self.check_username(request.uuid, &account.username)
+			{
+				conn.send_rejected(reason).await;
+				return;
+			}
+			info!(uuid:% = account.uuid; "registered {}", account.username);
+			self.accounts.insert(account.clone());
+		}
+
+		// the account may have been renamed or deleted in the meantime
+		let Some(account) = self.accounts.get(account.uuid) else {
+			conn.send_rejected(WRONG_CREDENTIALS.into()).await;
+			return;
+		};
+		info!(uuid:% = account.uuid; "logged in");
+		request.uuid = account.uuid;
+		request.username = account.username.clone();
+		self.connect_client(conn, request, true).await;
+	}
+
+	/// Connects the client, resuming its session if it has one.
+	/// Anyone can claim a uuid, so only clients that logged in to its account
+	/// can connect another session to a user that's already here.
+	async fn connect_client(
+		&mut self,
+		conn: Box<dyn NetworkConnection>,
+		request: ConnectRequest,
+		authenticated: bool,
+	) {
+		let ConnectRequest {
+			uuid,
+			mut username,
+			addr,
+			session_token,
+			capabilities,
+			..
+		} = request;
+
+		if let Some(ban) = self.bans.find(uuid, &username, addr.ip()) {
+			info!(uuid:% = uuid, addr:% = addr; "banned, rejecting");
+			conn.send_rejected(ban.describe()).await;
+			return;
+		}
+
+		let heartbeat = self.heartbeat_for(&capabilities);
+
+		if let Some(token) = session_token {
+			if self.get_session(uuid, token).is_some() {
+				self.resume_session(conn, uuid, token, heartbeat).await;
+				return;
+			}
+		}
+
+		// presence is per user, so only the first session announces a join.
+		let already_connected = self.client_map.contains_key(&uuid);
+
+		if already_connected && !authenticated {
+			info!(uuid:% = uuid, addr:% = addr; "already connected, rejecting");
+			conn
+				.send_rejected("this user is already connected".into())
+				.await;
+			return;
+		}
+
+		if !already_connected && self.is_full() {
+			warn!(uuid:% = uuid, addr:% = addr; "server full, rejecting");
+			conn.send_rejected("server is full".into()).await;
+			return;
+		}
+
+		if !already_connected {
+			if let Err(reason) = self.check_username(uuid, &username) {
+				info!(uuid:% = uuid, addr:% = addr; "rejecting: {}", reason);
+				conn.send_rejected(reason).await;
+				return;
+			}
+
+			let event = Event::ClientJoined {
+				uuid,
+				username: username.clone(),
+				addr,
+			};
+			let Some(Event::ClientJoined { username: name, .. }) =
+				self.event_bus.publish(event).await
+			else {
+				info!(uuid:% = uuid, addr:% = addr; "join vetoed, rejecting");
+				conn.send_rejected("rejected by the server".into()).await;
+				return;
+			};
+			// names rewritten by handlers follow the same rules,
+			// falling back to the name the client asked for
+			match self.check_username(uuid, &name) {
+				Ok(()) => username = name,
+				Err(reason) => {
+					warn!(uuid:% = uuid; "ignoring rewritten name: {}", reason)
+				}
+			}
+
+			info!(uuid:% = uuid, addr:% = addr; "{} connected", username);
+			let role = role_for(&self.roles, uuid, authenticated);
+			let store =
+				ClientInfo::new(uuid, username.clone(), addr, role, authenticated);
+			self.client_map.insert(uuid, store);
+		}
+
+		let token = Uuid::new_v4();
+		let thread = ClientThread::new_run(
+			uuid,
+			token,
+			false,
+			conn,
+			self.sender.clone(),
+			heartbeat,
+		)
+		.await;
+		let resumable = capabilities.iter().any(|c| c == SESSION_RESUME);
+		self.sessions.entry(uuid).or_default().insert(
+			token,
+			Session::new(token, thread, &self.session_config, resumable),
+		);
+		debug!(
+			uuid:% = uuid, session:% = logging::session(token);
+			"created client thread"
+		);
+
+		if already_connected {
+			info!(
+				uuid:% = uuid, session:% = logging::session(token);
+				"added another session"
+			);
+			return;
+		}
+
+		let Some(details) = self.client_map.get(&uuid).map(ClientInfo::details)
+		else {
+			return;
+		};
+		self.notify_peers(PeerManagerMessage::ClientJoined(details.clone()));
+		self.broadcast(SessionEvent::ClientJoined(details)).await;
+	}
+
+	/// Attaches a reconnecting client to its existing session.
+	/// Other clients are not told, as the client never left.
+	async fn resume_session(
+		&mut self,
+		conn: Box<dyn NetworkConnection>,
+		uuid: Uuid,
+		token: Uuid,
+		heartbeat: Option<HeartbeatConfig>,
+	) {
+		info!(uuid:% = uuid; "resuming session");
+
+		let thread = ClientThread::new_run(
+			uuid,
+			token,
+			true,
+			conn,
+			self.sender.clone(),
+			heartbeat,
+		)
+		.await;
+
+		if let Some(session) = self.get_session(uuid, token) {
+			session.resume(thread).await;
+		}
+	}
+
+	/// Called when a sessions connection drops.
+	/// The session is kept for the grace period so the client can resume it.
+	async fn suspend_session(&mut self, uuid: Uuid, session: Uuid) {
+		let resumable = self
+			.get_session(uuid, session)
+			.is_some_and(|s| s.is_resumable());
+
+		if self.session_config.grace_period_secs == 0 || !resumable {
+			self.remove_session(uuid, session).await;
+			return;
+		}
+
+		let config = self.session_config.clone();
+		let sender = self.sender.clone();
+		let Some(s) = self.get_session(uuid, session) else {
+			return;
+		};
+
+		info!(uuid:% = uuid; "suspending session");
+		s.suspend(uuid, &config, sender);
+	}
+
+	async fn expire_session(&mut self, uuid: Uuid, session: Uuid) {
+		let expired = self
+			.get_session(uuid, session)
+			.is_some_and(|s| s.is_suspended());
+
+		if expired {
+			info!(
+				uuid:% = uuid, session:% = logging::session(session);
+				"session expired"
+			);
+			self.remove_session(uuid, session).await;
+		}
+	}
+
+	/// Removes a single session.
+	/// If it was the users last, the user is removed as well.
+	async fn remove_session(&mut self, uuid: Uuid, session: Uuid) {
+		let Some(user_sessions) = self.sessions.get_mut(&uuid) else {
+			return;
+		};
+
+		// the reader and heartbeat can both report the same session
+		if user_sessions.remove(&session).is_none() {
+			return;
+		}
+
+		if user_sessions.is_empty() {
+			self.remove_client(uuid).await;
+		}
+	}
+
+	async fn remove_client(&mut self, uuid: Uuid) {
+		if self.client_map.remove(&uuid).is_none() {
+			return;
+		}
+		info!(uuid:% = uuid; "disconnected");
+		self.sessions.remove(&uuid);
+		self.event_bus.publish(Event::ClientLeft { uuid }).await;
+
+		self.notify_peers(PeerManagerMessage::ClientLeft(uuid));
+		self.broadcast(SessionEvent::ClientLeft(uuid)).await;
+	}
+
+	async fn remote_client_joined(&mut self, details: ClientDetails) {
+		let Ok(uuid) = details.uuid.parse() else {
+			return;
+		};
+
+		// a user connected here as well is already listed
+		if self.client_map.contains_key(&uuid) {
+			return;
+		}
+
+		info!(uuid:% = uuid; "remote client joined");
+		self.remote_clients.insert(uuid, details.clone());
+		self.broadcast(SessionEvent::ClientJoined(details)).await;
+	}
+
+	/// Shows the new details, as a rename if the name changed.
+	async fn remote_client_updated(&mut self, details: ClientDetails) {
+		let Ok(uuid) = details.uuid.parse() else {
+			return;
+		};
+		if self.client_map.contains_key(&uuid) {
+			return;
+		}
+		let Some(previous) = self.remote_clients.insert(uuid, details.clone())
+		else {
+			// not listed here yet, so it's shown as a join
+			self.broadcast(SessionEvent::ClientJoined(details)).await;
+			return;
+		};
+
+		info!(uuid:% = uuid; "remote client updated");
+		if previous.name != details.name {
+			self
+				.broadcast(SessionEvent::ClientRenamed {
+					uuid,
+					username: details.name.clone(),
+				})
+				.await;
+		}
+		if previous.role != details.role {
+			self.broadcast(SessionEvent::ClientJoined(details)).await;
+		}
+	}
+
+	async fn remote_client_left(&mut self, uuid: Uuid) {
+		if self.remote_clients.remove(&uuid).is_none() {
+			return;
+		}
+
+		info!(uuid:% = uuid; "remote client left");
+		self.broadcast(SessionEvent::ClientLeft(uuid)).await;
+	}
+
+	async fn remote_global_message(&mut self, message: GlobalMessage) {
+		_ = self
+			.server_sender
+			.send(ServerMessages::AddGlobalMessage(message.clone()));
+		self.broadcast(SessionEvent::GlobalMessage(message)).await;
+	}
+
+	/// Delivers a message from another server, if the recipient is here.
+	async fn remote_private_message(&mut self, message: PrivateMessage) {
+		let (Ok(from), Ok(to), Ok(uuid)) = (
+			message.from.parse(),
+			message.to.parse(),
+			message.uuid.parse(),
+		) else {
+			return;
+		};
+
+		let Some(sessions) = self.sessions.get_mut(&to) else {
+			return;
+		};
+
+		let event = SessionEvent::PrivateMessage {
+			from,
+			to,
+			uuid,
+			content: message.content,
+		};
+		for s in sessions.values_mut() {
+			s.send(event.clone()).await;
+		}
+	}
+
+	async fn bot_global_message(&mut self, bot: ClientDetails, content: String) {
+		let Some(uuid) = self.add_bot(bot).await else {
+			return;
+		};
+		self.publish_global_message(uuid, content).await;
+	}
+
+	async fn bot_private_message(
+		&mut self,
+		bot: ClientDetails,
+		to: Uuid,
+		content: String,
+	) {
+		let Some(uuid) = self.add_bot(bot).await else {
+			return;
+		};
+		self
+			.send_private_message(to, uuid, Uuid::nil(), Uuid::new_v4(), content)
+			.await;
+	}
+
+	/// Lists the bot as a client the first time it speaks,
+	/// so clients can show who its messages are from.
+	/// Plugin bots are named by the plugin, so follow the same rules as users.
+	async fn add_bot(&mut self, bot: ClientDetails) -> Option<Uuid> {
+		let uuid = bot.uuid.parse().ok()?;
+		if self.bots.contains_key(&uuid) {
+			return Some(uuid);
+		}
+
+		// the server speaks under its own name, which is reserved for it
+		if uuid != SYSTEM_UUID {
+			if let Err(reason) = self.check_username(uuid, &bot.name) {
+				warn!(uuid:% = uuid; "not adding bot {}: {}", bot.name, reason);
+				return None;
+			}
+		}
+
+		info!(uuid:% = uuid; "adding bot {}", bot.name);
+		self.bots.insert(uuid, bot.clone());
+		self.broadcast(SessionEvent::ClientJoined(bot)).await;
+		Some(uuid)
+	}
+
+	/// Disconnects every session of a user, on behalf of the server.
+	async fn disconnect_user(&mut self, uuid: Uuid, reason: String) {
+		let Some(sessions) = self.sessions.get_mut(&uuid) else {
+			return;
+		};
+
+		for session in sessions.values_mut() {
+			if let Some(t) = session.get_thread() {
+				t.send_disconnected(reason.clone()).await;
+			}
+		}
+		self.remove_client(uuid).await;
+	}
+
+	fn register_command(
+		&mut self,
+		plugin_id: String,
+		name: String,
+		description: String,
+	) {
+		let registered = self.commands.register_plugin_command(
+			plugin_id.clone(),
+			name.clone(),
+			description,
+		);
+
+		match registered {
+			Ok(()) => {
+				info!(plugin = plugin_id.as_str(); "registered /{}", name)
+			}
+			Err(e) => {
+				warn!(
+					plugin = plugin_id.as_str();
+					"can't register command: {}", e
+				)
+			}
+		}
+	}
+
+	/// Removes the plugins commands, and its bot if it had spoken.
+	async fn plugin_unloaded(&mut self, plugin_id: String, bot: ClientDetails) {
+		self.commands.remove_plugin_commands(&plugin_id);
+
+		let Ok(uuid) = bot.uuid.parse() else {
+			return;
+		};
+		if self.bots.remove(&uuid).is_some() {
+			info!(uuid:% = uuid; "removing bot {}", bot.name);
+			self.broadcast(SessionEvent::ClientLeft(uuid)).await;
+		}
+	}
+
+	/// Runs a command typed by a user.
+	/// Commands answer privately, rather than being broadcast.
+	async fn run_command(&mut self, from: Uuid, name: &str, args: &str) {
+		let Some(command) = self.commands.get(name) else {
+			let reply = format!("unknown command /{}, send /help to list them", name);
+			self.reply(from, reply).await;
+			return;
+		};
+		if !self.commands.allows(command, self.role_of(from)) {
+			self.reply(from, format!("you can't run /{}", name)).await;
+			return;
+		}
+
+		debug!(uuid:% = from; "ran /{}", name);
+		match command.handler.clone() {
+			CommandHandler::Me => self.me(from, args).await,
+			CommandHandler::Nick => self.nick(from, args).await,
+			CommandHandler::Msg => self.msg(from, args).await,
+			CommandHandler::Who => self.who(from).await,
+			CommandHandler::Help => self.help(from, args).await,
+			CommandHandler::Kick => self.kick(from, args).await,
+			CommandHandler::Ban => self.ban(from, args).await,
+			CommandHandler::Unban => self.unban(from, args).await,
+			CommandHandler::Mute => self.mute(from, args).await,
+			CommandHandler::Unmute => self.unmute(from, args).await,
+			CommandHandler::Role => self.role(from, args).await,
+			CommandHandler::Password => self.password(from, args).await,
+			CommandHandler::Unregister => self.unregister(from, args).await,
+			CommandHandler::Plugin(plugin_id) => {
+				self.run_plugin_command(plugin_id, from, name, args)
+			}
+		}
+	}
+
+	async fn me(&mut self, from: Uuid, action: &str) {
+		let Some(client) = self.client_map.get(&from) else {
+			return;
+		};
+		if action.is_empty() {
+			self.reply_usage(from, "me").await;
+			return;
+		}
+		if self.mutes.is_muted(from) {
+			self.reply(from, "you are muted".into()).await;
+			return;
+		}
+
+		let content = format!("* {} {}", client.get_username(), action);
+		self.publish_global_message(from, content).await;
+	}
+
+	/// Renames the user, and tells everyone their new name.
+	async fn nick(&mut self, from: Uuid, name: &str) {
+		if name.is_empty() {
+			self.reply_usage(from, "nick").await;
+			return;
+		}
+		if let Err(reason) = self.check_username(from, name) {
+			self.reply(from, reason).await;
+			return;
+		}
+		let Some(client) = self.client_map.get_mut(&from) else {
+			return;
+		};
+
+		info!(uuid:% = from; "now known as {}", name);
+		client.set_username(name.to_string());
+		let details = client.details();
+		self.accounts.rename(from, name.to_string());
+
+		self.notify_peers(PeerManagerMessage::ClientUpdated(details));
+		self
+			.broadcast(SessionEvent::ClientRenamed {
+				uuid: from,
+				username: name.to_string(),
+			})
+			.await;
+		self
+			.reply(from, format!("you are now known as {}", name))
+			.await;
+	}
+
+	/// Sends a private message to a user by name or uuid.
+	async fn msg(&mut self, from: Uuid, args: &str) {
+		let Some((user, content)) = args.split_once(' ') else {
+			self.reply_usage(from, "msg").await;
+			return;
+		};
+
+		let Some(to) = self.find_client(user) else {
+			self
+				.reply(from, format!("nobody called {} is online", user))
+				.await;
+			return;
+		};
+
+		// the senders sessions all see what they sent
+		self
+			.send_private_message(
+				to,
+				from,
+				Uuid::nil(),
+				Uuid::new_v4(),
+				content.trim().to_string(),
+			)
+			.await;
+	}
+
+	async fn who(&mut self, from: Uuid) {
+		let mut names: Vec<_> = self
+			.client_list()
+			.into_iter()
+			.map(|c| match c.role.as_str() {
+				"" => c.name,
+				role => format!("{} ({})", c.name, role),
+			})
+			.collect();
+		names.sort();
+
+		let reply = format!("{} online: {}", names.len(), names.join(", "));
+		self.reply(from, reply).await;
+	}
+
+	/// Lists the commands the user can run, or describes one of them.
+	async fn help(&mut self, from: Uuid, name: &str) {
+		let name = name.trim_start_matches('/');
+		let reply = if name.is_empty() {
+			self
+				.commands
+				.available(self.role_of(from))
+				.map(|c| format!("{} - {}", c.signature(), c.description))
+				.collect::<Vec<_>>()
+				.join("\n")
+		} else {
+			match self.commands.get(name) {
+				Some(c) if self.commands.allows(c, self.role_of(from)) => {
+					format!("{} - {}", c.signature(), c.description)
+				}
+				_ => format!("unknown command /{}", name),
+			}
+		};
+
+		self.reply(from, reply).await;
+	}
+
+	async fn kick(&mut self, from: Uuid, args: &str) {
+		let (user, reason) = command::split_word(args);
+		if user.is_empty() {
+			self.reply_usage(from, "kick").await;
+			return;
+		}
+		let Some(uuid) = self.find_local_client(user) else {
+			self
+				.reply(from, format!("nobody called {} is online", user))
+				.await;
+			return;
+		};
+		if !self.outranks(from, uuid) {
+			self.reply(from, format!("you can't kick {}", user)).await;
+			return;
+		}
+
+		let moderator = self.moderator_name(from);
+		self.kick_user(&moderator, uuid, reason).await;
+		self.reply(from, format!("kicked {}", user)).await;
+	}
+
+	async fn kick_user(&mut self, moderator: &str, uuid: Uuid, reason: &str) {
+		self.audit_log.record(
+			moderator,
+			AuditAction::Kick,
+			&uuid.to_string(),
+			None,
+			reason,
+		);
+		self
+			.disconnect_user(uuid, with_reason("kicked", reason))
+			.await;
+	}
+
+	/// Bans a user, username or ip, and disconnects anyone online it matches.
+	async fn ban(&mut self, from: Uuid, args: &str) {
+		let (target, rest) = command::split_word(args);
+		if target.is_empty() {
+			self.reply_usage(from, "ban").await;
+			return;
+		}
+		let (duration, reason) = split_duration(rest);
+		let target = BanTarget::parse(target);
+
+		if !self
+			.banned_by(&target)
+			.iter()
+			.all(|uuid| self.outranks(from, *uuid))
+		{
+			self.reply(from, format!("you can't ban {}", target)).await;
+			return;
+		}
+
+		let moderator = self.moderator_name(from);
+		self
+			.ban_target(moderator, target.clone(), duration, reason)
+			.await;
+
+		let reply = match duration {
+			Some(duration) => {
+				format!("banned {} for {}", target, format_duration(duration))
+			}
+			None => format!("banned {}", target),
+		};
+		self.reply(from, reply).await;
+	}
+
+	/// Adds the ban, disconnecting anyone online it matches.
+	async fn ban_target(
+		&mut self,
+		moderator: String,
+		target: BanTarget,
+		duration: Option<Duration>,
+		reason: &str,
+	) {
+		self.audit_log.record(
+			&moderator,
+			AuditAction::Ban,
+			&target.to_string(),
+			duration,
+			reason,
+		);
+		let banned = self.banned_by(&target);
+		let ban = Ban::new(target, reason.into(), moderator, duration);
+		let description = ban.describe();
+		self.bans.add(ban);
+
+		for uuid in banned {
+			self.disconnect_user(uuid, description.clone()).await;
+		}
+	}
+
+	/// The clients connected here the ban would apply to.
+	fn banned_by(&self, target: &BanTarget) -> Vec<Uuid> {
+		self
+			.client_map
+			.values()
+			.filter(|c| {
+				target.matches(c.get_uuid(), &c.get_username(), c.get_addr().ip())
+			})
+			.map(|c| c.get_uuid())
+			.collect()
+	}
+
+	async fn unban(&mut self, from: Uuid, args: &str) {
+		if args.is_empty() {
+			self.reply_usage(from, "unban").await;
+			return;
+		}
+		let target = BanTarget::parse(args);
+		if !self.bans.remove(&target) {
+			self.reply(from, format!("{} isn't banned", target)).await;
+			return;
+		}
+
+		let moderator = self.moderator_name(from);
+		self.audit_log.record(
+			&moderator,
+			AuditAction::Unban,
+			&target.to_string(),
+			None,
+			"",
+		);
+		self.reply(from, format!("unbanned {}", target)).await;
+	}
+
+	async fn mute(&mut self, from: Uuid, args: &str) {
+		let (user, rest) = command::split_word(args);
+		if user.is_empty() {
+			self.reply_usage(from, "mute").await;
+			return;
+		}
+		let Some(uuid) = self.find_local_client(user) else {
+			self
+				.reply(from, format!("nobody called {} is online", user))
+				.await;
+			return;
+		};
+		if !self.outranks(from, uuid) {
+			self.reply(from, format!("you can't mute {}", user)).await;
+			return;
+		}
+		let (duration, reason) = split_duration(rest);
 
-use crate::{
-	connection::{client_info::ClientInfo, client_thread::ClientThread},
-	network::NetworkConnection,
-	server_va::ServerMessages,
-};
+		let moderator = self.moderator_name(from);
+		self.audit_log.record(
+			&moderator,
+			AuditAction::Mute,
+			&uuid.to_string(),
+			duration,
+			reason,
+		);
+		self.mutes.mute(uuid, duration);
 
-pub struct ConnectionManager {
-	receiver: Mutex<UnboundedReceiver<ConnectionManagerMessage>>,
-	sender: UnboundedSender<ConnectionManagerMessage>,
-	server_sender: UnboundedSender<ServerMessages>,
-	client_map: HashMap<Uuid, ClientInfo>,
-	client_tasks_map: HashMap<Uuid, ClientThread>,
-}
+		let muted = match duration {
+			Some(duration) => format!("muted for {}", format_duration(duration)),
+			None => "muted".to_string(),
+		};
+		self
+			.reply(
+				uuid,
+				with_reason(&format!("you have been {}", muted), reason),
+			)
+			.await;
+		self.reply(from, format!("{} {}", user, muted)).await;
+	}
 
-impl ConnectionManager {
-	pub fn new(server_sender: UnboundedSender<ServerMessages>) -> Self {
-		let (tx, rx) = unbounded_channel();
-		Self {
-			client_map: HashMap::new(),
-			client_tasks_map: HashMap::new(),
-			server_sender,
-			receiver: Mutex::new(rx),
-			sender: tx,
+	async fn unmute(&mut self, from: Uuid, args: &str) {
+		if args.is_empty() {
+			self.reply_usage(from, "unmute").await;
+			return;
+		}
+		let Some(uuid) = self.find_local_client(args) else {
+			self
+				.reply(from, format!("nobody called {} is online", args))
+				.await;
+			return;
+		};
+		if !self.mutes.unmute(uuid) {
+			self.reply(from, format!("{} isn't muted", args)).await;
+			return;
 		}
+
+		let moderator = self.moderator_name(from);
+		self.audit_log.record(
+			&moderator,
+			AuditAction::Unmute,
+			&uuid.to_string(),
+			None,
+			"",
+		);
+		self.reply(uuid, "you have been unmuted".into()).await;
+		self.reply(from, format!("unmuted {}", args)).await;
 	}
 
-	pub async fn run(&mut self) {
-		loop {
-			let mut lock = self.receiver.lock().await;
-			let msg = lock.recv().await;
-			drop(lock);
+	/// Shows a users role, or changes it.
+	/// Users can only change the roles of those below them,
+	/// and only to roles below their own.
+	async fn role(&mut self, from: Uuid, args: &str) {
+		let (user, role) = command::split_word(args);
+		if user.is_empty() {
+			self.reply_usage(from, "role").await;
+			return;
+		}
+		let Some(uuid) = user.parse().ok().or_else(|| self.find_local_client(user))
+		else {
+			self
+				.reply(from, format!("nobody called {} is online", user))
+				.await;
+			return;
+		};
 
-			match msg {
-				Some(ConnectionManagerMessage::AddClient {
-					conn,
-					uuid,
-					username,
-					addr,
-				}) => self.add_client(conn, uuid, username, addr).await,
+		if role.is_empty() {
+			let reply = format!("{} is {}", user, self.role_of(uuid));
+			self.reply(from, reply).await;
+			return;
+		}
+		let role = match role.parse::<Role>() {
+			Ok(role) => role,
+			Err(e) => {
+				self.reply(from, e).await;
+				return;
+			}
+		};
 
-				Some(ConnectionManagerMessage::Disconnected { uuid }) => {
-					self.remove_client(uuid).await
-				}
-				Some(ConnectionManagerMessage::BroadcastGlobalMessage {
-					from,
-					content,
-				}) => {
-					self.broadcast_global_message(from, content).await;
-				}
-				Some(ConnectionManagerMessage::SendClientsTo { uuid }) => {
-					self.send_clients_to(uuid).await;
-				}
-				Some(ConnectionManagerMessage::SendGlobalMessages { uuid }) => {
-					self.send_global_messages(uuid).await;
-				}
+		let own = self.role_of(from);
+		if !own.outranks(self.role_of(uuid)) || !own.outranks(role) {
+			self
+				.reply(from, format!("you can't make {} {}", user, role))
+				.await;
+			return;
+		}
+		if let Err(e) = self.roles.set(uuid, role) {
+			self.reply(from, e).await;
+			return;
+		}
 
-				Some(ConnectionManagerMessage::SendGlobalMessagesTo {
-					uuid,
-					messages,
-				}) => {
-					self.send_global_messages_to(uuid, messages).await;
-				}
+		let moderator = self.moderator_name(from);
+		self.audit_log.record(
+			&moderator,
+			AuditAction::SetRole,
+			&uuid.to_string(),
+			None,
+			&role.to_string(),
+		);
 
-				Some(ConnectionManagerMessage::SendPrivateMessage {
-					uuid,
-					from,
-					to,
-					content,
-				}) => {
-					self.send_private_message(to, from, uuid, content).await;
-				}
-				Some(ConnectionManagerMessage::Disconnect { uuid }) => {
-					self.disconnect(uuid).await
-				}
-				None => todo!(),
+		let mut reply = format!("{} is now {}", user, role);
+		if let Some(client) = self.client_map.get_mut(&uuid) {
+			let live = role_for(&self.roles, uuid, client.is_authenticated());
+			if live != role {
+				reply = format!("{}, once they log in to an account", reply);
 			}
+			client.set_role(live);
+			let details = client.details();
+			self.notify_peers(PeerManagerMessage::ClientUpdated(details.clone()));
+			self.broadcast(SessionEvent::ClientJoined(details)).await;
+			self.reply(uuid, format!("you are now {}", live)).await;
 		}
+		self.reply(from, reply).await;
 	}
 
-	async fn add_client(
+	/// Kicks a user on behalf of an admin, who may kick anyone.
+	async fn admin_kick(
 		&mut self,
-		conn: Box<dyn NetworkConnection>,
-		uuid: Uuid,
-		username: String,
-		addr: SocketAddr,
-	) {
-		println!("[ConnectionManager] adding new client");
-		let store = ClientInfo::new(uuid, username.clone(), addr);
-		self.client_map.insert(uuid, store);
-		println!("[ConnectionManager] added client info to map");
-
-		let thread = ClientThread::new_run(uuid, conn, self.sender.clone()).await;
-		self.client_tasks_map.insert(uuid, thread);
-		println!("[ConnectionManager] created running thread for new clinet");
-
-		for c in self.client_tasks_map.iter_mut() {
-			c.1
-				.send_client_joined(ClientDetails {
-					uuid: uuid.to_string(),
-					name: username.clone(),
-					address: addr.to_string(),
-				})
-				.await;
-		}
+		user: &str,
+		reason: &str,
+	) -> Result<(), String> {
+		let Some(uuid) = self.find_local_client(user) else {
+			return Err(format!("nobody called {} is online", user));
+		};
+		self.kick_user(ADMIN_NAME, uuid, reason).await;
+		Ok(())
 	}
 
-	async fn remove_client(&mut self, uuid: Uuid) {
-		println!("[ConnectionManager] removing {}", uuid);
-		self.client_map.remove(&uuid);
-		self.client_tasks_map.remove(&uuid);
+	/// Sends a global message from the server itself.
+	async fn notice(&mut self, content: String) {
+		info!(content:% = logging::body(&content); "sending notice");
+		let system = self.system_details();
+		self.bot_global_message(system, content).await;
+	}
+
+	/// Applies the parts of a reloaded config that can change while running.
+	/// Listeners, federation and plugins keep their settings until a restart.
+	/// Files that fail to load are kept as they were.
+	async fn reload_config(&mut self, config: ServerConfig) {
+		info!("reloading config");
+		self.commands.configure(config.commands);
+		match BanList::load(&config.moderation.bans_file) {
+			Ok(bans) => self.bans = bans,
+			Err(e) => error!("keeping the current bans: {}", e),
+		}
+		self.audit_log = AuditLog::new(&config.moderation.audit_log);
+		match RoleStore::load(&config.roles) {
+			Ok(roles) => self.roles = roles,
+			Err(e) => error!("keeping the current roles: {}", e),
+		}
+		match AccountStore::load(&config.accounts.file) {
+			Ok(accounts) => self.accounts = accounts,
+			Err(e) => error!("keeping the current accounts: {}", e),
+		}
+		self.account_config = config.accounts;
+		self.info = config.info;
+		self.heartbeat = config.heartbeat;
+		self.session_config = config.session;
+		self.usernames = config.usernames;
 
-		for c in self.client_tasks_map.iter_mut() {
-			c.1.send_client_left(uuid).await;
+		let mut changed = Vec::new();
+		for client in self.client_map.values_mut() {
+			let role =
+				role_for(&self.roles, client.get_uuid(), client.is_authenticated());
+			if client.get_role() != role {
+				client.set_role(role);
+				changed.push(client.details());
+			}
+		}
+		for details in changed {
+			self.notify_peers(PeerManagerMessage::ClientUpdated(details.clone()));
+			self.broadcast(SessionEvent::ClientJoined(details)).await;
 		}
 	}
 
-	async fn send_clients_to(&mut self, uuid: Uuid) {
-		let clients = self
+	/// The clients connected here, as shown to admins.
+	fn client_statuses(&self) -> Vec<ClientStatus> {
+		self
 			.client_map
 			.values()
-			.cloned()
-			.map(|c| foundation::prelude::ClientDetails {
+			.map(|c| ClientStatus {
 				uuid: c.get_uuid().to_string(),
 				name: c.get_username(),
 				address: c.get_addr().to_string(),
+				role: c.get_role().to_string(),
+				connected_at: c.get_connected_at(),
+				sessions: self.sessions.get(&c.get_uuid()).map_or(0, HashMap::len),
 			})
-			.collect();
+			.collect()
+	}
+
+	/// Fills in what the connection manager knows,
+	/// the server adds the rest.
+	fn stats(&self) -> ServerStats {
+		ServerStats {
+			clients: self.client_map.len(),
+			sessions: self.sessions.values().map(HashMap::len).sum(),
+			remote_clients: self
+				.remote_clients
+				.keys()
+				.filter(|uuid| !self.client_map.contains_key(uuid))
+				.count(),
+			bots: self.bots.len(),
+			bans: self.bans.count(),
+			accounts: self.accounts.count(),
+			..Default::default()
+		}
+	}
+
+	/// Changes the password of the users account, once the current one is checked.
+	async fn password(&mut self, from: Uuid, args: &str) {
+		let (current, new) = command::split_word(args);
+		if current.is_empty() || new.is_empty() {
+			self.reply_usage(from, "password").await;
+			return;
+		}
+		let min_length = self.account_config.min_password_length;
+		if new.chars().count() < min_length {
+			let reply =
+				format!("passwords must be at least {} characters", min_length);
+			self.reply(from, reply).await;
+			return;
+		}
+
+		let current = Zeroizing::new(current.to_string());
+		let new = Zeroizing::new(new.to_string());
+		self
+			.check_password(from, current, move |mut account| {
+				account.set_password(&new)?;
+				Ok(AccountChange::SetPassword(account))
+			})
+			.await;
+	}
+
+	async fn unregister(&mut self, from: Uuid, args: &str) {
+		if args.is_empty() {
+			self.reply_usage(from, "unregister").await;
+			return;
+		}
+
+		let password = Zeroizing::new(args.to_string());
+		self
+			.check_password(from, password, |_| Ok(AccountChange::Delete))
+			.await;
+	}
+
+	/// Checks the password of the users account on a blocking thread,
+	/// then makes the change once it's done.
+	async fn check_password<F>(
+		&mut self,
+		uuid: Uuid,
+		password: Zeroizing<String>,
+		change: F,
+	) where
+		F: FnOnce(Account) -> Result<AccountChange, String> + Send + 'static,
+	{
+		let Some(account) = self.accounts.get(uuid).cloned() else {
+			self
+				.reply(uuid, "you aren't logged in to an account".into())
+				.await;
+			return;
+		};
+
+		let sender = self.sender.clone();
+		tokio::spawn(async move {
+			let change = tokio::task::spawn_blocking(move || {
+				if !account.verify(&password) {
+					return Err("wrong password".to_string());
+				}
+				change(account)
+			})
+			.await
+			.unwrap_or_else(|e| Err(e.to_string()));
+
+			_ =
+				sender.send(ConnectionManagerMessage::AccountChecked { uuid, change });
+		});
+	}
+
+	async fn account_checked(
+		&mut self,
+		uuid: Uuid,
+		change: Result<AccountChange, String>,
+	) {
+		let reply = match change {
+			Ok(_) if self.accounts.get(uuid).is_none() => {
+				"your account was deleted".to_string()
+			}
+			Ok(AccountChange::SetPassword(account)) => {
+				self.accounts.insert(account);
+				"your password has been changed".to_string()
+			}
+			Ok(AccountChange::Delete) => {
+				info!(uuid:% = uuid; "deleting account");
+				self.accounts.remove(uuid);
+				"your account has been deleted".to_string()
+			}
+			Err(e) => e,
+		};
+		self.reply(uuid, reply).await;
+	}
+
+	/// Passes the command to the plugin that registered it.
+	fn run_plugin_command(
+		&self,
+		plugin_id: String,
+		from: Uuid,
+		name: &str,
+		args: &str,
+	) {
+		_ = self.server_sender.send(ServerMessages::RunPluginCommand {
+			plugin_id,
+			command: CommandInvocation {
+				name: name.to_string(),
+				args: args.to_string(),
+				from,
+			},
+		});
+	}
+
+	async fn reply_usage(&mut self, to: Uuid, name: &str) {
+		let Some(command) = self.commands.get(name) else {
+			return;
+		};
+		let reply = format!("usage: {}", command.signature());
+		self.reply(to, reply).await;
+	}
+
+	/// Answers a user privately, from the server.
+	async fn reply(&mut self, to: Uuid, content: String) {
+		let system = self.system_details();
+		self.bot_private_message(system, to, content).await;
+	}
+
+	/// The server, as a bot sending replies and notices.
+	fn system_details(&self) -> ClientDetails {
+		ClientDetails {
+			uuid: SYSTEM_UUID.to_string(),
+			name: self.info.name.clone(),
+			address: "server".into(),
+			role: String::new(),
+		}
+	}
+
+	/// Finds a client connected to this server by uuid, or by name ignoring case.
+	fn find_local_client(&self, user: &str) -> Option<Uuid> {
+		self
+			.client_map
+			.values()
+			.find(|c| {
+				c.get_uuid().to_string() == user
+					|| c.get_username().eq_ignore_ascii_case(user)
+			})
+			.map(|c| c.get_uuid())
+	}
+
+	/// Checks the user may take the name,
+	/// by the configs rules and, if names are unique, that no one else has it.
+	fn check_username(&self, uuid: Uuid, name: &str) -> Result<(), String> {
+		username::validate(name, &self.info.name, &self.usernames)?;
+
+		let taken = self.usernames.unique
+			&& self
+				.client_list()
+				.iter()
+				.filter(|c| c.uuid != uuid.to_string())
+				.any(|c| c.name.eq_ignore_ascii_case(name));
+		// an accounts name is kept for it, even while it's offline
+		let owned = self.accounts.find(name).is_some_and(|a| a.uuid != uuid);
+		if taken || owned {
+			return Err(format!("the username {} is taken", name));
+		}
+		Ok(())
+	}
+
+	/// Users not connected here have the role they'd be given if they were.
+	fn role_of(&self, uuid: Uuid) -> Role {
+		match self.client_map.get(&uuid) {
+			Some(client) => client.get_role(),
+			None => self.roles.get(uuid),
+		}
+	}
+
+	/// Whether the user may act against the other, by their roles.
+	fn outranks(&self, uuid: Uuid, other: Uuid) -> bool {
+		self.role_of(uuid).outranks(self.role_of(other))
+	}
+
+	/// Checks the user has the permission, telling them if they don't.
+	async fn check(&mut self, uuid: Uuid, permission: Permission) -> bool {
+		if self.role_of(uuid).has(permission) {
+			return true;
+		}
+		self.reply(uuid, format!("you can't {}", permission)).await;
+		false
+	}
+
+	/// How a moderator is named in the audit log.
+	fn moderator_name(&self, uuid: Uuid) -> String {
+		match self.client_map.get(&uuid) {
+			Some(client) => format!("{} ({})", client.get_username(), uuid),
+			None => uuid.to_string(),
+		}
+	}
+
+	/// Finds a client by uuid, or by name ignoring case.
+	fn find_client(&self, user: &str) -> Option<Uuid> {
+		if let Ok(uuid) = user.parse() {
+			return Some(uuid);
+		}
+
+		self
+			.client_list()
+			.into_iter()
+			.find(|c| c.name.eq_ignore_ascii_case(user))
+			.and_then(|c| c.uuid.parse().ok())
+	}
 
-		let t = self.client_tasks_map.get_mut(&uuid);
+	fn notify_peers(&self, message: PeerManagerMessage) {
+		if let Some(peer_sender) = &self.peer_sender {
+			_ = peer_sender.send(message);
+		}
+	}
+
+	/// Sends an event to every session, queueing it for suspended ones.
+	async fn broadcast(&mut self, event: SessionEvent) {
+		let started = Instant::now();
+		for session in self.sessions.values_mut().flat_map(HashMap::values_mut) {
+			session.send(event.clone()).await;
+		}
+		METRICS.broadcast_latency.observe(started.elapsed());
+	}
+
+	/// Describes the server from its config and current state.
+	fn get_info(&self) -> Info {
+		Info {
+			server_name: self.info.name.clone(),
+			owner: self.info.owner.clone(),
+			description: self.info.description.clone(),
+			server_version: env!("CARGO_PKG_VERSION").into(),
+			connected_users: self.client_map.len() as u32,
+			max_users: self.info.max_users,
+			protocols: PROTOCOLS.iter().map(|p| p.to_string()).collect(),
+			capabilities: handshake::capabilities(),
+			min_version: MIN_PROTOCOL_VERSION,
+			max_version: PROTOCOL_VERSION,
+			authentication_required: self.account_config.required,
+		}
+	}
+
+	fn is_full(&self) -> bool {
+		self.info.max_users != 0
+			&& self.client_map.len() >= self.info.max_users as usize
+	}
+
+	/// Clients are only pinged if they said they can answer,
+	/// and the heartbeat isn't disabled.
+	fn heartbeat_for(&self, capabilities: &[String]) -> Option<HeartbeatConfig> {
+		let answers = capabilities.iter().any(|c| c == HEARTBEAT);
+		(answers && self.heartbeat.enabled()).then(|| self.heartbeat.clone())
+	}
+
+	fn get_session(&mut self, uuid: Uuid, session: Uuid) -> Option<&mut Session> {
+		self.sessions.get_mut(&uuid)?.get_mut(&session)
+	}
+
+	fn get_thread(
+		&mut self,
+		uuid: Uuid,
+		session: Uuid,
+	) -> Option<&mut ClientThread> {
+		self.get_session(uuid, session)?.get_thread()
+	}
+
+	/// Every client known to the server, including remote clients and bots.
+	fn client_list(&self) -> Vec<ClientDetails> {
+		self
+			.client_map
+			.values()
+			.map(ClientInfo::details)
+			.chain(
+				self
+					.remote_clients
+					.iter()
+					.filter(|(uuid, _)| !self.client_map.contains_key(uuid))
+					.map(|(_, details)| details.clone()),
+			)
+			.chain(self.bots.values().cloned())
+			.collect()
+	}
+
+	async fn send_clients_to(&mut self, uuid: Uuid, session: Uuid) {
+		let clients = self.client_list();
+
+		let t = self.get_thread(uuid, session);
 		let Some(t) = t else {
 			return;
 		};
 
-		println!("[ConnectionManager] sending client list to {:?}", clients);
+		debug!(
+			uuid:% = uuid, session:% = logging::session(session);
+			"sending {} clients", clients.len()
+		);
 
 		t.send_clients(clients).await;
 	}
 
+	/// Runs the message as a command if it is one,
+	/// otherwise sends it to everyone.
 	async fn broadcast_global_message(&mut self, from: Uuid, content: String) {
+		// commands are run before anything is published or logged,
+		// so the passwords given to /password and /unregister never reach
+		// the history, plugins or peers
+		if let Some((name, args)) = command::parse(&content) {
+			METRICS.messages_received.inc("command");
+			self.run_command(from, name, args).await;
+			return;
+		}
+		METRICS.messages_received.inc("global_message");
+		if !self.check(from, Permission::PostGlobal).await {
+			return;
+		}
+		if self.mutes.is_muted(from) {
+			self.reply(from, "you are muted".into()).await;
+			return;
+		}
+
+		self
+			.publish_global_message(from, command::unescape(content))
+			.await;
+	}
+
+	async fn publish_global_message(&mut self, from: Uuid, content: String) {
+		let event = Event::GlobalMessage { from, content };
+		let Some(Event::GlobalMessage { content, .. }) =
+			self.event_bus.publish(event).await
+		else {
+			return;
+		};
+
 		let message = GlobalMessage {
 			uuid: Uuid::new_v4().to_string(),
 			from: from.to_string(),
@@ -154,23 +1676,23 @@ impl ConnectionManager {
 		_ = self
 			.server_sender
 			.send(ServerMessages::AddGlobalMessage(message.clone()));
-		for c in self.client_tasks_map.iter_mut() {
-			c.1.send_global_message(message.clone()).await;
-		}
+		self.notify_peers(PeerManagerMessage::GlobalMessage(message.clone()));
+		self.broadcast(SessionEvent::GlobalMessage(message)).await;
 	}
 
-	async fn send_global_messages(&mut self, uuid: Uuid) {
+	async fn send_global_messages(&mut self, uuid: Uuid, session: Uuid) {
 		_ = self
 			.server_sender
-			.send(ServerMessages::SendGlobalMessages(uuid));
+			.send(ServerMessages::SendGlobalMessages { uuid, session });
 	}
 
 	async fn send_global_messages_to(
 		&mut self,
 		uuid: Uuid,
+		session: Uuid,
 		messages: Vec<GlobalMessage>,
 	) {
-		let t = self.client_tasks_map.get_mut(&uuid);
+		let t = self.get_thread(uuid, session);
 		let Some(t) = t else {
 			return;
 		};
@@ -178,28 +1700,73 @@ impl ConnectionManager {
 		t.send_global_messages(messages).await;
 	}
 
+	/// Sends the message to every session of the recipient,
+	/// and echoes it to the senders other sessions.
 	async fn send_private_message(
 		&mut self,
 		to: Uuid,
 		from: Uuid,
+		session: Uuid,
 		uuid: Uuid,
 		content: String,
 	) {
-		let t = self.client_tasks_map.get_mut(&to);
-		let Some(t) = t else {
+		let event = Event::PrivateMessage { from, to, content };
+		let Some(Event::PrivateMessage { content, .. }) =
+			self.event_bus.publish(event).await
+		else {
 			return;
 		};
 
-		t.send_private_message(from, uuid, content).await
+		// users on other servers get it through the mesh
+		if !self.sessions.contains_key(&to) && self.remote_clients.contains_key(&to)
+		{
+			self.notify_peers(PeerManagerMessage::PrivateMessage(PrivateMessage {
+				uuid: uuid.to_string(),
+				from: from.to_string(),
+				to: to.to_string(),
+				content: content.clone(),
+			}));
+		}
+
+		let event = SessionEvent::PrivateMessage {
+			from,
+			to,
+			uuid,
+			content,
+		};
+
+		if let Some(sessions) = self.sessions.get_mut(&to) {
+			for s in sessions.values_mut() {
+				s.send(event.clone()).await;
+			}
+		}
+
+		if to == from {
+			return;
+		}
+
+		if let Some(sessions) = self.sessions.get_mut(&from) {
+			for (_, s) in sessions.iter_mut().filter(|(t, _)| **t != session) {
+				s.send(event.clone()).await;
+			}
+		}
 	}
 
-	async fn disconnect(&mut self, uuid: Uuid) {
-		let t = self.client_tasks_map.get_mut(&uuid);
+	/// The client asked to leave, so its session isn't kept.
+	async fn disconnect(&mut self, uuid: Uuid, session: Uuid) {
+		let t = self.get_thread(uuid, session);
 		let Some(t) = t else {
 			return;
 		};
 
-		t.send_disconnected().await;
+		t.send_disconnected("disconnected".into()).await;
+		self.remove_session(uuid, session).await;
+	}
+
+	fn received_pong(&mut self, uuid: Uuid, session: Uuid) {
+		if let Some(t) = self.get_thread(uuid, session) {
+			t.received_pong();
+		}
 	}
 
 	pub fn get_sender(&self) -> UnboundedSender<ConnectionManagerMessage> {
@@ -207,26 +1774,78 @@ impl ConnectionManager {
 	}
 }
 
+/// The role a client is given.
+/// Anyone can claim a uuid, so roles above member
+/// are only given to clients logged in to its account.
+fn role_for(roles: &RoleStore, uuid: Uuid, authenticated: bool) -> Role {
+	let role = roles.get(uuid);
+	if authenticated {
+		role
+	} else {
+		role.min(Role::Member)
+	}
+}
+
+/// Adds the reason to a message, if one was given.
+fn with_reason(message: &str, reason: &str) -> String {
+	if reason.is_empty() {
+		message.to_string()
+	} else {
+		format!("{}: {}", message, reason)
+	}
+}
+
+/// Splits an optional duration, such as "2h", from the start of the arguments.
+fn split_duration(args: &str) -> (Option<Duration>, &str) {
+	let (first, rest) = command::split_word(args);
+	match parse_duration(first) {
+		Some(duration) => (Some(duration), rest),
+		None => (None, args),
+	}
+}
+
 pub enum ConnectionManagerMessage {
 	// server messages
 	AddClient {
 		conn: Box<dyn NetworkConnection + 'static>,
+		request: ConnectRequest,
+	},
+	/// a client logged in, or registered, once their password was hashed.
+	Authenticated {
+		conn: Box<dyn NetworkConnection + 'static>,
+		request: ConnectRequest,
+		account: Account,
+		/// true if the account is new, so should be saved.
+		registered: bool,
+	},
+	/// a users password was checked, for a change they asked for.
+	AccountChecked {
 		uuid: Uuid,
-		username: String,
-		addr: SocketAddr,
+		change: Result<AccountChange, String>,
+	},
+
+	SendInfo {
+		conn: Box<dyn NetworkConnection + 'static>,
+	},
+
+	GetInfo {
+		reply: oneshot::Sender<Info>,
 	},
 
 	// client thread messages
 	SendClientsTo {
 		uuid: Uuid,
+		session: Uuid,
 	},
 
 	SendGlobalMessages {
 		uuid: Uuid,
+		session: Uuid,
 	},
 
 	SendGlobalMessagesTo {
 		uuid: Uuid,
+		session: Uuid,
 		messages: Vec<GlobalMessage>,
 	},
 
@@ -238,15 +1857,86 @@ pub enum ConnectionManagerMessage {
 	SendPrivateMessage {
 		uuid: Uuid,
 		from: Uuid,
+		session: Uuid,
 		to: Uuid,
 		content: String,
 	},
 
 	Disconnect {
 		uuid: Uuid,
+		session: Uuid,
 	},
 
 	Disconnected {
 		uuid: Uuid,
+		session: Uuid,
+	},
+
+	ReceivedPong {
+		uuid: Uuid,
+		session: Uuid,
+	},
+
+	// session messages
+	SessionExpired {
+		uuid: Uuid,
+		session: Uuid,
+	},
+
+	// peer manager messages
+	RemoteClientJoined(ClientDetails),
+	/// a remote client was renamed, or its role changed.
+	RemoteClientUpdated(ClientDetails),
+	RemoteClientLeft(Uuid),
+	RemoteGlobalMessage(GlobalMessage),
+	RemotePrivateMessage(PrivateMessage),
+
+	// plugin api messages
+	ListClients {
+		reply: oneshot::Sender<Vec<ClientDetails>>,
+	},
+	BotGlobalMessage {
+		bot: ClientDetails,
+		content: String,
+	},
+	BotPrivateMessage {
+		bot: ClientDetails,
+		to: Uuid,
+		content: String,
+	},
+	DisconnectUser {
+		uuid: Uuid,
+	},
+	RegisterCommand {
+		plugin_id: String,
+		name: String,
+		description: String,
+	},
+	PluginUnloaded {
+		plugin_id: String,
+		bot: ClientDetails,
+	},
+
+	// admin messages
+	ListClientStatuses {
+		reply: oneshot::Sender<Vec<ClientStatus>>,
+	},
+	Notice {
+		content: String,
+	},
+	AdminKick {
+		user: String,
+		reason: String,
+		reply: oneshot::Sender<Result<(), String>>,
+	},
+	AdminBan {
+		target: String,
+		duration: Option<Duration>,
+		reason: String,
+		reply: oneshot::Sender<()>,
+	},
+	ReloadConfig(Box<ServerConfig>),
+	GetStats {
+		reply: oneshot::Sender<ServerStats>,
 	},
 }
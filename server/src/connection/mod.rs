@@ -1,3 +1,5 @@
 pub mod client_info;
 pub mod client_thread;
 pub mod connection_manager;
+pub mod session;
+pub mod username;
@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+
+use foundation::prelude::{ClientDetails, GlobalMessage};
+use log::{debug, warn};
+use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle, time::sleep};
+use uuid::Uuid;
+
+use crate::{
+	config::SessionConfig,
+	connection::{
+		client_thread::ClientThread,
+		connection_manager::ConnectionManagerMessage,
+	},
+	logging,
+	metrics::METRICS,
+};
+
+/// the queue depth label for events kept for suspended sessions.
+const SUSPENDED_QUEUE: &str = "suspended";
+
+/// # SessionEvent
+/// Events sent to a client that are queued while its session is suspended.
+#[derive(Clone)]
+pub enum SessionEvent {
+	ClientJoined(ClientDetails),
+	ClientLeft(Uuid),
+	ClientRenamed {
+		uuid: Uuid,
+		username: String,
+	},
+	GlobalMessage(GlobalMessage),
+	PrivateMessage {
+		from: Uuid,
+		to: Uuid,
+		uuid: Uuid,
+		content: String,
+	},
+}
+
+enum SessionState {
+	Connected(ClientThread),
+	Suspended {
+		pending: VecDeque<SessionEvent>,
+		expiry_task: JoinHandle<()>,
+	},
+}
+
+/// # Session
+/// Keeps a clients place on the server across dropped connections.
+/// While connected, events go straight to the clients thread.
+/// While suspended, events are queued until the client resumes,
+/// or the grace period runs out.
+pub struct Session {
+	token: Uuid,
+	state: SessionState,
+	max_pending_events: usize,
+	/// false if the client can't resume, so there is no point suspending it.
+	resumable: bool,
+}
+
+impl Session {
+	pub fn new(
+		token: Uuid,
+		thread: ClientThread,
+		config: &SessionConfig,
+		resumable: bool,
+	) -> Self {
+		Self {
+			token,
+			state: SessionState::Connected(thread),
+			max_pending_events: config.max_pending_events,
+			resumable,
+		}
+	}
+
+	pub fn get_token(&self) -> Uuid {
+		self.token
+	}
+
+	pub fn is_resumable(&self) -> bool {
+		self.resumable
+	}
+
+	pub fn is_suspended(&self) -> bool {
+		matches!(self.state, SessionState::Suspended { .. })
+	}
+
+	/// Gets the clients thread, if it is currently connected.
+	pub fn get_thread(&mut self) -> Option<&mut ClientThread> {
+		match &mut self.state {
+			SessionState::Connected(thread) => Some(thread),
+			SessionState::Suspended { .. } => None,
+		}
+	}
+
+	/// Sends the event to the client, or queues it if suspended.
+	/// When the queue is full the oldest event is dropped.
+	pub async fn send(&mut self, event: SessionEvent) {
+		match &mut self.state {
+			SessionState::Connected(thread) => match event {
+				SessionEvent::ClientJoined(details) => {
+					thread.send_client_joined(details).await
+				}
+				SessionEvent::ClientLeft(uuid) => thread.send_client_left(uuid).await,
+				SessionEvent::ClientRenamed { uuid, username } => {
+					thread.send_client_renamed(uuid, username).await
+				}
+				SessionEvent::GlobalMessage(message) => {
+					thread.send_global_message(message).await
+				}
+				SessionEvent::PrivateMessage {
+					from,
+					to,
+					uuid,
+					content,
+				} => thread.send_private_message(from, to, uuid, content).await,
+			},
+			SessionState::Suspended { pending, .. } => {
+				if pending.len() >= self.max_pending_events {
+					warn!(
+						session:% = logging::session(self.token);
+						"pending queue full, dropping oldest event"
+					);
+					pending.pop_front();
+					METRICS.queue_depth.dec(SUSPENDED_QUEUE);
+				}
+				pending.push_back(event);
+				METRICS.queue_depth.inc(SUSPENDED_QUEUE);
+			}
+		}
+	}
+
+	/// Drops the clients thread and starts queueing events.
+	/// Once the grace period ends, the connection manager is told to expire it.
+	pub fn suspend(
+		&mut self,
+		uuid: Uuid,
+		config: &SessionConfig,
+		channel: UnboundedSender<ConnectionManagerMessage>,
+	) {
+		if self.is_suspended() {
+			return;
+		}
+
+		let token = self.token;
+		let grace_period = config.grace_period();
+		let expiry_task = tokio::spawn(async move {
+			sleep(grace_period).await;
+			_ = channel.send(ConnectionManagerMessage::SessionExpired {
+				uuid,
+				session: token,
+			});
+		});
+
+		self.state = SessionState::Suspended {
+			pending: VecDeque::new(),
+			expiry_task,
+		};
+	}
+
+	/// Attaches a new thread to the session,
+	/// replaying any events the client missed in order.
+	pub async fn resume(&mut self, thread: ClientThread) {
+		let previous =
+			std::mem::replace(&mut self.state, SessionState::Connected(thread));
+
+		let SessionState::Suspended {
+			pending,
+			expiry_task,
+		} = previous
+		else {
+			return;
+		};
+
+		expiry_task.abort();
+		METRICS
+			.queue_depth
+			.add(SUSPENDED_QUEUE, -(pending.len() as i64));
+		debug!(
+			session:% = logging::session(self.token);
+			"resuming with {} missed events", pending.len()
+		);
+
+		for event in pending {
+			self.send(event).await;
+		}
+	}
+}
+
+impl Drop for Session {
+	fn drop(&mut self) {
+		if let SessionState::Suspended {
+			pending,
+			expiry_task,
+		} = &self.state
+		{
+			expiry_task.abort();
+			METRICS
+				.queue_depth
+				.add(SUSPENDED_QUEUE, -(pending.len() as i64));
+		}
+	}
+}
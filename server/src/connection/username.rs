@@ -0,0 +1,90 @@
+//! Checks the names users connect with, or change to.
+
+use std::iter;
+
+use crate::config::UsernameConfig;
+
+/// Checks the name follows the configs rules,
+/// returning why it doesn't if not.
+/// Whether the name is taken is checked by the connection manager.
+pub fn validate(
+	name: &str,
+	server_name: &str,
+	config: &UsernameConfig,
+) -> Result<(), String> {
+	if name.is_empty() {
+		return Err("usernames can't be empty".into());
+	}
+	let length = name.chars().count();
+	if length < config.min_length {
+		return Err(format!(
+			"usernames must be at least {} characters",
+			config.min_length
+		));
+	}
+	if length > config.max_length {
+		return Err(format!(
+			"usernames can't be longer than {} characters",
+			config.max_length
+		));
+	}
+
+	if let Some(c) = name.chars().find(|c| !is_allowed(*c)) {
+		return Err(format!("usernames can't contain {:?}", c));
+	}
+	if name.starts_with(' ') || name.ends_with(' ') {
+		return Err("usernames can't start or end with a space".into());
+	}
+
+	let reserved = config
+		.reserved
+		.iter()
+		.map(String::as_str)
+		.chain(iter::once(server_name));
+	for r in reserved {
+		if r.eq_ignore_ascii_case(name) {
+			return Err(format!("the username {} is reserved", name));
+		}
+	}
+	Ok(())
+}
+
+fn is_allowed(c: char) -> bool {
+	c.is_alphanumeric() || matches!(c, ' ' | '_' | '-' | '.')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn check(name: &str) -> Result<(), String> {
+		validate(name, "Example Server", &UsernameConfig::default())
+	}
+
+	#[test]
+	fn ordinary_names_are_allowed() {
+		assert!(check("alice").is_ok());
+		assert!(check("Bob the-builder_2.0").is_ok());
+	}
+
+	#[test]
+	fn lengths_are_checked() {
+		assert!(check("").is_err());
+		assert!(check(&"a".repeat(32)).is_ok());
+		assert!(check(&"a".repeat(33)).is_err());
+	}
+
+	#[test]
+	fn symbols_and_outer_spaces_are_refused() {
+		assert!(check("alice!").is_err());
+		assert!(check("a\u{202e}b").is_err());
+		assert!(check(" alice").is_err());
+		assert!(check("alice ").is_err());
+	}
+
+	#[test]
+	fn reserved_names_are_refused_ignoring_case() {
+		assert!(check("Admin").is_err());
+		assert!(check("example server").is_err());
+	}
+}
@@ -0,0 +1,159 @@
+use std::{
+	sync::{
+		atomic::{AtomicU32, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use foundation::event::{Event, EventFilter, EventResult, IEventHandler};
+use log::{debug, warn};
+use tokio::{
+	sync::RwLock,
+	time::{timeout_at, Instant},
+};
+use uuid::Uuid;
+
+/// how long every handler together has to reply to an event.
+/// Handlers not reached in time are skipped.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// how many times in a row a handler may time out before it is disabled.
+const MAX_TIMEOUTS: u32 = 3;
+
+struct Subscription {
+	id: Uuid,
+	name: String,
+	filter: EventFilter,
+	handler: Arc<dyn IEventHandler>,
+	/// timeouts since the handler last replied in time.
+	timeouts: AtomicU32,
+}
+
+impl Subscription {
+	fn is_disabled(&self) -> bool {
+		self.timeouts.load(Ordering::SeqCst) >= MAX_TIMEOUTS
+	}
+}
+
+/// # EventBus
+/// Passes server events to subscribed plugins and subsystems.
+///
+/// Publishers wait for the matching handlers,
+/// so handlers can veto or rewrite an event before it takes effect.
+/// Each event has a single deadline shared by all its handlers,
+/// and handlers that keep missing it are disabled,
+/// so slow handlers delay the server by at most the deadline.
+pub struct EventBus {
+	subscriptions: RwLock<Vec<Arc<Subscription>>>,
+}
+
+impl EventBus {
+	pub fn new() -> Arc<Self> {
+		Arc::new(Self {
+			subscriptions: RwLock::new(Vec::new()),
+		})
+	}
+
+	/// Subscribes a handler to the events matching the filter.
+	/// Returns an id used to unsubscribe.
+	pub async fn subscribe(
+		&self,
+		name: String,
+		filter: EventFilter,
+		handler: Arc<dyn IEventHandler>,
+	) -> Uuid {
+		let id = Uuid::new_v4();
+		debug!(subscriber = name; "subscribed to {:?}", filter);
+		self
+			.subscriptions
+			.write()
+			.await
+			.push(Arc::new(Subscription {
+				id,
+				name,
+				filter,
+				handler,
+				timeouts: AtomicU32::new(0),
+			}));
+		id
+	}
+
+	pub async fn unsubscribe(&self, id: Uuid) {
+		self.subscriptions.write().await.retain(|s| s.id != id);
+	}
+
+	/// Runs the event past every matching handler.
+	/// Returns the event as rewritten by the handlers,
+	/// or none if it was vetoed.
+	pub async fn publish(&self, mut event: Event) -> Option<Event> {
+		// handlers may subscribe others, so the lock isn't held while they run
+		let subscriptions: Vec<Arc<Subscription>> = self
+			.subscriptions
+			.read()
+			.await
+			.iter()
+			.filter(|s| !s.is_disabled() && s.filter.matches(&event))
+			.cloned()
+			.collect();
+
+		let deadline = Instant::now() + PUBLISH_TIMEOUT;
+		for subscription in subscriptions {
+			let name = subscription.name.as_str();
+			let result =
+				match timeout_at(deadline, subscription.handler.on_event(&event)).await
+				{
+					Ok(result) => {
+						subscription.timeouts.store(0, Ordering::SeqCst);
+						result
+					}
+					Err(_) => {
+						warn!(
+							subscriber = name;
+							"timed out handling {:?}", event.kind()
+						);
+						let timeouts =
+							subscription.timeouts.fetch_add(1, Ordering::SeqCst) + 1;
+						if timeouts >= MAX_TIMEOUTS {
+							warn!(
+								subscriber = name;
+								"disabled after {} timeouts in a row", timeouts
+							);
+						}
+						// the rest of the handlers have no time left either
+						break;
+					}
+				};
+
+			match result {
+				EventResult::Continue => {}
+				EventResult::Veto if event.is_vetoable() => {
+					debug!(
+						subscriber = name;
+						"vetoed {:?}", event.kind()
+					);
+					return None;
+				}
+				EventResult::Veto => {
+					warn!(
+						subscriber = name;
+						"can't veto {:?}", event.kind()
+					);
+				}
+				EventResult::Rewrite(rewritten) if rewritten.kind() == event.kind() => {
+					event = rewritten;
+				}
+				EventResult::Rewrite(rewritten) => {
+					warn!(
+						subscriber = name;
+						"can't rewrite {:?} into {:?}",
+						event.kind(),
+						rewritten.kind()
+					);
+				}
+			}
+		}
+
+		Some(event)
+	}
+}
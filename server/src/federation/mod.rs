@@ -0,0 +1,7 @@
+//! Links servers together into a mesh,
+//! so clients connected to different servers can talk to each other.
+
+pub mod peer_connector;
+pub mod peer_link;
+pub mod peer_listener;
+pub mod peer_manager;
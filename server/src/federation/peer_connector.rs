@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use log::{debug, warn};
+use tokio::{
+	net::TcpStream,
+	sync::mpsc::UnboundedSender,
+	task::JoinHandle,
+	time::sleep,
+};
+
+use crate::federation::{
+	peer_link::{run_link, PeerIdentity},
+	peer_manager::PeerManagerMessage,
+};
+
+/// # PeerConnector
+/// Keeps a link open to a configured peer,
+/// reconnecting after a delay whenever it drops.
+pub struct PeerConnector {
+	address: String,
+	identity: PeerIdentity,
+	reconnect_delay: Duration,
+	sender: UnboundedSender<PeerManagerMessage>,
+}
+
+impl PeerConnector {
+	pub fn new(
+		address: String,
+		identity: PeerIdentity,
+		reconnect_delay: Duration,
+		sender: UnboundedSender<PeerManagerMessage>,
+	) -> Self {
+		Self {
+			address,
+			identity,
+			reconnect_delay,
+			sender,
+		}
+	}
+
+	pub async fn run(&self) {
+		loop {
+			debug!(peer = self.address.as_str(); "connecting");
+			match TcpStream::connect(&self.address).await {
+				Ok(stream) => {
+					let addr = match stream.peer_addr() {
+						Ok(addr) => addr,
+						Err(e) => {
+							warn!(
+								peer = self.address.as_str();
+								"failed to get peer address: {}", e
+							);
+							sleep(self.reconnect_delay).await;
+							continue;
+						}
+					};
+					run_link(stream, addr, self.identity.clone(), self.sender.clone())
+						.await;
+				}
+				Err(e) => {
+					warn!(
+						peer = self.address.as_str();
+						"failed to connect: {}", e
+					)
+				}
+			}
+
+			sleep(self.reconnect_delay).await;
+		}
+	}
+
+	pub fn start_run(self) -> JoinHandle<()> {
+		tokio::spawn(async move {
+			self.run().await;
+		})
+	}
+}
@@ -0,0 +1,247 @@
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use foundation::{
+	networking::protobuf::{read_message, write_message},
+	prelude::{peer_message, PeerAuth, PeerHello, PeerMessage},
+};
+use log::{debug, info, warn};
+use openssl::{hash::MessageDigest, memcmp, pkey::PKey, rand, sign::Signer};
+use tokio::{
+	io::{split, ReadHalf, WriteHalf},
+	net::TcpStream,
+	sync::{mpsc::UnboundedSender, oneshot},
+	time::timeout,
+};
+use uuid::Uuid;
+
+use crate::federation::peer_manager::PeerManagerMessage;
+
+/// how long the other server has to introduce itself.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// how long a write to another server may take.
+/// A peer that can't keep up is dropped,
+/// so it can't hold up the rest of the mesh.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// # PeerLink
+/// The writing half of a link to another server, held by the peer manager.
+/// Dropping it closes the link.
+pub struct PeerLink {
+	pub link_id: Uuid,
+	pub peer_id: Uuid,
+	pub writer: WriteHalf<TcpStream>,
+	/// stops the links reader when dropped.
+	_closed: oneshot::Sender<()>,
+}
+
+impl PeerLink {
+	/// Fails if the write fails or times out, after which the link should be
+	/// dropped, as part of a message may have been written.
+	pub async fn send(&mut self, message: PeerMessage) -> io::Result<()> {
+		timeout(WRITE_TIMEOUT, write_message(&mut self.writer, message))
+			.await
+			.map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+	}
+}
+
+const NONCE_LENGTH: usize = 32;
+
+/// # PeerIdentity
+/// How this server introduces itself to the mesh.
+#[derive(Clone)]
+pub struct PeerIdentity {
+	pub server_id: Uuid,
+	pub server_name: String,
+	/// shared by every server in the mesh.
+	pub secret: Arc<String>,
+}
+
+/// Introduces this server over a new link, checking the peer knows the secret,
+/// then passes events from the peer to the manager until the link closes.
+pub async fn run_link(
+	mut stream: TcpStream,
+	addr: SocketAddr,
+	identity: PeerIdentity,
+	sender: UnboundedSender<PeerManagerMessage>,
+) {
+	let server_id = identity.server_id;
+	let handshake = timeout(HANDSHAKE_TIMEOUT, handshake(&mut stream, &identity));
+	let peer_id = match handshake.await {
+		Ok(Ok(peer_id)) => peer_id,
+		Ok(Err(e)) => {
+			warn!(addr:% = addr; "handshake failed: {}", e);
+			return;
+		}
+		Err(_) => {
+			warn!(addr:% = addr; "handshake timed out");
+			return;
+		}
+	};
+
+	if peer_id == server_id {
+		warn!(addr:% = addr; "linked to itself, closing");
+		return;
+	}
+
+	let link_id = Uuid::new_v4();
+	let (reader, writer) = split(stream);
+	let (closed, mut dropped) = oneshot::channel();
+
+	_ = sender.send(PeerManagerMessage::LinkUp(PeerLink {
+		link_id,
+		peer_id,
+		writer,
+		_closed: closed,
+	}));
+
+	info!(addr:% = addr, peer:% = peer_id; "linked");
+	tokio::select! {
+		_ = read_events(reader, peer_id, &sender) => {}
+		_ = &mut dropped => {}
+	}
+
+	info!(addr:% = addr, peer:% = peer_id; "link closed");
+	_ = sender.send(PeerManagerMessage::LinkDown { link_id });
+}
+
+/// Swaps hellos with the peer, then proves to each other they know the secret,
+/// by sending an hmac of the others nonce and their own id.
+/// Returns the peers id.
+async fn handshake(
+	stream: &mut TcpStream,
+	identity: &PeerIdentity,
+) -> io::Result<Uuid> {
+	let mut nonce = vec![0; NONCE_LENGTH];
+	rand::rand_bytes(&mut nonce).map_err(io::Error::other)?;
+
+	let hello = PeerHello {
+		server_id: identity.server_id.to_string(),
+		server_name: identity.server_name.clone(),
+		nonce: nonce.clone(),
+	};
+	send(stream, peer_message::Message::Hello(hello)).await?;
+
+	let Some(peer_message::Message::Hello(hello)) = receive(stream).await? else {
+		return Err(invalid("expected a hello from the peer"));
+	};
+	let peer_id: Uuid = hello
+		.server_id
+		.parse()
+		.map_err(|_| invalid("invalid peer server id"))?;
+
+	let proof = prove(&identity.secret, &hello.nonce, identity.server_id)?;
+	send(stream, peer_message::Message::Auth(PeerAuth { proof })).await?;
+
+	let Some(peer_message::Message::Auth(auth)) = receive(stream).await? else {
+		return Err(invalid("expected proof from the peer"));
+	};
+	let expected = prove(&identity.secret, &nonce, peer_id)?;
+	if auth.proof.len() != expected.len() || !memcmp::eq(&auth.proof, &expected) {
+		return Err(invalid("the peer doesn't know the mesh secret"));
+	}
+
+	Ok(peer_id)
+}
+
+/// The hmac a server with the id sends to prove it knows the secret.
+fn prove(secret: &str, nonce: &[u8], server_id: Uuid) -> io::Result<Vec<u8>> {
+	let key = PKey::hmac(secret.as_bytes()).map_err(io::Error::other)?;
+	let mut signer =
+		Signer::new(MessageDigest::sha256(), &key).map_err(io::Error::other)?;
+	signer.update(nonce).map_err(io::Error::other)?;
+	signer
+		.update(server_id.as_bytes())
+		.map_err(io::Error::other)?;
+	signer.sign_to_vec().map_err(io::Error::other)
+}
+
+async fn send(
+	stream: &mut TcpStream,
+	message: peer_message::Message,
+) -> io::Result<()> {
+	let message = PeerMessage {
+		message: Some(message),
+	};
+	write_message(stream, message).await
+}
+
+async fn receive(
+	stream: &mut TcpStream,
+) -> io::Result<Option<peer_message::Message>> {
+	let message = read_message::<PeerMessage, TcpStream>(stream).await?;
+	Ok(message.message)
+}
+
+fn invalid(reason: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+async fn read_events(
+	mut reader: ReadHalf<TcpStream>,
+	peer_id: Uuid,
+	sender: &UnboundedSender<PeerManagerMessage>,
+) {
+	loop {
+		let message = read_message::<PeerMessage, ReadHalf<TcpStream>>(&mut reader);
+
+		match message.await {
+			Ok(PeerMessage {
+				message: Some(peer_message::Message::Event(event)),
+			}) => {
+				_ = sender.send(PeerManagerMessage::Received { peer_id, event });
+			}
+			Ok(_) => debug!(peer:% = peer_id; "ignoring unexpected message"),
+			Err(_) => return,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::net::TcpListener;
+
+	use super::*;
+
+	fn identity(secret: &str) -> PeerIdentity {
+		PeerIdentity {
+			server_id: Uuid::new_v4(),
+			server_name: "test".into(),
+			secret: Arc::new(secret.into()),
+		}
+	}
+
+	/// Runs the handshake from both ends of a loopback connection.
+	async fn link(
+		a: PeerIdentity,
+		b: PeerIdentity,
+	) -> (io::Result<Uuid>, io::Result<Uuid>) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let accept = tokio::spawn(async move {
+			let (mut stream, _) = listener.accept().await.unwrap();
+			handshake(&mut stream, &b).await
+		});
+
+		let mut stream = TcpStream::connect(addr).await.unwrap();
+		let a = handshake(&mut stream, &a).await;
+		(a, accept.await.unwrap())
+	}
+
+	#[tokio::test]
+	async fn servers_sharing_the_secret_link() {
+		let (a, b) = (identity("secret"), identity("secret"));
+		let (a_id, b_id) = (a.server_id, b.server_id);
+
+		let (a_peer, b_peer) = link(a, b).await;
+		assert_eq!(a_peer.unwrap(), b_id);
+		assert_eq!(b_peer.unwrap(), a_id);
+	}
+
+	#[tokio::test]
+	async fn servers_without_the_secret_are_refused() {
+		let (a_peer, b_peer) = link(identity("secret"), identity("guess")).await;
+		assert!(a_peer.is_err());
+		assert!(b_peer.is_err());
+	}
+}
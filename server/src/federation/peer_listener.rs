@@ -0,0 +1,69 @@
+use std::io;
+
+use log::{debug, error, trace, warn};
+use tokio::{net::TcpListener, sync::mpsc::UnboundedSender, task::JoinHandle};
+
+use crate::{
+	config::FederationConfig,
+	federation::{
+		peer_link::{run_link, PeerIdentity},
+		peer_manager::PeerManagerMessage,
+	},
+};
+
+/// # PeerListener
+/// Accepts links from other servers in the mesh.
+/// Links are only relayed once the other server proves it knows the secret.
+pub struct PeerListener {
+	listener: TcpListener,
+	identity: PeerIdentity,
+	sender: UnboundedSender<PeerManagerMessage>,
+}
+
+impl PeerListener {
+	pub async fn new(
+		config: &FederationConfig,
+		identity: PeerIdentity,
+		sender: UnboundedSender<PeerManagerMessage>,
+	) -> io::Result<Self> {
+		debug!("setting up listener");
+		let listener =
+			TcpListener::bind((config.bind_address, config.port)).await?;
+
+		Ok(Self {
+			listener,
+			identity,
+			sender,
+		})
+	}
+
+	pub async fn run(&self) {
+		loop {
+			trace!("waiting for peer");
+			let Ok((stream, addr)) = self.listener.accept().await else {
+				warn!("accept failed");
+				continue;
+			};
+
+			tokio::spawn(run_link(
+				stream,
+				addr,
+				self.identity.clone(),
+				self.sender.clone(),
+			));
+		}
+	}
+
+	pub fn start_run(
+		config: FederationConfig,
+		identity: PeerIdentity,
+		sender: UnboundedSender<PeerManagerMessage>,
+	) -> JoinHandle<()> {
+		tokio::spawn(async move {
+			match PeerListener::new(&config, identity, sender).await {
+				Ok(listener) => listener.run().await,
+				Err(e) => error!("failed to bind: {}", e),
+			}
+		})
+	}
+}
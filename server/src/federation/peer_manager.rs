@@ -0,0 +1,546 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use foundation::prelude::{
+	peer_event,
+	peer_message,
+	ClientDetails,
+	GlobalMessage,
+	PeerClient,
+	PeerClientLeft,
+	PeerClientList,
+	PeerEvent,
+	PeerMessage,
+	PrivateMessage,
+};
+use log::{info, warn};
+use tokio::sync::{
+	mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+	Mutex,
+};
+use uuid::Uuid;
+
+use crate::{
+	connection::connection_manager::ConnectionManagerMessage,
+	federation::peer_link::PeerLink,
+};
+
+/// how many event uuids are remembered to drop duplicates.
+const SEEN_EVENTS: usize = 10_000;
+
+/// events that have passed through more servers than this are dropped.
+const MAX_HOPS: usize = 16;
+
+/// A client known to the mesh.
+struct KnownClient {
+	client: PeerClient,
+	/// the peer the client was learned from, none for our own clients.
+	via: Option<Uuid>,
+}
+
+/// # PeerManager
+/// Owns the links to other servers.
+/// Local events are flooded to every peer,
+/// and events from peers are applied locally then passed on.
+///
+/// Every event carries a uuid and the path it has taken,
+/// so copies arriving by other routes are dropped,
+/// and events are never sent back to a server they have passed through.
+pub struct PeerManager {
+	receiver: Mutex<UnboundedReceiver<PeerManagerMessage>>,
+	sender: UnboundedSender<PeerManagerMessage>,
+	connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+
+	server_id: Uuid,
+	/// keyed by link id.
+	links: HashMap<Uuid, PeerLink>,
+	clients: HashMap<Uuid, KnownClient>,
+
+	seen: HashSet<Uuid>,
+	seen_order: VecDeque<Uuid>,
+}
+
+impl PeerManager {
+	pub fn new(
+		server_id: Uuid,
+		connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+	) -> Self {
+		let (tx, rx) = unbounded_channel();
+		Self {
+			receiver: Mutex::new(rx),
+			sender: tx,
+			connection_manager_sender,
+			server_id,
+			links: HashMap::new(),
+			clients: HashMap::new(),
+			seen: HashSet::new(),
+			seen_order: VecDeque::new(),
+		}
+	}
+
+	pub async fn run(&mut self) {
+		loop {
+			let mut lock = self.receiver.lock().await;
+			let msg = lock.recv().await;
+			drop(lock);
+
+			match msg {
+				Some(PeerManagerMessage::LinkUp(link)) => self.link_up(link).await,
+				Some(PeerManagerMessage::LinkDown { link_id }) => {
+					self.link_down(link_id)
+				}
+				Some(PeerManagerMessage::Received { peer_id, event }) => {
+					self.received(peer_id, event).await
+				}
+
+				Some(PeerManagerMessage::ClientJoined(details))
+				| Some(PeerManagerMessage::ClientUpdated(details)) => {
+					self.local_client_joined(details).await
+				}
+				Some(PeerManagerMessage::ClientLeft(uuid)) => {
+					self.local_client_left(uuid).await
+				}
+				Some(PeerManagerMessage::GlobalMessage(message)) => {
+					self
+						.flood_local(peer_event::Event::GlobalMessage(message))
+						.await
+				}
+				Some(PeerManagerMessage::PrivateMessage(message)) => {
+					self
+						.flood_local(peer_event::Event::PrivateMessage(message))
+						.await
+				}
+				None => return,
+			}
+		}
+	}
+
+	/// Adds a new link, then sends it every client we know of.
+	///
+	/// Two servers may be linked more than once,
+	/// such as when both list each other as peers.
+	/// Events sent over both links are dropped as duplicates.
+	async fn link_up(&mut self, mut link: PeerLink) {
+		let peer_id = link.peer_id;
+		info!(peer:% = peer_id; "link up");
+
+		let event = self.new_event(
+			Uuid::new_v4(),
+			peer_event::Event::ClientList(PeerClientList {
+				clients: self.clients.values().map(|c| c.client.clone()).collect(),
+			}),
+		);
+		if let Err(e) = link.send(event_message(event)).await {
+			warn!(peer:% = peer_id; "failed to send clients, dropping link: {}", e);
+			return;
+		}
+
+		self.links.insert(link.link_id, link);
+	}
+
+	/// Removes the link.
+	/// If it was the last link to the peer,
+	/// every client reached through the peer is removed as well.
+	fn link_down(&mut self, link_id: Uuid) {
+		let Some(link) = self.links.remove(&link_id) else {
+			return;
+		};
+
+		let peer_id = link.peer_id;
+		info!(peer:% = peer_id; "link down");
+
+		if self.links.values().any(|l| l.peer_id == peer_id) {
+			return;
+		}
+
+		let lost: Vec<Uuid> = self
+			.clients
+			.iter()
+			.filter(|(_, c)| c.via == Some(peer_id))
+			.map(|(uuid, _)| *uuid)
+			.collect();
+
+		for uuid in lost {
+			self.clients.remove(&uuid);
+			_ = self
+				.connection_manager_sender
+				.send(ConnectionManagerMessage::RemoteClientLeft(uuid));
+		}
+	}
+
+	/// Applies an event from a peer, then passes it on to the others.
+	async fn received(&mut self, peer_id: Uuid, mut event: PeerEvent) {
+		let Ok(uuid) = event.uuid.parse::<Uuid>() else {
+			warn!("dropping event with invalid uuid");
+			return;
+		};
+
+		let server_id = self.server_id.to_string();
+		if !self.mark_seen(uuid)
+			|| event.path.contains(&server_id)
+			|| event.path.len() >= MAX_HOPS
+		{
+			return;
+		}
+
+		self.apply(peer_id, &event);
+
+		event.path.push(server_id);
+		self.forward(event).await;
+	}
+
+	fn apply(&mut self, peer_id: Uuid, event: &PeerEvent) {
+		match &event.event {
+			Some(peer_event::Event::ClientList(list)) => {
+				for client in &list.clients {
+					self.remote_client_joined(peer_id, client.clone());
+				}
+			}
+			Some(peer_event::Event::ClientJoined(client)) => {
+				self.remote_client_joined(peer_id, client.clone())
+			}
+			Some(peer_event::Event::ClientLeft(PeerClientLeft { uuid })) => {
+				let Ok(uuid) = uuid.parse() else {
+					return;
+				};
+				let is_remote =
+					self.clients.get(&uuid).is_some_and(|c| c.via.is_some());
+				if is_remote {
+					self.clients.remove(&uuid);
+					_ = self
+						.connection_manager_sender
+						.send(ConnectionManagerMessage::RemoteClientLeft(uuid));
+				}
+			}
+			Some(peer_event::Event::GlobalMessage(message)) => {
+				if self.is_local(&message.from) {
+					warn!(peer:% = peer_id; "dropping message from a local user");
+					return;
+				}
+				_ = self.connection_manager_sender.send(
+					ConnectionManagerMessage::RemoteGlobalMessage(message.clone()),
+				);
+			}
+			Some(peer_event::Event::PrivateMessage(message)) => {
+				if self.is_local(&message.from) {
+					warn!(peer:% = peer_id; "dropping message from a local user");
+					return;
+				}
+				_ = self.connection_manager_sender.send(
+					ConnectionManagerMessage::RemotePrivateMessage(message.clone()),
+				);
+			}
+			None => {}
+		}
+	}
+
+	/// Whether the user is connected to this server,
+	/// so can't have sent anything from another.
+	fn is_local(&self, uuid: &str) -> bool {
+		uuid
+			.parse()
+			.ok()
+			.and_then(|uuid| self.clients.get(&uuid))
+			.is_some_and(|c| c.via.is_none())
+	}
+
+	/// Adds the client, or updates it if it's already known,
+	/// such as when it's renamed or its role changes.
+	/// Only the server the client is connected to may update it.
+	fn remote_client_joined(&mut self, peer_id: Uuid, client: PeerClient) {
+		let Some(details) = client.details.clone() else {
+			return;
+		};
+		let Ok(uuid) = details.uuid.parse() else {
+			return;
+		};
+
+		if client.server_id == self.server_id.to_string() {
+			return;
+		}
+		if let Some(known) = self.clients.get_mut(&uuid) {
+			if known.via.is_none()
+				|| known.client.server_id != client.server_id
+				|| known.client == client
+			{
+				return;
+			}
+			known.client = client;
+			_ = self
+				.connection_manager_sender
+				.send(ConnectionManagerMessage::RemoteClientUpdated(details));
+			return;
+		}
+
+		self.clients.insert(
+			uuid,
+			KnownClient {
+				client,
+				via: Some(peer_id),
+			},
+		);
+		_ = self
+			.connection_manager_sender
+			.send(ConnectionManagerMessage::RemoteClientJoined(details));
+	}
+
+	async fn local_client_joined(&mut self, details: ClientDetails) {
+		let Ok(uuid) = details.uuid.parse() else {
+			return;
+		};
+
+		let client = PeerClient {
+			details: Some(details),
+			server_id: self.server_id.to_string(),
+		};
+		self.clients.insert(
+			uuid,
+			KnownClient {
+				client: client.clone(),
+				via: None,
+			},
+		);
+
+		self
+			.flood_local(peer_event::Event::ClientJoined(client))
+			.await;
+	}
+
+	async fn local_client_left(&mut self, uuid: Uuid) {
+		self.clients.remove(&uuid);
+		self
+			.flood_local(peer_event::Event::ClientLeft(PeerClientLeft {
+				uuid: uuid.to_string(),
+			}))
+			.await;
+	}
+
+	/// Sends an event that started on this server to every peer.
+	/// Messages keep their own uuid, so every server sees the same one.
+	async fn flood_local(&mut self, event: peer_event::Event) {
+		let uuid = match &event {
+			peer_event::Event::GlobalMessage(GlobalMessage { uuid, .. })
+			| peer_event::Event::PrivateMessage(PrivateMessage { uuid, .. }) => {
+				uuid.parse().unwrap_or_else(|_| Uuid::new_v4())
+			}
+			_ => Uuid::new_v4(),
+		};
+
+		self.mark_seen(uuid);
+		let event = self.new_event(uuid, event);
+		self.forward(event).await;
+	}
+
+	/// Sends the event to every peer not already on its path.
+	/// Links that fail to take the event are dropped.
+	async fn forward(&mut self, event: PeerEvent) {
+		let mut failed = Vec::new();
+		for link in self.links.values_mut() {
+			if event.path.contains(&link.peer_id.to_string()) {
+				continue;
+			}
+
+			if let Err(e) = link.send(event_message(event.clone())).await {
+				warn!(peer:% = link.peer_id; "failed to send, dropping link: {}", e);
+				failed.push(link.link_id);
+			}
+		}
+
+		for link_id in failed {
+			self.link_down(link_id);
+		}
+	}
+
+	fn new_event(&self, uuid: Uuid, event: peer_event::Event) -> PeerEvent {
+		PeerEvent {
+			uuid: uuid.to_string(),
+			path: vec![self.server_id.to_string()],
+			event: Some(event),
+		}
+	}
+
+	/// Remembers the event, returning false if it was already seen.
+	fn mark_seen(&mut self, uuid: Uuid) -> bool {
+		if !self.seen.insert(uuid) {
+			return false;
+		}
+
+		self.seen_order.push_back(uuid);
+		if self.seen_order.len() > SEEN_EVENTS {
+			if let Some(oldest) = self.seen_order.pop_front() {
+				self.seen.remove(&oldest);
+			}
+		}
+		true
+	}
+
+	pub fn get_sender(&self) -> UnboundedSender<PeerManagerMessage> {
+		self.sender.clone()
+	}
+}
+
+fn event_message(event: PeerEvent) -> PeerMessage {
+	PeerMessage {
+		message: Some(peer_message::Message::Event(event)),
+	}
+}
+
+pub enum PeerManagerMessage {
+	// link messages
+	LinkUp(PeerLink),
+	LinkDown {
+		link_id: Uuid,
+	},
+	Received {
+		peer_id: Uuid,
+		event: PeerEvent,
+	},
+
+	// connection manager messages
+	ClientJoined(ClientDetails),
+	/// a local client was renamed, or its role changed.
+	/// Other servers update the details they already know.
+	ClientUpdated(ClientDetails),
+	ClientLeft(Uuid),
+	GlobalMessage(GlobalMessage),
+	PrivateMessage(PrivateMessage),
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{sync::Arc, time::Duration};
+
+	use tokio::{
+		net::{TcpListener, TcpStream},
+		time::timeout,
+	};
+
+	use super::*;
+	use crate::federation::peer_link::{run_link, PeerIdentity};
+
+	fn peer_manager() -> PeerManager {
+		let (tx, _) = unbounded_channel();
+		PeerManager::new(Uuid::new_v4(), tx)
+	}
+
+	/// A running server in a test mesh.
+	struct Node {
+		sender: UnboundedSender<PeerManagerMessage>,
+		connection_manager: UnboundedReceiver<ConnectionManagerMessage>,
+	}
+
+	fn node(server_id: Uuid) -> Node {
+		let (tx, rx) = unbounded_channel();
+		let mut peer_manager = PeerManager::new(server_id, tx);
+		let sender = peer_manager.get_sender();
+		tokio::spawn(async move { peer_manager.run().await });
+		Node {
+			sender,
+			connection_manager: rx,
+		}
+	}
+
+	/// Links two servers over loopback.
+	async fn mesh() -> (Node, Node) {
+		let identity = |server_id| PeerIdentity {
+			server_id,
+			server_name: "test".into(),
+			secret: Arc::new("secret".into()),
+		};
+		let (a_id, b_id) = (Uuid::new_v4(), Uuid::new_v4());
+		let (a, b) = (node(a_id), node(b_id));
+
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let b_sender = b.sender.clone();
+		tokio::spawn(async move {
+			let (stream, addr) = listener.accept().await.unwrap();
+			run_link(stream, addr, identity(b_id), b_sender).await
+		});
+		let stream = TcpStream::connect(addr).await.unwrap();
+		tokio::spawn(run_link(stream, addr, identity(a_id), a.sender.clone()));
+
+		(a, b)
+	}
+
+	fn details(uuid: Uuid, name: &str, role: &str) -> ClientDetails {
+		ClientDetails {
+			uuid: uuid.to_string(),
+			name: name.into(),
+			address: "127.0.0.1".into(),
+			role: role.into(),
+		}
+	}
+
+	async fn next(node: &mut Node) -> ConnectionManagerMessage {
+		timeout(Duration::from_secs(5), node.connection_manager.recv())
+			.await
+			.expect("timed out waiting for the mesh")
+			.unwrap()
+	}
+
+	/// Joins a client to the first server.
+	/// The second hears of it in the client list when the link comes up,
+	/// or from the event if the link is already up.
+	async fn joined(a: &Node, b: &mut Node, details: ClientDetails) {
+		_ = a
+			.sender
+			.send(PeerManagerMessage::ClientJoined(details.clone()));
+		match next(b).await {
+			ConnectionManagerMessage::RemoteClientJoined(joined) => {
+				assert_eq!(joined, details)
+			}
+			_ => panic!("expected the client to join"),
+		}
+	}
+
+	/// Updates a client on the first server,
+	/// checking the second sees the new details.
+	async fn updated(a: &Node, b: &mut Node, details: ClientDetails) {
+		_ = a
+			.sender
+			.send(PeerManagerMessage::ClientUpdated(details.clone()));
+		match next(b).await {
+			ConnectionManagerMessage::RemoteClientUpdated(updated) => {
+				assert_eq!(updated, details)
+			}
+			_ => panic!("expected the client to be updated"),
+		}
+	}
+
+	#[tokio::test]
+	async fn renames_cross_the_mesh() {
+		let (a, mut b) = mesh().await;
+		let uuid = Uuid::new_v4();
+		joined(&a, &mut b, details(uuid, "alice", "")).await;
+		updated(&a, &mut b, details(uuid, "alice2", "")).await;
+	}
+
+	#[tokio::test]
+	async fn role_changes_cross_the_mesh() {
+		let (a, mut b) = mesh().await;
+		let uuid = Uuid::new_v4();
+		joined(&a, &mut b, details(uuid, "alice", "user")).await;
+		updated(&a, &mut b, details(uuid, "alice", "moderator")).await;
+	}
+
+	#[test]
+	fn events_are_only_seen_once() {
+		let mut peer_manager = peer_manager();
+		let uuid = Uuid::new_v4();
+		assert!(peer_manager.mark_seen(uuid));
+		assert!(!peer_manager.mark_seen(uuid));
+	}
+
+	#[test]
+	fn oldest_events_are_forgotten() {
+		let mut peer_manager = peer_manager();
+		let first = Uuid::new_v4();
+		peer_manager.mark_seen(first);
+		for _ in 0..SEEN_EVENTS {
+			peer_manager.mark_seen(Uuid::new_v4());
+		}
+
+		assert_eq!(peer_manager.seen.len(), SEEN_EVENTS);
+		assert!(peer_manager.mark_seen(first));
+	}
+}
@@ -0,0 +1,183 @@
+//! Levelled logging, written to stdout as plain text or json lines.
+//!
+//! Each module logs under its own target, such as `server::network`,
+//! so levels can be set for parts of the server.
+//! Fields such as a connections uuid or address are attached as key values.
+
+use std::{
+	fmt,
+	io::{self, Write},
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+use chrono::{SecondsFormat, Utc};
+use log::{
+	kv::{self, Key, Value, VisitSource},
+	LevelFilter,
+	Log,
+	Metadata,
+	Record,
+};
+use openssl::sha::sha256;
+use serde_json::{Map, Value as JsonValue};
+use uuid::Uuid;
+
+use crate::config::{LogConfig, LogFormat};
+
+/// whether message bodies are logged, rather than redacted.
+static SHOW_BODIES: AtomicBool = AtomicBool::new(false);
+
+/// # Logger
+/// Writes each record enabled by the config.
+struct Logger {
+	level: LevelFilter,
+	/// levels for targets, longest first so the most specific matches.
+	targets: Vec<(String, LevelFilter)>,
+	format: LogFormat,
+}
+
+impl Logger {
+	fn level_for(&self, target: &str) -> LevelFilter {
+		self
+			.targets
+			.iter()
+			.find(|(prefix, _)| target.starts_with(prefix.as_str()))
+			.map_or(self.level, |(_, level)| *level)
+	}
+
+	fn plain(&self, record: &Record) -> String {
+		let mut line = format!(
+			"{} {:<5} {}: {}",
+			timestamp(),
+			record.level(),
+			record.target(),
+			record.args()
+		);
+		let mut fields = Fields(Vec::new());
+		_ = record.key_values().visit(&mut fields);
+		for (key, value) in fields.0 {
+			line.push_str(&format!(" {}={}", key, value));
+		}
+		line
+	}
+
+	fn json(&self, record: &Record) -> String {
+		let mut object = Map::new();
+		object.insert("time".into(), timestamp().into());
+		object.insert("level".into(), record.level().as_str().into());
+		object.insert("target".into(), record.target().into());
+		object.insert("message".into(), record.args().to_string().into());
+
+		let mut fields = Fields(Vec::new());
+		_ = record.key_values().visit(&mut fields);
+		for (key, value) in fields.0 {
+			object.insert(key, value.into());
+		}
+		JsonValue::Object(object).to_string()
+	}
+}
+
+impl Log for Logger {
+	fn enabled(&self, metadata: &Metadata) -> bool {
+		metadata.level() <= self.level_for(metadata.target())
+	}
+
+	fn log(&self, record: &Record) {
+		if !self.enabled(record.metadata()) {
+			return;
+		}
+
+		let line = match self.format {
+			LogFormat::Plain => self.plain(record),
+			LogFormat::Json => self.json(record),
+		};
+		_ = writeln!(io::stdout().lock(), "{}", line);
+	}
+
+	fn flush(&self) {
+		_ = io::stdout().flush();
+	}
+}
+
+/// Collects a records key values as strings.
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+	fn visit_pair(
+		&mut self,
+		key: Key<'kvs>,
+		value: Value<'kvs>,
+	) -> Result<(), kv::Error> {
+		self.0.push((key.to_string(), value.to_string()));
+		Ok(())
+	}
+}
+
+fn timestamp() -> String {
+	Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Sets up logging from the config.
+/// This can only be done once, later calls are ignored.
+pub fn init(config: &LogConfig) {
+	let mut targets: Vec<_> = config
+		.targets
+		.iter()
+		.map(|(target, level)| (target.clone(), *level))
+		.collect();
+	targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+
+	let max_level = targets
+		.iter()
+		.map(|(_, level)| *level)
+		.chain(std::iter::once(config.level))
+		.max()
+		.unwrap_or(config.level);
+
+	SHOW_BODIES.store(config.message_bodies, Ordering::Relaxed);
+	let logger = Logger {
+		level: config.level,
+		targets,
+		format: config.format,
+	};
+	if log::set_boxed_logger(Box::new(logger)).is_ok() {
+		log::set_max_level(max_level);
+	}
+}
+
+/// # Body
+/// Chat content, shown in logs only if message bodies are enabled.
+pub struct Body<'a>(&'a str);
+
+impl fmt::Display for Body<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if SHOW_BODIES.load(Ordering::Relaxed) {
+			write!(f, "{:?}", self.0)
+		} else {
+			write!(f, "<{} bytes>", self.0.len())
+		}
+	}
+}
+
+pub fn body(content: &str) -> Body<'_> {
+	Body(content)
+}
+
+/// # SessionId
+/// A session token as shown in logs, the start of a hash of it,
+/// so sessions can be told apart without logging what resumes them.
+pub struct SessionId(Uuid);
+
+impl fmt::Display for SessionId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let hash = sha256(self.0.as_bytes());
+		for byte in &hash[..4] {
+			write!(f, "{:02x}", byte)?;
+		}
+		Ok(())
+	}
+}
+
+pub fn session(token: Uuid) -> SessionId {
+	SessionId(token)
+}
@@ -3,17 +3,59 @@
 
 pub mod network;
 
+pub mod account;
+pub mod admin;
 pub mod chat;
+pub mod command;
+pub mod config;
 pub mod connection;
+pub mod event_bus;
+pub mod federation;
+pub mod logging;
+pub mod metrics;
+pub mod moderation;
 pub mod os_signal_manager;
+pub mod plugin;
+pub mod role;
+pub mod scripting;
 pub mod server_va;
 
-use crate::server_va::Server;
+use std::path::PathBuf;
+
+use clap::Parser;
+use log::{error, warn};
+
+use crate::{config::ServerConfig, server_va::Server};
+
+/// # Args
+/// Command line arguments for the server.
+#[derive(Parser)]
+struct Args {
+	/// path to the servers config file
+	#[arg(short, long, default_value = "server.toml")]
+	config: PathBuf,
+}
 
 /// The main function
 #[actix::main()]
 async fn main() {
-	// creating listeners
+	let args = Args::parse();
+	let config =
+		ServerConfig::load(&args.config).expect("[main] failed to load config");
+	logging::init(&config.log);
+	if !args.config.exists() {
+		warn!(
+			path:% = args.config.display();
+			"config not found, using defaults"
+		);
+	}
 
-	Server::default().run().await;
+	// creating listeners
+	match Server::new(config, args.config) {
+		Ok(mut server) => server.run().await,
+		Err(e) => {
+			error!("failed to start: {}", e);
+			std::process::exit(1);
+		}
+	}
 }
@@ -0,0 +1,125 @@
+use std::{
+	io,
+	net::{Ipv4Addr, SocketAddr},
+	time::Duration,
+};
+
+use log::{debug, error, trace, warn};
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+	task::JoinHandle,
+	time::timeout,
+};
+
+use crate::{config::MetricsConfig, metrics::METRICS};
+
+/// how long a scraper has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// the most of a request that is read, which only needs its first line.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// # MetricsListener
+/// Serves the servers metrics over http on localhost,
+/// for prometheus or anything else that reads its text format.
+pub struct MetricsListener {
+	listener: TcpListener,
+}
+
+impl MetricsListener {
+	pub async fn new(config: &MetricsConfig) -> io::Result<Self> {
+		debug!("setting up listener");
+		let listener =
+			TcpListener::bind((Ipv4Addr::LOCALHOST, config.port)).await?;
+
+		Ok(Self { listener })
+	}
+
+	pub async fn run(&self) {
+		loop {
+			trace!("waiting for scraper");
+			let Ok((stream, addr)) = self.listener.accept().await else {
+				warn!("accept failed");
+				continue;
+			};
+
+			tokio::spawn(serve(stream, addr));
+		}
+	}
+
+	pub fn start_run(config: MetricsConfig) -> JoinHandle<()> {
+		tokio::spawn(async move {
+			match MetricsListener::new(&config).await {
+				Ok(listener) => listener.run().await,
+				Err(e) => error!("failed to bind: {}", e),
+			}
+		})
+	}
+}
+
+/// Answers a single request, then closes the connection.
+async fn serve(mut stream: TcpStream, addr: SocketAddr) {
+	let request = match timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await
+	{
+		Ok(Ok(request)) => request,
+		Ok(Err(e)) => {
+			debug!(addr:% = addr; "failed to read request: {}", e);
+			return;
+		}
+		Err(_) => {
+			debug!(addr:% = addr; "request timed out");
+			return;
+		}
+	};
+
+	let mut parts = request.split_whitespace();
+	let method = parts.next().unwrap_or_default();
+	let path = parts.next().unwrap_or_default();
+	let path = path.split('?').next().unwrap_or_default();
+	trace!(addr:% = addr; "{} {}", method, path);
+
+	let (status, body) = match (method, path) {
+		("GET", "/metrics") => ("200 OK", METRICS.render()),
+		("GET", _) => ("404 Not Found", "not found\n".to_string()),
+		_ => (
+			"405 Method Not Allowed",
+			"only GET is allowed\n".to_string(),
+		),
+	};
+
+	let response = format!(
+		"HTTP/1.1 {}\r\n\
+		 Content-Type: text/plain; version=0.0.4\r\n\
+		 Content-Length: {}\r\n\
+		 Connection: close\r\n\r\n{}",
+		status,
+		body.len(),
+		body
+	);
+	if let Err(e) = stream.write_all(response.as_bytes()).await {
+		debug!(addr:% = addr; "failed to respond: {}", e);
+	}
+	_ = stream.shutdown().await;
+}
+
+/// Reads the request line and headers, ignoring any body.
+async fn read_request(stream: &mut TcpStream) -> io::Result<String> {
+	let mut buffer = Vec::new();
+	let mut chunk = [0; 1024];
+
+	while !buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+		if buffer.len() >= MAX_REQUEST_SIZE {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"request too large",
+			));
+		}
+		let read = stream.read(&mut chunk).await?;
+		if read == 0 {
+			return Err(io::ErrorKind::UnexpectedEof.into());
+		}
+		buffer.extend_from_slice(&chunk[..read]);
+	}
+
+	Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
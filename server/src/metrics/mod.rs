@@ -0,0 +1,296 @@
+//! Counters for monitoring the server,
+//! served in the prometheus text format by the metrics listener.
+//!
+//! Each metric has at most one label, such as the protocol or message type.
+
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration};
+
+pub mod metrics_listener;
+
+/// The port metrics are served on, if not set in the config.
+pub const METRICS_PORT: u16 = 9600;
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// # Metrics
+/// Everything the server measures.
+pub struct Metrics {
+	/// connections accepted, by listener.
+	pub accepted: Counter,
+	/// clients with a running thread, by protocol.
+	pub connections: Gauge,
+	/// handshakes by outcome, such as connected or rejected.
+	pub handshakes: Counter,
+	/// messages from clients, by type.
+	pub messages_received: Counter,
+	/// messages to clients, by type.
+	pub messages_sent: Counter,
+	/// bytes read from clients, by protocol.
+	pub bytes_received: Counter,
+	/// bytes written to clients, by protocol.
+	pub bytes_sent: Counter,
+	/// events waiting to be sent, by queue,
+	/// either for suspended sessions or writes to connected clients.
+	pub queue_depth: Gauge,
+	/// time taken to send an event to every local client.
+	pub broadcast_latency: Histogram,
+	/// plugins that failed to load or run, by plugin id.
+	pub plugin_errors: Counter,
+}
+
+impl Metrics {
+	const fn new() -> Self {
+		Self {
+			accepted: Counter::new(
+				"chatkit_accepted_connections_total",
+				"Connections accepted, by listener.",
+				"listener",
+			),
+			connections: Gauge::new(
+				"chatkit_connections",
+				"Connected clients, by protocol.",
+				"protocol",
+			),
+			handshakes: Counter::new(
+				"chatkit_handshakes_total",
+				"Handshakes, by outcome.",
+				"outcome",
+			),
+			messages_received: Counter::new(
+				"chatkit_messages_received_total",
+				"Messages received from clients, by type.",
+				"type",
+			),
+			messages_sent: Counter::new(
+				"chatkit_messages_sent_total",
+				"Messages sent to clients, by type.",
+				"type",
+			),
+			bytes_received: Counter::new(
+				"chatkit_received_bytes_total",
+				"Bytes read from clients, by protocol.",
+				"protocol",
+			),
+			bytes_sent: Counter::new(
+				"chatkit_sent_bytes_total",
+				"Bytes written to clients, by protocol.",
+				"protocol",
+			),
+			queue_depth: Gauge::new(
+				"chatkit_client_queue_depth",
+				"Events waiting to be sent to clients, by queue.",
+				"queue",
+			),
+			broadcast_latency: Histogram::new(
+				"chatkit_broadcast_duration_seconds",
+				"Time taken to send an event to every local client.",
+				&[0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0],
+			),
+			plugin_errors: Counter::new(
+				"chatkit_plugin_errors_total",
+				"Plugin failures, by plugin.",
+				"plugin",
+			),
+		}
+	}
+
+	/// Writes every metric in the prometheus text format.
+	pub fn render(&self) -> String {
+		let mut out = String::new();
+		self.accepted.render(&mut out);
+		self.connections.render(&mut out);
+		self.handshakes.render(&mut out);
+		self.messages_received.render(&mut out);
+		self.messages_sent.render(&mut out);
+		self.bytes_received.render(&mut out);
+		self.bytes_sent.render(&mut out);
+		self.queue_depth.render(&mut out);
+		self.broadcast_latency.render(&mut out);
+		self.plugin_errors.render(&mut out);
+		out
+	}
+}
+
+/// # Counter
+/// A count that only goes up, kept for each value of its label.
+pub struct Counter {
+	name: &'static str,
+	help: &'static str,
+	label: &'static str,
+	values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Counter {
+	const fn new(
+		name: &'static str,
+		help: &'static str,
+		label: &'static str,
+	) -> Self {
+		Self {
+			name,
+			help,
+			label,
+			values: Mutex::new(BTreeMap::new()),
+		}
+	}
+
+	pub fn inc(&self, value: &str) {
+		self.add(value, 1);
+	}
+
+	pub fn add(&self, value: &str, amount: u64) {
+		let mut values = self.values.lock().unwrap();
+		match values.get_mut(value) {
+			Some(count) => *count += amount,
+			None => {
+				values.insert(value.to_owned(), amount);
+			}
+		}
+	}
+
+	fn render(&self, out: &mut String) {
+		header(out, self.name, self.help, "counter");
+		for (value, count) in self.values.lock().unwrap().iter() {
+			_ = writeln!(
+				out,
+				"{}{{{}=\"{}\"}} {}",
+				self.name,
+				self.label,
+				escape(value),
+				count
+			);
+		}
+	}
+}
+
+/// # Gauge
+/// A value that can go up and down, kept for each value of its label.
+pub struct Gauge {
+	name: &'static str,
+	help: &'static str,
+	label: &'static str,
+	values: Mutex<BTreeMap<String, i64>>,
+}
+
+impl Gauge {
+	const fn new(
+		name: &'static str,
+		help: &'static str,
+		label: &'static str,
+	) -> Self {
+		Self {
+			name,
+			help,
+			label,
+			values: Mutex::new(BTreeMap::new()),
+		}
+	}
+
+	pub fn inc(&self, value: &str) {
+		self.add(value, 1);
+	}
+
+	pub fn dec(&self, value: &str) {
+		self.add(value, -1);
+	}
+
+	pub fn add(&self, value: &str, amount: i64) {
+		*self
+			.values
+			.lock()
+			.unwrap()
+			.entry(value.to_owned())
+			.or_default() += amount;
+	}
+
+	fn render(&self, out: &mut String) {
+		header(out, self.name, self.help, "gauge");
+		for (value, amount) in self.values.lock().unwrap().iter() {
+			_ = writeln!(
+				out,
+				"{}{{{}=\"{}\"}} {}",
+				self.name,
+				self.label,
+				escape(value),
+				amount
+			);
+		}
+	}
+}
+
+/// # Histogram
+/// Counts observed durations into buckets, measured in seconds.
+pub struct Histogram {
+	name: &'static str,
+	help: &'static str,
+	buckets: &'static [f64],
+	state: Mutex<HistogramState>,
+}
+
+struct HistogramState {
+	/// observations no greater than each bucket, not cumulative.
+	counts: Vec<u64>,
+	sum: f64,
+	count: u64,
+}
+
+impl Histogram {
+	const fn new(
+		name: &'static str,
+		help: &'static str,
+		buckets: &'static [f64],
+	) -> Self {
+		Self {
+			name,
+			help,
+			buckets,
+			state: Mutex::new(HistogramState {
+				counts: Vec::new(),
+				sum: 0.0,
+				count: 0,
+			}),
+		}
+	}
+
+	pub fn observe(&self, duration: Duration) {
+		let seconds = duration.as_secs_f64();
+		let mut state = self.state.lock().unwrap();
+		state.counts.resize(self.buckets.len(), 0);
+		if let Some(bucket) = self.buckets.iter().position(|b| seconds <= *b) {
+			state.counts[bucket] += 1;
+		}
+		state.sum += seconds;
+		state.count += 1;
+	}
+
+	fn render(&self, out: &mut String) {
+		header(out, self.name, self.help, "histogram");
+		let state = self.state.lock().unwrap();
+
+		let mut cumulative = 0;
+		for (i, bucket) in self.buckets.iter().enumerate() {
+			cumulative += state.counts.get(i).copied().unwrap_or(0);
+			_ = writeln!(
+				out,
+				"{}_bucket{{le=\"{}\"}} {}",
+				self.name, bucket, cumulative
+			);
+		}
+		_ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", self.name, state.count);
+		_ = writeln!(out, "{}_sum {}", self.name, state.sum);
+		_ = writeln!(out, "{}_count {}", self.name, state.count);
+	}
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+	_ = writeln!(out, "# HELP {} {}", name, help);
+	_ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value, as plugin ids can hold any character.
+fn escape(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
@@ -0,0 +1,88 @@
+use std::{
+	fs::OpenOptions,
+	io::Write,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use log::{error, info};
+use serde::Serialize;
+
+use crate::moderation::unix_time;
+
+/// # AuditAction
+/// Something a moderator did.
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+	Kick,
+	Ban,
+	Unban,
+	Mute,
+	Unmute,
+	SetRole,
+}
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+	time: u64,
+	moderator: &'a str,
+	action: AuditAction,
+	target: &'a str,
+	/// how long a ban or mute lasts, if it isn't permanent.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	duration_secs: Option<u64>,
+	reason: &'a str,
+}
+
+/// # AuditLog
+/// Records moderation actions, one json object per line.
+#[derive(Debug)]
+pub struct AuditLog {
+	path: PathBuf,
+}
+
+impl AuditLog {
+	pub fn new(path: &Path) -> Self {
+		Self {
+			path: path.to_path_buf(),
+		}
+	}
+
+	pub fn record(
+		&self,
+		moderator: &str,
+		action: AuditAction,
+		target: &str,
+		duration: Option<Duration>,
+		reason: &str,
+	) {
+		info!(
+			moderator = moderator, target:% = target;
+			"{:?}: {}", action, reason
+		);
+
+		let entry = AuditEntry {
+			time: unix_time(),
+			moderator,
+			action,
+			target,
+			duration_secs: duration.map(|d| d.as_secs()),
+			reason,
+		};
+		let result = serde_json::to_string(&entry)
+			.map_err(|e| e.to_string())
+			.and_then(|line| {
+				OpenOptions::new()
+					.create(true)
+					.append(true)
+					.open(&self.path)
+					.and_then(|mut file| writeln!(file, "{}", line))
+					.map_err(|e| e.to_string())
+			});
+
+		if let Err(e) = result {
+			error!(path:? = self.path; "failed to write: {}", e);
+		}
+	}
+}
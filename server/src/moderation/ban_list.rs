@@ -0,0 +1,178 @@
+use std::{
+	fmt,
+	fs,
+	io,
+	net::IpAddr,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::moderation::{format_duration, unix_time};
+
+/// # BanTarget
+/// Who a ban applies to.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(tag = "type", content = "value")]
+pub enum BanTarget {
+	Uuid(Uuid),
+	/// matched ignoring case.
+	Username(String),
+	Ip(IpAddr),
+}
+
+impl BanTarget {
+	/// Reads an ip address or uuid, treating anything else as a username.
+	pub fn parse(target: &str) -> Self {
+		if let Ok(ip) = target.parse() {
+			return Self::Ip(ip);
+		}
+		if let Ok(uuid) = target.parse() {
+			return Self::Uuid(uuid);
+		}
+		Self::Username(target.to_string())
+	}
+
+	pub fn matches(&self, uuid: Uuid, username: &str, ip: IpAddr) -> bool {
+		match self {
+			Self::Uuid(banned) => *banned == uuid,
+			Self::Username(banned) => banned.eq_ignore_ascii_case(username),
+			Self::Ip(banned) => *banned == ip,
+		}
+	}
+}
+
+impl fmt::Display for BanTarget {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Uuid(uuid) => write!(f, "{}", uuid),
+			Self::Username(username) => write!(f, "{}", username),
+			Self::Ip(ip) => write!(f, "{}", ip),
+		}
+	}
+}
+
+/// # Ban
+/// Stops matching clients from connecting until it expires.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ban {
+	pub target: BanTarget,
+	pub reason: String,
+	/// the moderator who made the ban.
+	pub by: String,
+	/// the unix time the ban was made.
+	pub created: u64,
+	/// the unix time the ban ends, or none if it's permanent.
+	pub expires: Option<u64>,
+}
+
+impl Ban {
+	pub fn new(
+		target: BanTarget,
+		reason: String,
+		by: String,
+		duration: Option<Duration>,
+	) -> Self {
+		let created = unix_time();
+		Self {
+			target,
+			reason,
+			by,
+			created,
+			expires: duration.map(|d| created.saturating_add(d.as_secs())),
+		}
+	}
+
+	pub fn is_expired(&self) -> bool {
+		self.expires.is_some_and(|expires| expires <= unix_time())
+	}
+
+	/// What a banned client is told when they're turned away.
+	pub fn describe(&self) -> String {
+		let mut description = String::from("you are banned");
+		if !self.reason.is_empty() {
+			description = format!("{}: {}", description, self.reason);
+		}
+		if let Some(expires) = self.expires {
+			let left = Duration::from_secs(expires.saturating_sub(unix_time()));
+			description = format!("{} ({} left)", description, format_duration(left));
+		}
+		description
+	}
+}
+
+/// # BanList
+/// The bans in effect, saved as json whenever they change.
+#[derive(Debug)]
+pub struct BanList {
+	path: PathBuf,
+	bans: Vec<Ban>,
+}
+
+impl BanList {
+	/// Loads the bans from the path.
+	/// If the file doesn't exist no one is banned.
+	/// An invalid file is an error rather than being emptied,
+	/// as the next save would overwrite it.
+	pub fn load(path: &Path) -> Result<Self, String> {
+		let bans = match fs::read_to_string(path) {
+			Ok(json) => serde_json::from_str(&json)
+				.map_err(|e| format!("invalid ban list {}: {}", path.display(), e))?,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+			Err(e) => return Err(format!("can't read {}: {}", path.display(), e)),
+		};
+
+		Ok(Self {
+			path: path.to_path_buf(),
+			bans,
+		})
+	}
+
+	/// Adds the ban, replacing any existing ban on the same target.
+	pub fn add(&mut self, ban: Ban) {
+		self.bans.retain(|b| b.target != ban.target);
+		self.bans.push(ban);
+		self.save();
+	}
+
+	/// Returns false if the target wasn't banned.
+	pub fn remove(&mut self, target: &BanTarget) -> bool {
+		let before = self.bans.len();
+		self.bans.retain(|b| b.target != *target);
+		let removed = self.bans.len() != before;
+
+		if removed {
+			self.save();
+		}
+		removed
+	}
+
+	/// The ban stopping the client from connecting, if there is one.
+	pub fn find(&self, uuid: Uuid, username: &str, ip: IpAddr) -> Option<&Ban> {
+		self
+			.bans
+			.iter()
+			.find(|b| !b.is_expired() && b.target.matches(uuid, username, ip))
+	}
+
+	/// How many bans are in effect.
+	pub fn count(&self) -> usize {
+		self.bans.iter().filter(|b| !b.is_expired()).count()
+	}
+
+	/// Expired bans are dropped when the list is saved.
+	fn save(&mut self) {
+		self.bans.retain(|b| !b.is_expired());
+
+		let result = serde_json::to_string_pretty(&self.bans)
+			.map_err(|e| e.to_string())
+			.and_then(|json| fs::write(&self.path, json).map_err(|e| e.to_string()));
+
+		if let Err(e) = result {
+			error!(path:? = self.path; "failed to save bans: {}", e);
+		}
+	}
+}
@@ -0,0 +1,56 @@
+//! Removing and silencing misbehaving users.
+//!
+//! Bans are saved to disk and checked whenever a client connects.
+//! Mutes only last while the server is running.
+//! Every action a moderator takes is written to the audit log.
+
+mod audit_log;
+mod ban_list;
+mod mute_list;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub use audit_log::{AuditAction, AuditLog};
+pub use ban_list::{Ban, BanList, BanTarget};
+pub use mute_list::MuteList;
+
+/// Parses a duration such as "30s", "10m", "2h" or "7d".
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+	let split = duration.len().checked_sub(1)?;
+	let (amount, unit) = duration.split_at(split);
+	let amount: u64 = amount.parse().ok()?;
+
+	let secs = match unit {
+		"s" => amount,
+		"m" => amount.checked_mul(60)?,
+		"h" => amount.checked_mul(60 * 60)?,
+		"d" => amount.checked_mul(24 * 60 * 60)?,
+		_ => return None,
+	};
+	Some(Duration::from_secs(secs))
+}
+
+/// Describes a duration in its largest whole unit, such as "3 hours".
+pub fn format_duration(duration: Duration) -> String {
+	let secs = duration.as_secs();
+	let (amount, unit) = match secs {
+		0..=59 => (secs, "second"),
+		60..=3599 => (secs / 60, "minute"),
+		3600..=86399 => (secs / 3600, "hour"),
+		_ => (secs / 86400, "day"),
+	};
+
+	if amount == 1 {
+		format!("1 {}", unit)
+	} else {
+		format!("{} {}s", amount, unit)
+	}
+}
+
+/// Seconds since the unix epoch, as saved in bans and the audit log.
+pub fn unix_time() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_secs()
+}
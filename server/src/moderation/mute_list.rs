@@ -0,0 +1,39 @@
+use std::{
+	collections::HashMap,
+	time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+/// # MuteList
+/// Users who can't send global messages,
+/// until they're unmuted or their mute expires.
+#[derive(Debug, Default)]
+pub struct MuteList {
+	/// when each mute ends, or none if it lasts until the user is unmuted.
+	mutes: HashMap<Uuid, Option<Instant>>,
+}
+
+impl MuteList {
+	pub fn mute(&mut self, uuid: Uuid, duration: Option<Duration>) {
+		self
+			.mutes
+			.insert(uuid, duration.map(|d| Instant::now() + d));
+	}
+
+	/// Returns false if the user wasn't muted.
+	pub fn unmute(&mut self, uuid: Uuid) -> bool {
+		self.mutes.remove(&uuid).is_some()
+	}
+
+	pub fn is_muted(&mut self, uuid: Uuid) -> bool {
+		match self.mutes.get(&uuid) {
+			Some(Some(ends)) if *ends <= Instant::now() => {
+				self.mutes.remove(&uuid);
+				false
+			}
+			Some(_) => true,
+			None => false,
+		}
+	}
+}
@@ -0,0 +1,246 @@
+use std::{
+	collections::HashMap,
+	convert::TryFrom,
+	fmt,
+	net::{IpAddr, SocketAddr},
+	str::FromStr,
+	sync::{Arc, Mutex},
+};
+
+use log::info;
+use serde::Deserialize;
+
+use crate::{config::ConnectionConfig, metrics::METRICS};
+
+/// # IpNetwork
+/// A single address, or a range of them written as `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpNetwork {
+	address: IpAddr,
+	prefix: u8,
+}
+
+impl IpNetwork {
+	pub fn contains(&self, ip: IpAddr) -> bool {
+		let prefix = self.prefix as u32;
+		// ipv4 clients of a dual stack socket show up as ipv6
+		match (self.address, ip.to_canonical()) {
+			(IpAddr::V4(network), IpAddr::V4(ip)) => {
+				let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+				u32::from(network) & mask == u32::from(ip) & mask
+			}
+			(IpAddr::V6(network), IpAddr::V6(ip)) => {
+				let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+				u128::from(network) & mask == u128::from(ip) & mask
+			}
+			_ => false,
+		}
+	}
+}
+
+impl FromStr for IpNetwork {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (address, prefix) = match s.split_once('/') {
+			Some((address, prefix)) => (address, Some(prefix)),
+			None => (s, None),
+		};
+		let address: IpAddr = address
+			.parse()
+			.map_err(|_| format!("{} is not an ip address", address))?;
+		let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+
+		let prefix = match prefix {
+			Some(prefix) => prefix
+				.parse()
+				.ok()
+				.filter(|length| *length <= max_prefix)
+				.ok_or_else(|| format!("{} is not a valid prefix", prefix))?,
+			None => max_prefix,
+		};
+		Ok(Self { address, prefix })
+	}
+}
+
+impl TryFrom<String> for IpNetwork {
+	type Error = String;
+
+	fn try_from(s: String) -> Result<Self, Self::Error> {
+		s.parse()
+	}
+}
+
+impl fmt::Display for IpNetwork {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}/{}", self.address, self.prefix)
+	}
+}
+
+/// # ConnectionLimiter
+/// Decides which addresses may connect, and how many connections each may hold.
+/// Every accepted connection holds a [ConnectionPermit] until it closes.
+pub struct ConnectionLimiter {
+	state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+	config: ConnectionConfig,
+	total: usize,
+	by_ip: HashMap<IpAddr, usize>,
+}
+
+impl ConnectionLimiter {
+	pub fn new(config: ConnectionConfig) -> Arc<Self> {
+		Arc::new(Self {
+			state: Mutex::new(LimiterState {
+				config,
+				total: 0,
+				by_ip: HashMap::new(),
+			}),
+		})
+	}
+
+	/// Replaces the config.
+	/// Open connections are kept, even if they are now over a limit.
+	pub fn configure(&self, config: ConnectionConfig) {
+		self.state.lock().unwrap().config = config;
+	}
+
+	pub fn config(&self) -> ConnectionConfig {
+		self.state.lock().unwrap().config.clone()
+	}
+
+	/// Takes a permit for a new connection from the address,
+	/// or says why it isn't allowed.
+	pub fn acquire(
+		self: &Arc<Self>,
+		ip: IpAddr,
+	) -> Result<ConnectionPermit, &'static str> {
+		let ip = ip.to_canonical();
+		let mut state = self.state.lock().unwrap();
+		let config = &state.config;
+
+		if config.deny.iter().any(|network| network.contains(ip)) {
+			return Err("address is denied");
+		}
+		if !config.allow.is_empty()
+			&& !config.allow.iter().any(|network| network.contains(ip))
+		{
+			return Err("address is not allowed");
+		}
+		if config.max_connections != 0 && state.total >= config.max_connections {
+			return Err("too many connections");
+		}
+		let open = state.by_ip.get(&ip).copied().unwrap_or(0);
+		if config.max_per_ip != 0 && open >= config.max_per_ip {
+			return Err("too many connections from this address");
+		}
+
+		state.total += 1;
+		state.by_ip.insert(ip, open + 1);
+		Ok(ConnectionPermit {
+			limiter: self.clone(),
+			ip,
+		})
+	}
+
+	/// Takes a permit for a connection as it's accepted,
+	/// so refused clients are dropped before anything is read from them.
+	pub fn admit(self: &Arc<Self>, addr: SocketAddr) -> Option<ConnectionPermit> {
+		match self.acquire(addr.ip()) {
+			Ok(permit) => Some(permit),
+			Err(reason) => {
+				info!(addr:% = addr; "refusing connection: {}", reason);
+				METRICS.handshakes.inc("refused");
+				None
+			}
+		}
+	}
+
+	fn release(&self, ip: IpAddr) {
+		let mut state = self.state.lock().unwrap();
+		state.total -= 1;
+		if let Some(open) = state.by_ip.get_mut(&ip) {
+			*open -= 1;
+			if *open == 0 {
+				state.by_ip.remove(&ip);
+			}
+		}
+	}
+}
+
+/// # ConnectionPermit
+/// Counts a connection against the limits until it is dropped.
+pub struct ConnectionPermit {
+	limiter: Arc<ConnectionLimiter>,
+	ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+	fn drop(&mut self) {
+		self.limiter.release(self.ip);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ip(ip: &str) -> IpAddr {
+		ip.parse().unwrap()
+	}
+
+	#[test]
+	fn networks_contain_their_addresses() {
+		let network: IpNetwork = "10.0.0.0/8".parse().unwrap();
+		assert!(network.contains(ip("10.1.2.3")));
+		assert!(!network.contains(ip("11.0.0.1")));
+		// ipv4 clients of a dual stack socket
+		assert!(network.contains(ip("::ffff:10.0.0.1")));
+	}
+
+	#[test]
+	fn invalid_networks_are_refused() {
+		assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+		assert!("not an ip".parse::<IpNetwork>().is_err());
+	}
+
+	#[test]
+	fn permits_are_limited_per_address() {
+		let limiter = ConnectionLimiter::new(ConnectionConfig {
+			max_per_ip: 2,
+			..Default::default()
+		});
+		let first = limiter.acquire(ip("10.0.0.1")).unwrap();
+		let _second = limiter.acquire(ip("10.0.0.1")).unwrap();
+		assert!(limiter.acquire(ip("10.0.0.1")).is_err());
+		assert!(limiter.acquire(ip("10.0.0.2")).is_ok());
+
+		drop(first);
+		assert!(limiter.acquire(ip("10.0.0.1")).is_ok());
+	}
+
+	#[test]
+	fn permits_are_limited_overall() {
+		let limiter = ConnectionLimiter::new(ConnectionConfig {
+			max_connections: 1,
+			..Default::default()
+		});
+		let _first = limiter.acquire(ip("10.0.0.1")).unwrap();
+		assert!(limiter.acquire(ip("10.0.0.2")).is_err());
+	}
+
+	#[test]
+	fn denied_and_unlisted_addresses_are_refused() {
+		let limiter = ConnectionLimiter::new(ConnectionConfig {
+			allow: vec!["10.0.0.0/8".parse().unwrap()],
+			deny: vec!["10.0.0.1".parse().unwrap()],
+			..Default::default()
+		});
+		assert!(limiter.acquire(ip("10.0.0.1")).is_err());
+		assert!(limiter.acquire(ip("192.168.0.1")).is_err());
+		assert!(limiter.acquire(ip("10.0.0.2")).is_ok());
+	}
+}
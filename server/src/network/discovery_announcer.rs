@@ -0,0 +1,184 @@
+use std::{
+	collections::HashMap,
+	io,
+	net::{IpAddr, Ipv4Addr},
+	time::{Duration, Instant},
+};
+
+use foundation::{
+	networking::discovery::{decode_datagram, encode_datagram},
+	prelude::{DiscoveryRequest, DiscoveryResponse},
+};
+use log::{debug, error, trace, warn};
+use tokio::{
+	net::UdpSocket,
+	sync::{mpsc::UnboundedSender, oneshot},
+	task::JoinHandle,
+};
+
+use crate::{
+	config::DiscoveryConfig,
+	connection::connection_manager::ConnectionManagerMessage,
+	network::PORTS,
+};
+
+/// how often each address may be answered.
+const REPLY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// how many addresses are remembered before old ones are forgotten.
+const TRACKED_SOURCES: usize = 1024;
+
+/// # DiscoveryAnnouncer
+/// Answers discovery requests broadcast by clients on the local network,
+/// with the servers current info and the ports it listens on.
+///
+/// Requests from outside the local network are ignored,
+/// and each address is answered at most once a second,
+/// so the server can't be used to flood others with responses.
+pub struct DiscoveryAnnouncer {
+	socket: UdpSocket,
+	connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+}
+
+impl DiscoveryAnnouncer {
+	pub async fn new(
+		config: &DiscoveryConfig,
+		connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+	) -> io::Result<Self> {
+		debug!("setting up socket");
+		let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.port)).await?;
+
+		Ok(Self {
+			socket,
+			connection_manager_sender,
+		})
+	}
+
+	pub async fn run(&self) {
+		let mut buffer = vec![0; u16::MAX as usize];
+		let mut limiter = ReplyLimiter::default();
+
+		loop {
+			trace!("waiting for request");
+			let Ok((length, addr)) = self.socket.recv_from(&mut buffer).await else {
+				warn!("receive failed");
+				continue;
+			};
+
+			if !is_local(addr.ip()) {
+				trace!(addr:% = addr; "ignoring request from outside the network");
+				continue;
+			}
+			if decode_datagram::<DiscoveryRequest>(&buffer[..length]).is_err() {
+				continue;
+			}
+			if !limiter.allow(addr.ip(), Instant::now()) {
+				trace!(addr:% = addr; "ignoring request, answered recently");
+				continue;
+			}
+
+			let Some(response) = self.get_response().await else {
+				return;
+			};
+
+			debug!(addr:% = addr; "answering");
+			let datagram = encode_datagram(&response);
+			if let Err(e) = self.socket.send_to(&datagram, addr).await {
+				warn!(addr:% = addr; "failed to answer: {}", e);
+			}
+		}
+	}
+
+	/// Gets the servers info from the connection manager,
+	/// or none if it has shut down.
+	async fn get_response(&self) -> Option<DiscoveryResponse> {
+		let (reply, info) = oneshot::channel();
+		self
+			.connection_manager_sender
+			.send(ConnectionManagerMessage::GetInfo { reply })
+			.ok()?;
+
+		Some(DiscoveryResponse {
+			info: Some(info.await.ok()?),
+			ports: PORTS
+				.iter()
+				.map(|(protocol, port)| (protocol.to_string(), *port as u32))
+				.collect(),
+		})
+	}
+
+	pub fn start_run(
+		config: DiscoveryConfig,
+		connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+	) -> JoinHandle<()> {
+		tokio::spawn(async move {
+			match DiscoveryAnnouncer::new(&config, connection_manager_sender).await {
+				Ok(announcer) => announcer.run().await,
+				Err(e) => error!("failed to bind: {}", e),
+			}
+		})
+	}
+}
+
+/// Whether the address is on a local network,
+/// as discovery requests are only broadcast there.
+fn is_local(ip: IpAddr) -> bool {
+	match ip {
+		IpAddr::V4(ip) => ip.is_private() || ip.is_link_local() || ip.is_loopback(),
+		IpAddr::V6(_) => false,
+	}
+}
+
+/// Remembers when each address was last answered.
+#[derive(Default)]
+struct ReplyLimiter {
+	answered: HashMap<IpAddr, Instant>,
+}
+
+impl ReplyLimiter {
+	/// Whether the address may be answered now, remembering it if so.
+	fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+		if let Some(last) = self.answered.get(&ip) {
+			if now.duration_since(*last) < REPLY_INTERVAL {
+				return false;
+			}
+		}
+
+		if self.answered.len() >= TRACKED_SOURCES {
+			self
+				.answered
+				.retain(|_, last| now.duration_since(*last) < REPLY_INTERVAL);
+		}
+		self.answered.insert(ip, now);
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ip(ip: &str) -> IpAddr {
+		ip.parse().unwrap()
+	}
+
+	#[test]
+	fn only_local_addresses_are_answered() {
+		assert!(is_local(ip("192.168.1.20")));
+		assert!(is_local(ip("10.0.0.5")));
+		assert!(is_local(ip("169.254.3.4")));
+		assert!(is_local(ip("127.0.0.1")));
+		assert!(!is_local(ip("8.8.8.8")));
+		assert!(!is_local(ip("::1")));
+	}
+
+	#[test]
+	fn addresses_are_answered_once_a_second() {
+		let mut limiter = ReplyLimiter::default();
+		let now = Instant::now();
+		assert!(limiter.allow(ip("10.0.0.5"), now));
+		assert!(!limiter.allow(ip("10.0.0.5"), now));
+		assert!(limiter.allow(ip("10.0.0.6"), now));
+		assert!(limiter.allow(ip("10.0.0.5"), now + REPLY_INTERVAL));
+	}
+}
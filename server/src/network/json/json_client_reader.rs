@@ -4,32 +4,40 @@ use foundation::{
 	messages::client::ClientStreamIn,
 	networking::json::read_message,
 };
+use log::{debug, info};
 use tokio::{io::ReadHalf, net::TcpStream, sync::mpsc::UnboundedSender};
 use uuid::Uuid;
 
 use crate::{
 	connection::connection_manager::ConnectionManagerMessage,
-	network::ClientReader,
+	network::{ClientReader, NetworkStream},
 };
 
-pub struct JSONClientReader {
-	reader: ReadHalf<TcpStream>,
+pub struct JSONClientReader<S = TcpStream> {
+	reader: ReadHalf<S>,
 	addr: SocketAddr,
 	uuid: Uuid,
+	session: Uuid,
 }
 
-impl JSONClientReader {
+impl<S: NetworkStream> JSONClientReader<S> {
 	pub fn new(
-		reader: ReadHalf<TcpStream>,
+		reader: ReadHalf<S>,
 		addr: SocketAddr,
 		uuid: Uuid,
+		session: Uuid,
 	) -> Self {
-		Self { reader, addr, uuid }
+		Self {
+			reader,
+			addr,
+			uuid,
+			session,
+		}
 	}
 
 	// move to other one
 	pub async fn get_message(&mut self) -> io::Result<ClientStreamIn> {
-		read_message::<ReadHalf<TcpStream>, ClientStreamIn>(&mut self.reader).await
+		read_message::<ReadHalf<S>, ClientStreamIn>(&mut self.reader).await
 	}
 
 	pub fn handle_message(
@@ -37,21 +45,22 @@ impl JSONClientReader {
 		msg: ClientStreamIn,
 		channel: &UnboundedSender<ConnectionManagerMessage>,
 	) {
-		println!("[JSONClientReader:{}] got message", self.addr);
+		debug!(addr:% = self.addr; "got message");
 
 		let uuid = self.uuid;
+		let session = self.session;
 
 		_ = match msg {
 			ClientStreamIn::GetClients => {
-				channel.send(ConnectionManagerMessage::SendClientsTo { uuid })
-			}
-			ClientStreamIn::GetMessages => {
-				channel.send(ConnectionManagerMessage::SendGlobalMessages { uuid })
+				channel.send(ConnectionManagerMessage::SendClientsTo { uuid, session })
 			}
+			ClientStreamIn::GetMessages => channel
+				.send(ConnectionManagerMessage::SendGlobalMessages { uuid, session }),
 			ClientStreamIn::SendMessage { to, content } => {
 				channel.send(ConnectionManagerMessage::SendPrivateMessage {
 					uuid: Uuid::new_v4(),
 					from: uuid,
+					session,
 					to,
 					content,
 				})
@@ -62,14 +71,17 @@ impl JSONClientReader {
 					content,
 				})
 			}
+			ClientStreamIn::Pong => {
+				channel.send(ConnectionManagerMessage::ReceivedPong { uuid, session })
+			}
 			ClientStreamIn::Disconnect => {
-				channel.send(ConnectionManagerMessage::Disconnect { uuid })
+				channel.send(ConnectionManagerMessage::Disconnect { uuid, session })
 			}
 		};
 	}
 }
 
-impl ClientReader for JSONClientReader {
+impl<S: NetworkStream> ClientReader for JSONClientReader<S> {
 	fn start_run(
 		mut self: Box<Self>,
 		uuid: Uuid,
@@ -81,12 +93,15 @@ impl ClientReader for JSONClientReader {
 
 				let Ok(msg) = msg else {
 					let error = msg.unwrap_err();
-					println!(
-						"[JSONClientReader:{}] errored with '{}' disconnecting",
-						self.addr, error
+					info!(
+						addr:% = self.addr;
+						"errored with '{}', disconnecting", error
 					);
 
-					_ = channel.send(ConnectionManagerMessage::Disconnected { uuid });
+					_ = channel.send(ConnectionManagerMessage::Disconnected {
+						uuid,
+						session: self.session,
+					});
 
 					return;
 				};
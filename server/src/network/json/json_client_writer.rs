@@ -9,30 +9,27 @@ use foundation::{
 	prelude::{GlobalMessage, PrivateMessage},
 	ClientDetails,
 };
+use log::{debug, trace};
 use tokio::{io::WriteHalf, net::TcpStream};
 use uuid::Uuid;
 
-use crate::network::ClientWriter;
+use crate::network::{ClientWriter, NetworkStream};
 
 #[allow(dead_code)]
-pub struct JSONClientWriter {
-	writer: WriteHalf<TcpStream>,
+pub struct JSONClientWriter<S = TcpStream> {
+	writer: WriteHalf<S>,
 	addr: SocketAddr,
 	uuid: Uuid,
 }
 
-impl JSONClientWriter {
-	pub fn new(
-		writer: WriteHalf<TcpStream>,
-		addr: SocketAddr,
-		uuid: Uuid,
-	) -> Self {
+impl<S: NetworkStream> JSONClientWriter<S> {
+	pub fn new(writer: WriteHalf<S>, addr: SocketAddr, uuid: Uuid) -> Self {
 		Self { writer, addr, uuid }
 	}
 }
 
 #[async_trait]
-impl ClientWriter for JSONClientWriter {
+impl<S: NetworkStream> ClientWriter for JSONClientWriter<S> {
 	async fn send_clients(
 		&mut self,
 		clients: Vec<foundation::prelude::ClientDetails>,
@@ -45,10 +42,11 @@ impl ClientWriter for JSONClientWriter {
 					username: c.name,
 					address: c.address,
 					public_key: None,
+					role: c.role,
 				})
 				.collect(),
 		};
-		println!("[JSONClientWriter:{}] sending clients", self.addr);
+		debug!(addr:% = self.addr; "sending clients");
 		write_message(&mut self.writer, message).await;
 	}
 
@@ -59,20 +57,21 @@ impl ClientWriter for JSONClientWriter {
 		let message = ClientStreamOut::ClientConnected {
 			id: details.uuid.parse().unwrap(),
 			username: details.name,
+			role: details.role,
 		};
-		println!(
-			"[JSONClientReader:{}] sending client connected message",
-			self.addr
-		);
+		debug!(addr:% = self.addr; "sending client connected");
 		write_message(&mut self.writer, message).await;
 	}
 
 	async fn send_client_left(&mut self, uuid: Uuid) {
 		let message = ClientStreamOut::ClientRemoved { id: uuid };
-		println!(
-			"[JSONClientReader:{}] sending client connected message",
-			self.addr
-		);
+		debug!(addr:% = self.addr; "sending client connected");
+		write_message(&mut self.writer, message).await;
+	}
+
+	async fn send_client_renamed(&mut self, uuid: Uuid, username: String) {
+		let message = ClientStreamOut::ClientRenamed { id: uuid, username };
+		debug!(addr:% = self.addr; "sending client renamed");
 		write_message(&mut self.writer, message).await;
 	}
 
@@ -88,16 +87,17 @@ impl ClientWriter for JSONClientWriter {
 				})
 				.collect(),
 		};
-		println!("[JSONClientWriter:{}] sending global messages", self.addr);
+		debug!(addr:% = self.addr; "sending global messages");
 		write_message(&mut self.writer, message).await;
 	}
 
 	async fn send_private_message(&mut self, message: PrivateMessage) {
 		let message = ClientStreamOut::UserMessage {
 			from: message.from.parse().unwrap(),
+			to: message.to.parse().unwrap(),
 			content: message.content,
 		};
-		println!("[JSONClientWriter:{}] sending private message", self.addr);
+		debug!(addr:% = self.addr; "sending private message");
 		write_message(&mut self.writer, message).await;
 	}
 
@@ -109,9 +109,15 @@ impl ClientWriter for JSONClientWriter {
 		write_message(&mut self.writer, message).await;
 	}
 
-	async fn send_disconnect(&mut self) {
-		let message = ClientStreamOut::Disconnected;
-		println!("[JSONClientWriter:{}] sending disconnect", self.addr);
+	async fn send_disconnect(&mut self, reason: String) {
+		let message = ClientStreamOut::Disconnected { reason };
+		debug!(addr:% = self.addr; "sending disconnect");
+		write_message(&mut self.writer, message).await;
+	}
+
+	async fn send_ping(&mut self) {
+		let message = ClientStreamOut::Ping;
+		trace!(addr:% = self.addr; "sending ping");
 		write_message(&mut self.writer, message).await;
 	}
 }
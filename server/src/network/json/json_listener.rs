@@ -1,8 +1,16 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
+use log::{debug, trace, warn};
 use tokio::{net::TcpListener, sync::mpsc::UnboundedSender, task::JoinHandle};
 
 use crate::{
-	network::{ConnectionType, NetworkListener},
+	metrics::METRICS,
+	network::{
+		connection_limiter::ConnectionLimiter,
+		ConnectionType,
+		NetworkListener,
+	},
 	server_va::ServerMessages,
 };
 
@@ -12,43 +20,59 @@ use crate::{
 pub struct JSONListener {
 	listener: TcpListener,
 	sender: UnboundedSender<ServerMessages>,
+	limiter: Arc<ConnectionLimiter>,
 }
 
 #[async_trait]
 impl NetworkListener for JSONListener {
 	/// Binds listeners and stores them in the ListenerManager
-	async fn new(sender: UnboundedSender<ServerMessages>) -> Self {
+	async fn new(
+		sender: UnboundedSender<ServerMessages>,
+		limiter: Arc<ConnectionLimiter>,
+	) -> Self {
 		let address = "0.0.0.0:5600";
 
-		println!("[JSONListener] setting up listeners");
+		debug!("setting up listener");
 		let listener = TcpListener::bind(address)
 			.await
 			.expect("[JSONListener] failed to bind to 0.0.0.0:5600");
 
-		Self { listener, sender }
+		Self {
+			listener,
+			sender,
+			limiter,
+		}
 	}
 
 	async fn run(&self) {
 		loop {
-			println!("[JSONListener] waiting for connection");
+			trace!("waiting for connection");
 			let accept_protobuf = self.listener.accept().await;
 
 			let Ok((stream, addr)) = accept_protobuf else {
-				println!("[JSONListener] accept failed");
+				warn!("accept failed");
+				continue;
+			};
+			METRICS.accepted.inc("json");
+			let Some(permit) = self.limiter.admit(addr) else {
 				continue;
 			};
 
-			let msg = ServerMessages::NewConnection(ConnectionType::JsonConnection(
-				stream, addr,
-			));
-			println!("[JSONListener] passing message to server");
+			let msg = ServerMessages::NewConnection {
+				connection: ConnectionType::JsonConnection(stream, addr),
+				permit,
+			};
+			debug!(addr:% = addr; "accepted connection");
 			_ = self.sender.send(msg);
 		}
 	}
 
-	fn start_run(sender: UnboundedSender<ServerMessages>) -> JoinHandle<()> {
+	fn start_run(
+		sender: UnboundedSender<ServerMessages>,
+		limiter: Arc<ConnectionLimiter>,
+	) -> JoinHandle<()> {
 		tokio::spawn(async move {
-			JSONListener::new(sender).await.run().await;
+			JSONListener::new(sender, limiter).await.run().await;
 		})
 	}
 }
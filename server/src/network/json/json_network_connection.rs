@@ -2,46 +2,99 @@ use std::{io, net::SocketAddr};
 
 use foundation::{
 	messages::network::{NetworkSockIn, NetworkSockOut},
-	networking::json::{read_message, write_message},
+	networking::{
+		handshake::{
+			capabilities,
+			check_version,
+			negotiate_capabilities,
+			MIN_PROTOCOL_VERSION,
+			PROTOCOL_VERSION,
+		},
+		json::{read_message, write_message},
+	},
+	prelude::Info,
 };
+use log::{debug, info, trace};
 use tokio::{io::split, net::TcpStream};
 use uuid::Uuid;
 
-use crate::network::{
-	json::{
-		json_client_reader::JSONClientReader,
-		json_client_writer::JSONClientWriter,
+use crate::{
+	account::Credentials,
+	metrics::METRICS,
+	network::{
+		json::{
+			json_client_reader::JSONClientReader,
+			json_client_writer::JSONClientWriter,
+		},
+		metered_stream::MeteredStream,
+		ClientReader,
+		ClientWriter,
+		ConnectRequest,
+		NetworkConnection,
+		NetworkStream,
+		ServerRequest,
 	},
-	ClientReader,
-	ClientWriter,
-	NetworkConnection,
-	ServerRequest,
 };
 
-pub struct JSONNetworkConnection {
-	pub(super) stream: TcpStream,
+pub struct JSONNetworkConnection<S = TcpStream> {
+	pub(super) stream: MeteredStream<S>,
 	pub(super) addr: SocketAddr,
+	/// the version and capabilities agreed with the client.
+	version: u32,
+	capabilities: Vec<String>,
 }
 
-impl JSONNetworkConnection {
-	pub fn new(stream: TcpStream, addr: SocketAddr) -> Self {
-		Self { stream, addr }
+impl<S: NetworkStream> JSONNetworkConnection<S> {
+	pub fn new(stream: MeteredStream<S>, addr: SocketAddr) -> Self {
+		Self {
+			stream,
+			addr,
+			version: PROTOCOL_VERSION,
+			capabilities: Vec::new(),
+		}
+	}
+
+	/// Tells the client why it can't connect.
+	async fn reject(&mut self, reason: String) -> io::Error {
+		METRICS.handshakes.inc("rejected");
+		info!(addr:% = self.addr; "rejecting client: {}", reason);
+		write_message(
+			&mut self.stream,
+			NetworkSockOut::Rejected {
+				reason: reason.clone(),
+				min_version: MIN_PROTOCOL_VERSION,
+				max_version: PROTOCOL_VERSION,
+			},
+		)
+		.await;
+		io::Error::new(io::ErrorKind::InvalidData, reason)
 	}
 }
 
 #[async_trait::async_trait]
-impl NetworkConnection for JSONNetworkConnection {
-	async fn get_request(&mut self) -> io::Result<ServerRequest> {
-		println!("[JSONNetworkConnection] sending request");
+impl<S: NetworkStream> NetworkConnection for JSONNetworkConnection<S> {
+	fn protocol(&self) -> &'static str {
+		self.stream.protocol()
+	}
 
-		write_message(&mut self.stream, NetworkSockOut::Request).await;
+	async fn get_request(&mut self) -> io::Result<ServerRequest> {
+		debug!(addr:% = self.addr; "sending request");
 
-		println!("[JSONNetworkConnection] waiting for response");
+		write_message(
+			&mut self.stream,
+			NetworkSockOut::Request {
+				min_version: MIN_PROTOCOL_VERSION,
+				max_version: PROTOCOL_VERSION,
+				capabilities: capabilities(),
+			},
+		)
+		.await;
 
-		let request =
-			read_message::<TcpStream, NetworkSockIn>(&mut self.stream).await?;
+		trace!(addr:% = self.addr; "waiting for response");
 
-		println!("[JSONNetworkConnection] returning request");
+		let request = read_message::<_, NetworkSockIn>(&mut self.stream)
+			.await
+			.inspect_err(|_| METRICS.handshakes.inc("failed"))?;
 
 		match request {
 			NetworkSockIn::Info => Ok(ServerRequest::GetInfo),
@@ -49,39 +102,85 @@ impl NetworkConnection for JSONNetworkConnection {
 				uuid,
 				username,
 				address: _,
-			} => Ok(ServerRequest::Connect {
-				username,
-				uuid,
-				addr: self.addr,
-			}),
-			// _ => Ok(ServerRequest::Ignore),
+				session_token,
+				version,
+				capabilities,
+				password,
+				register,
+			} => {
+				self.version = match check_version(version) {
+					Ok(version) => version,
+					Err(reason) => return Err(self.reject(reason).await),
+				};
+				self.capabilities = negotiate_capabilities(self.version, &capabilities);
+
+				Ok(ServerRequest::Connect(ConnectRequest {
+					username,
+					uuid,
+					addr: self.addr,
+					session_token,
+					capabilities: self.capabilities.clone(),
+					credentials: password.map(|password| Credentials {
+						password: password.into(),
+						register,
+					}),
+				}))
+			} // _ => Ok(ServerRequest::Ignore),
 		}
 	}
 
-	async fn send_info(mut self: Box<Self>, name: String, owner: String) {
-		println!("[JSONNetworkConnection] Sending info to client");
+	async fn send_info(mut self: Box<Self>, info: Info) {
+		METRICS.handshakes.inc("info");
+		debug!(addr:% = self.addr; "sending info");
 		write_message(
 			&mut self.stream,
 			NetworkSockOut::GotInfo {
-				server_name: name,
-				server_owner: owner,
+				server_name: info.server_name,
+				server_owner: info.owner,
+				description: info.description,
+				server_version: info.server_version,
+				connected_users: info.connected_users,
+				max_users: info.max_users,
+				protocols: info.protocols,
+				capabilities: info.capabilities,
+				min_version: info.min_version,
+				max_version: info.max_version,
+				authentication_required: info.authentication_required,
 			},
 		)
 		.await;
+	}
 
-		println!("[JSONNetworkConnection] droping connection");
+	async fn send_rejected(mut self: Box<Self>, reason: String) {
+		self.reject(reason).await;
 	}
 
 	async fn send_connected(
 		mut self: Box<Self>,
 		uuid: Uuid,
+		session_token: Uuid,
+		resumed: bool,
 	) -> (Box<dyn ClientWriter>, Box<dyn ClientReader>) {
-		write_message(&mut self.stream, NetworkSockOut::Connected).await;
+		METRICS
+			.handshakes
+			.inc(if resumed { "resumed" } else { "connected" });
+		write_message(
+			&mut self.stream,
+			NetworkSockOut::Connected {
+				session_token,
+				resumed,
+				version: self.version,
+				capabilities: self.capabilities.clone(),
+				uuid,
+			},
+		)
+		.await;
 
 		let (read, write) = split(self.stream);
 
 		let writer = Box::new(JSONClientWriter::new(write, self.addr, uuid));
-		let reader = Box::new(JSONClientReader::new(read, self.addr, uuid));
+		let reader =
+			Box::new(JSONClientReader::new(read, self.addr, uuid, session_token));
 		(writer, reader)
 	}
 }
@@ -0,0 +1,84 @@
+use std::{
+	io,
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{
+	metrics::METRICS,
+	network::{connection_limiter::ConnectionPermit, NetworkStream},
+};
+
+/// # MeteredStream
+/// Counts the bytes read from and written to a client,
+/// under the protocol it connected with.
+/// The connections permit is held until the stream is dropped.
+pub struct MeteredStream<S> {
+	inner: S,
+	protocol: &'static str,
+	_permit: ConnectionPermit,
+}
+
+impl<S: NetworkStream> MeteredStream<S> {
+	pub fn new(
+		inner: S,
+		protocol: &'static str,
+		permit: ConnectionPermit,
+	) -> Self {
+		Self {
+			inner,
+			protocol,
+			_permit: permit,
+		}
+	}
+
+	pub fn protocol(&self) -> &'static str {
+		self.protocol
+	}
+}
+
+impl<S: NetworkStream> AsyncRead for MeteredStream<S> {
+	fn poll_read(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		let before = buf.filled().len();
+		let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+		let read = buf.filled().len() - before;
+		if read > 0 {
+			METRICS.bytes_received.add(self.protocol, read as u64);
+		}
+		result
+	}
+}
+
+impl<S: NetworkStream> AsyncWrite for MeteredStream<S> {
+	fn poll_write(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+		if let Poll::Ready(Ok(written)) = result {
+			METRICS.bytes_sent.add(self.protocol, written as u64);
+		}
+		result
+	}
+
+	fn poll_flush(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_shutdown(cx)
+	}
+}
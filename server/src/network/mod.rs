@@ -1,37 +1,104 @@
-use std::{io, net::SocketAddr};
+use std::{io, net::SocketAddr, sync::Arc};
 
 use async_trait::async_trait;
-use foundation::prelude::{ClientDetails, GlobalMessage, PrivateMessage};
-use tokio::{net::TcpStream, sync::mpsc::UnboundedSender, task::JoinHandle};
+use foundation::prelude::{ClientDetails, GlobalMessage, Info, PrivateMessage};
+use tokio::{
+	io::{AsyncRead, AsyncWrite},
+	net::TcpStream,
+	sync::mpsc::UnboundedSender,
+	task::JoinHandle,
+};
 use uuid::Uuid;
 
 use crate::{
+	account::Credentials,
 	connection::connection_manager::ConnectionManagerMessage,
+	network::{
+		connection_limiter::ConnectionLimiter,
+		websocket::websocket_stream::WebSocketByteStream,
+	},
 	server_va::ServerMessages,
 };
 
+pub mod connection_limiter;
+pub mod discovery_announcer;
 pub mod json;
+pub mod metered_stream;
 pub mod protobuf;
+pub mod unified_listener;
+pub mod websocket;
+
+/// The protocols clients can connect to the server with.
+pub const PROTOCOLS: &[&str] = &["protobuf", "json", "websocket"];
+
+/// The port each listener is bound to, keyed by protocol name.
+/// The unified port accepts every protocol.
+pub const PORTS: &[(&str, u16)] = &[
+	("protobuf", 6500),
+	("json", 5600),
+	("websocket", 5700),
+	("unified", 5800),
+];
+
+/// # NetworkStream
+/// Any byte stream a connection can be run over,
+/// such as a tcp socket or a websocket.
+pub trait NetworkStream:
+	AsyncRead + AsyncWrite + Unpin + Send + 'static
+{
+}
+
+impl<T> NetworkStream for T where
+	T: AsyncRead + AsyncWrite + Unpin + Send + 'static
+{
+}
 
 pub enum ConnectionType {
 	ProtobufConnection(TcpStream, SocketAddr),
 	JsonConnection(TcpStream, SocketAddr),
+	WebSocketConnection(Box<WebSocketByteStream>, SocketAddr),
 }
 
+impl ConnectionType {
+	pub fn addr(&self) -> SocketAddr {
+		match self {
+			Self::ProtobufConnection(_, addr)
+			| Self::JsonConnection(_, addr)
+			| Self::WebSocketConnection(_, addr) => *addr,
+		}
+	}
+}
+
+/// # NetworkListener
+/// Accepts connections on a port, passing them to the server.
+/// Each connection takes a permit from the limiter as it's accepted,
+/// and holds it through any upgrade or protocol detection.
 #[async_trait]
 pub trait NetworkListener {
-	async fn new(channel: UnboundedSender<ServerMessages>) -> Self;
+	async fn new(
+		channel: UnboundedSender<ServerMessages>,
+		limiter: Arc<ConnectionLimiter>,
+	) -> Self;
 	async fn run(&self);
-	fn start_run(sender: UnboundedSender<ServerMessages>) -> JoinHandle<()>;
+	fn start_run(
+		sender: UnboundedSender<ServerMessages>,
+		limiter: Arc<ConnectionLimiter>,
+	) -> JoinHandle<()>;
 }
 
 #[async_trait::async_trait]
 pub trait NetworkConnection: Send {
+	/// the protocol the client connected with, one of [PROTOCOLS].
+	fn protocol(&self) -> &'static str;
 	async fn get_request(&mut self) -> io::Result<ServerRequest>;
-	async fn send_info(self: Box<Self>, name: String, owner: String);
+	async fn send_info(self: Box<Self>, info: Info);
+	/// Tells the client it can't connect, and why.
+	async fn send_rejected(self: Box<Self>, reason: String);
 	async fn send_connected(
 		self: Box<Self>,
 		uuid: Uuid,
+		session_token: Uuid,
+		resumed: bool,
 	) -> (Box<dyn ClientWriter>, Box<dyn ClientReader>);
 }
 
@@ -50,17 +117,29 @@ pub trait ClientWriter: Send {
 	async fn send_global_messages(&mut self, messages: Vec<GlobalMessage>);
 	async fn send_global_message(&mut self, message: GlobalMessage);
 	async fn send_private_message(&mut self, message: PrivateMessage);
-	async fn send_disconnect(&mut self);
+	/// Tells the client it is being disconnected, and why.
+	async fn send_disconnect(&mut self, reason: String);
 	async fn send_client_joined(&mut self, details: ClientDetails);
 	async fn send_client_left(&mut self, uuid: Uuid);
+	async fn send_client_renamed(&mut self, uuid: Uuid, username: String);
+	async fn send_ping(&mut self);
 }
 
 pub enum ServerRequest {
 	GetInfo,
-	Connect {
-		username: String,
-		uuid: uuid::Uuid,
-		addr: SocketAddr,
-	},
+	Connect(ConnectRequest),
 	Ignore,
 }
+
+/// # ConnectRequest
+/// What a client sent to connect.
+pub struct ConnectRequest {
+	pub username: String,
+	pub uuid: Uuid,
+	pub addr: SocketAddr,
+	pub session_token: Option<Uuid>,
+	/// the optional features agreed with the client.
+	pub capabilities: Vec<String>,
+	/// set if the client is logging in to, or creating, an account.
+	pub credentials: Option<Credentials>,
+}
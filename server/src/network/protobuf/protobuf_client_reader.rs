@@ -8,39 +8,45 @@ use foundation::{
 		Disconnect,
 		GetClients,
 		GetGlobalMessages,
+		Pong,
 		SendGlobalMessage,
 		SendPrivateMessage,
 	},
 };
+use log::{debug, info};
 use tokio::{io::ReadHalf, net::TcpStream, sync::mpsc::UnboundedSender};
 use uuid::Uuid;
 
 use crate::{
 	connection::connection_manager::ConnectionManagerMessage,
-	network::ClientReader,
+	network::{ClientReader, NetworkStream},
 };
 
-pub struct ProtobufClientReader {
-	reader: ReadHalf<TcpStream>,
+pub struct ProtobufClientReader<S = TcpStream> {
+	reader: ReadHalf<S>,
 	addr: SocketAddr,
 	uuid: Uuid,
+	session: Uuid,
 }
 
-impl ProtobufClientReader {
+impl<S: NetworkStream> ProtobufClientReader<S> {
 	pub fn new(
-		reader: ReadHalf<TcpStream>,
+		reader: ReadHalf<S>,
 		addr: SocketAddr,
 		uuid: Uuid,
+		session: Uuid,
 	) -> Self {
-		Self { reader, addr, uuid }
+		Self {
+			reader,
+			addr,
+			uuid,
+			session,
+		}
 	}
 
 	// move to other one
 	pub async fn get_message(&mut self) -> io::Result<ConnectedClientMessage> {
-		read_message::<ConnectedClientMessage, ReadHalf<TcpStream>>(
-			&mut self.reader,
-		)
-		.await
+		read_message::<ConnectedClientMessage, ReadHalf<S>>(&mut self.reader).await
 	}
 
 	pub fn handle_message(
@@ -51,17 +57,21 @@ impl ProtobufClientReader {
 	) {
 		use connected_client_message::Message;
 
-		println!("[ProtobufClientReader:{}] got message", self.addr);
+		debug!(addr:% = self.addr; "got message");
 
 		let uuid = self.uuid;
+		let session = self.session;
 
 		_ = match msg {
 			ConnectedClientMessage {
 				message: Some(Message::GetClients(GetClients {})),
-			} => channel.send(ConnectionManagerMessage::SendClientsTo { uuid }),
+			} => {
+				channel.send(ConnectionManagerMessage::SendClientsTo { uuid, session })
+			}
 			ConnectedClientMessage {
 				message: Some(Message::GetGlobalMessage(GetGlobalMessages {})),
-			} => channel.send(ConnectionManagerMessage::SendGlobalMessages { uuid }),
+			} => channel
+				.send(ConnectionManagerMessage::SendGlobalMessages { uuid, session }),
 			ConnectedClientMessage {
 				message:
 					Some(Message::SendPrivateMessage(SendPrivateMessage {
@@ -69,27 +79,43 @@ impl ProtobufClientReader {
 						to,
 						content,
 					})),
-			} => channel.send(ConnectionManagerMessage::SendPrivateMessage {
-				uuid: message_uuid.parse().unwrap(),
-				from: uuid,
-				to: to.parse().unwrap(),
-				content,
-			}),
+			} => {
+				let (Ok(message_uuid), Ok(to)) = (message_uuid.parse(), to.parse())
+				else {
+					debug!(addr:% = self.addr; "ignoring message with an invalid uuid");
+					return;
+				};
+				channel.send(ConnectionManagerMessage::SendPrivateMessage {
+					uuid: message_uuid,
+					from: uuid,
+					session,
+					to,
+					content,
+				})
+			}
 			ConnectedClientMessage {
 				message: Some(Message::SendGlobalMessage(SendGlobalMessage { content })),
 			} => channel.send(ConnectionManagerMessage::BroadcastGlobalMessage {
 				from: uuid,
 				content,
 			}),
+			ConnectedClientMessage {
+				message: Some(Message::Pong(Pong {})),
+			} => {
+				channel.send(ConnectionManagerMessage::ReceivedPong { uuid, session })
+			}
 			ConnectedClientMessage {
 				message: Some(Message::Disconnect(Disconnect {})),
-			} => channel.send(ConnectionManagerMessage::Disconnect { uuid }),
-			ConnectedClientMessage { message: None } => unimplemented!(),
+			} => channel.send(ConnectionManagerMessage::Disconnect { uuid, session }),
+			ConnectedClientMessage { message: None } => {
+				debug!(addr:% = self.addr; "ignoring empty message");
+				return;
+			}
 		};
 	}
 }
 
-impl ClientReader for ProtobufClientReader {
+impl<S: NetworkStream> ClientReader for ProtobufClientReader<S> {
 	fn start_run(
 		mut self: Box<Self>,
 		uuid: Uuid,
@@ -101,12 +127,15 @@ impl ClientReader for ProtobufClientReader {
 
 				let Ok(msg) = msg else {
 					let error = msg.unwrap_err();
-					println!(
-						"[ProtobufClientReader:{}] errored with '{}' disconnecting",
-						self.addr, error
+					info!(
+						addr:% = self.addr;
+						"errored with '{}', disconnecting", error
 					);
 
-					_ = channel.send(ConnectionManagerMessage::Disconnected { uuid });
+					_ = channel.send(ConnectionManagerMessage::Disconnected {
+						uuid,
+						session: self.session,
+					});
 
 					return;
 				};
@@ -8,35 +8,42 @@ use foundation::{
 		ClientConnected,
 		ClientDetails,
 		ClientDisconnected,
+		ClientRenamed,
 		ConnectedClients,
 		ConnectedServerMessage,
 		Disconnected,
 		GlobalMessage,
 		GlobalMessages,
+		Ping,
 		PrivateMessage,
 	},
 };
+use log::{debug, trace};
 use tokio::{io::WriteHalf, net::TcpStream};
 use uuid::Uuid;
 
-use crate::network::ClientWriter;
+use crate::network::{ClientWriter, NetworkStream};
 
 #[allow(dead_code)]
-pub struct ProtobufClientWriter {
-	writer: WriteHalf<TcpStream>,
+pub struct ProtobufClientWriter<S = TcpStream> {
+	writer: WriteHalf<S>,
 	addr: SocketAddr,
 	uuid: Uuid,
 }
 
-impl ProtobufClientWriter {
-	pub fn new(
-		writer: WriteHalf<TcpStream>,
-		addr: SocketAddr,
-		uuid: Uuid,
-	) -> Self {
+impl<S: NetworkStream> ProtobufClientWriter<S> {
+	pub fn new(writer: WriteHalf<S>, addr: SocketAddr, uuid: Uuid) -> Self {
 		Self { writer, addr, uuid }
 	}
 
+	/// A failed write means the client has gone,
+	/// which its reader reports to the connection manager.
+	async fn write(&mut self, message: ConnectedServerMessage) {
+		if let Err(e) = write_message(&mut self.writer, message).await {
+			debug!(addr:% = self.addr; "write failed: {}", e);
+		}
+	}
+
 	#[deprecated]
 	pub async fn send_clients(&mut self, clients: Vec<ClientDetails>) {
 		let message = ConnectedServerMessage {
@@ -44,8 +51,8 @@ impl ProtobufClientWriter {
 				ConnectedClients { clients },
 			)),
 		};
-		println!("[ProtobufClientWriter:{}] sending clients", self.addr);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending clients");
+		self.write(message).await;
 	}
 
 	#[deprecated]
@@ -55,11 +62,8 @@ impl ProtobufClientWriter {
 				GlobalMessages { messages },
 			)),
 		};
-		println!(
-			"[ProtobufClientWriter:{}] sending global messages",
-			self.addr
-		);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending global messages");
+		self.write(message).await;
 	}
 
 	#[deprecated]
@@ -67,11 +71,8 @@ impl ProtobufClientWriter {
 		let message = ConnectedServerMessage {
 			message: Some(connected_server_message::Message::PrivateMessage(message)),
 		};
-		println!(
-			"[ProtobufClientWriter:{}] sending private message",
-			self.addr
-		);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending private message");
+		self.write(message).await;
 	}
 	#[deprecated]
 	pub async fn send_disconnect(&mut self) {
@@ -82,21 +83,21 @@ impl ProtobufClientWriter {
 				},
 			)),
 		};
-		println!("[ProtobufClientWriter:{}] sending disconnect", self.addr);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending disconnect");
+		self.write(message).await;
 	}
 }
 
 #[async_trait]
-impl ClientWriter for ProtobufClientWriter {
+impl<S: NetworkStream> ClientWriter for ProtobufClientWriter<S> {
 	async fn send_clients(&mut self, clients: Vec<ClientDetails>) {
 		let message = ConnectedServerMessage {
 			message: Some(connected_server_message::Message::ConnectedClients(
 				ConnectedClients { clients },
 			)),
 		};
-		println!("[ProtobufClientWriter:{}] sending clients", self.addr);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending clients");
+		self.write(message).await;
 	}
 
 	async fn send_client_joined(&mut self, details: ClientDetails) {
@@ -107,11 +108,8 @@ impl ClientWriter for ProtobufClientWriter {
 				},
 			)),
 		};
-		println!(
-			"[ProtobufClientWriter:{}] sending client connected message",
-			self.addr
-		);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending client connected");
+		self.write(message).await;
 	}
 
 	async fn send_client_left(&mut self, uuid: Uuid) {
@@ -122,11 +120,21 @@ impl ClientWriter for ProtobufClientWriter {
 				},
 			)),
 		};
-		println!(
-			"[ProtobufClientWriter:{}] sending client connected message",
-			self.addr
-		);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending client connected");
+		self.write(message).await;
+	}
+
+	async fn send_client_renamed(&mut self, uuid: Uuid, username: String) {
+		let message = ConnectedServerMessage {
+			message: Some(connected_server_message::Message::ClientRenamed(
+				ClientRenamed {
+					uuid: uuid.to_string(),
+					name: username,
+				},
+			)),
+		};
+		debug!(addr:% = self.addr; "sending client renamed");
+		self.write(message).await;
 	}
 
 	async fn send_global_messages(&mut self, messages: Vec<GlobalMessage>) {
@@ -135,41 +143,44 @@ impl ClientWriter for ProtobufClientWriter {
 				GlobalMessages { messages },
 			)),
 		};
-		println!(
-			"[ProtobufClientWriter:{}] sending global messages",
-			self.addr
-		);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending global messages");
+		self.write(message).await;
 	}
 
 	async fn send_global_message(&mut self, message: GlobalMessage) {
 		let message = ConnectedServerMessage {
 			message: Some(connected_server_message::Message::GlobalMessage(message)),
 		};
-		println!("[ProtobufClientWriter:{}] sending disconnect", self.addr);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending disconnect");
+		self.write(message).await;
 	}
 
 	async fn send_private_message(&mut self, message: PrivateMessage) {
 		let message = ConnectedServerMessage {
 			message: Some(connected_server_message::Message::PrivateMessage(message)),
 		};
-		println!(
-			"[ProtobufClientWriter:{}] sending private message",
-			self.addr
-		);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending private message");
+		self.write(message).await;
 	}
 
-	async fn send_disconnect(&mut self) {
+	async fn send_disconnect(&mut self, reason: String) {
 		let message = ConnectedServerMessage {
 			message: Some(connected_server_message::Message::Disconnected(
-				Disconnected {
-					reason: "shutting down".into(),
-				},
+				Disconnected { reason },
 			)),
 		};
-		println!("[ProtobufClientWriter:{}] sending disconnect", self.addr);
-		write_message(&mut self.writer, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending disconnect");
+		self.write(message).await;
+	}
+
+	async fn send_ping(&mut self) {
+		let message = ConnectedServerMessage {
+			message: Some(connected_server_message::Message::Ping(Ping {})),
+		};
+		trace!(addr:% = self.addr; "sending ping");
+		// a failed ping isn't fatal, the missed pong will disconnect the client.
+		if let Err(e) = write_message(&mut self.writer, message).await {
+			debug!(addr:% = self.addr; "ping failed: {}", e);
+		}
 	}
 }
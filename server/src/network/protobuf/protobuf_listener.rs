@@ -1,8 +1,16 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
+use log::{debug, trace, warn};
 use tokio::{net::TcpListener, sync::mpsc::UnboundedSender, task::JoinHandle};
 
 use crate::{
-	network::{ConnectionType, NetworkListener},
+	metrics::METRICS,
+	network::{
+		connection_limiter::ConnectionLimiter,
+		ConnectionType,
+		NetworkListener,
+	},
 	server_va::ServerMessages,
 };
 
@@ -12,13 +20,17 @@ use crate::{
 pub struct ProtobufListener {
 	protobuf_listener: TcpListener,
 	sender: UnboundedSender<ServerMessages>,
+	limiter: Arc<ConnectionLimiter>,
 }
 
 #[async_trait]
 impl NetworkListener for ProtobufListener {
 	/// Binds listeners and stores them in the ListenerManager
-	async fn new(channel: UnboundedSender<ServerMessages>) -> Self {
-		println!("[ProtobufListener] setting up listeners");
+	async fn new(
+		channel: UnboundedSender<ServerMessages>,
+		limiter: Arc<ConnectionLimiter>,
+	) -> Self {
+		debug!("setting up listener");
 		let protobuf_listener = TcpListener::bind("0.0.0.0:6500")
 			.await
 			.expect("[ProtobufListener] failed to bind to 0.0.0.0:6500");
@@ -26,30 +38,39 @@ impl NetworkListener for ProtobufListener {
 		Self {
 			protobuf_listener,
 			sender: channel,
+			limiter,
 		}
 	}
 
 	async fn run(&self) {
 		loop {
-			println!("[ProtobufListener] waiting for connection");
+			trace!("waiting for connection");
 			let accept_protobuf = self.protobuf_listener.accept().await;
 			let Ok((stream, addr)) = accept_protobuf else {
-				println!("[ProtobufListener] accept failed");
+				warn!("accept failed");
+				continue;
+			};
+			METRICS.accepted.inc("protobuf");
+			let Some(permit) = self.limiter.admit(addr) else {
 				continue;
 			};
 
-			let msg = ServerMessages::NewConnection(
-				ConnectionType::ProtobufConnection(stream, addr),
-			);
+			let msg = ServerMessages::NewConnection {
+				connection: ConnectionType::ProtobufConnection(stream, addr),
+				permit,
+			};
 
-			println!("[ProtobufListener] passing message to server");
+			debug!(addr:% = addr; "accepted connection");
 			_ = self.sender.send(msg);
 		}
 	}
 
-	fn start_run(sender: UnboundedSender<ServerMessages>) -> JoinHandle<()> {
+	fn start_run(
+		sender: UnboundedSender<ServerMessages>,
+		limiter: Arc<ConnectionLimiter>,
+	) -> JoinHandle<()> {
 		tokio::spawn(async move {
-			ProtobufListener::new(sender).await.run().await;
+			ProtobufListener::new(sender, limiter).await.run().await;
 		})
 	}
 }
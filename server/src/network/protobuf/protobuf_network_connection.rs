@@ -2,7 +2,16 @@ use std::{io, net::SocketAddr};
 
 use async_trait::async_trait;
 use foundation::{
-	networking::protobuf::{read_message, write_message},
+	networking::{
+		handshake::{
+			capabilities,
+			check_version,
+			negotiate_capabilities,
+			MIN_PROTOCOL_VERSION,
+			PROTOCOL_VERSION,
+		},
+		protobuf::{read_message, write_message},
+	},
 	prelude::{
 		network_client_message,
 		network_server_message,
@@ -12,118 +21,91 @@ use foundation::{
 		Info,
 		NetworkClientMessage,
 		NetworkServerMessage,
+		Rejected,
 		Request,
 	},
 };
+use log::{debug, info, trace};
 use tokio::{io::split, net::TcpStream};
 use uuid::Uuid;
 
-use crate::network::{
-	protobuf::{
-		protobuf_client_reader::ProtobufClientReader,
-		protobuf_client_writer::ProtobufClientWriter,
+use crate::{
+	account::Credentials,
+	metrics::METRICS,
+	network::{
+		metered_stream::MeteredStream,
+		protobuf::{
+			protobuf_client_reader::ProtobufClientReader,
+			protobuf_client_writer::ProtobufClientWriter,
+		},
+		ClientReader,
+		ClientWriter,
+		ConnectRequest,
+		NetworkConnection,
+		NetworkStream,
+		ServerRequest,
 	},
-	ClientReader,
-	ClientWriter,
-	NetworkConnection,
-	ServerRequest,
 };
 
-pub struct ProtobufNetworkConnection {
-	pub(super) stream: TcpStream,
+pub struct ProtobufNetworkConnection<S = TcpStream> {
+	pub(super) stream: MeteredStream<S>,
 	pub(super) addr: SocketAddr,
+	/// the version and capabilities agreed with the client.
+	version: u32,
+	capabilities: Vec<String>,
 }
 
-impl ProtobufNetworkConnection {
-	pub fn new(stream: TcpStream, addr: SocketAddr) -> Self {
-		Self { stream, addr }
-	}
-
-	pub async fn get_request(&mut self) -> io::Result<ServerRequest> {
-		let message = NetworkServerMessage {
-			message: Some(network_server_message::Message::Request(Request {})),
-		};
-
-		println!("[ProtobufNetworkConnection] sending request");
-		write_message(&mut self.stream, message).await.unwrap();
-
-		println!("[ProtobufNetworkConnection] waiting for response");
-		let request =
-			read_message::<NetworkClientMessage, TcpStream>(&mut self.stream)
-				.await
-				.unwrap();
-
-		println!("[ProtobufNetworkConnection] returning request");
-		match request {
-			NetworkClientMessage {
-				message: Some(network_client_message::Message::GetInfo(GetInfo {})),
-			} => Ok(ServerRequest::GetInfo),
-			NetworkClientMessage {
-				message:
-					Some(network_client_message::Message::Connect(Connect {
-						username,
-						uuid,
-					})),
-			} => Ok(ServerRequest::Connect {
-				username,
-				uuid: uuid.parse().unwrap(),
-				addr: self.addr,
-			}),
-			_ => Ok(ServerRequest::Ignore),
+impl<S: NetworkStream> ProtobufNetworkConnection<S> {
+	pub fn new(stream: MeteredStream<S>, addr: SocketAddr) -> Self {
+		Self {
+			stream,
+			addr,
+			version: PROTOCOL_VERSION,
+			capabilities: Vec::new(),
 		}
 	}
 
-	pub async fn send_info(mut self, name: String, owner: String) {
+	/// Tells the client why it can't connect.
+	async fn reject(&mut self, reason: String) -> io::Error {
+		METRICS.handshakes.inc("rejected");
+		info!(addr:% = self.addr; "rejecting client: {}", reason);
 		let message = NetworkServerMessage {
-			message: Some(network_server_message::Message::GotInfo(Info {
-				server_name: name,
-				owner,
+			message: Some(network_server_message::Message::Rejected(Rejected {
+				reason: reason.clone(),
+				min_version: MIN_PROTOCOL_VERSION,
+				max_version: PROTOCOL_VERSION,
 			})),
 		};
-		println!("[ProtobufNetworkConnection] Sending info to client");
-		write_message(&mut self.stream, message).await.unwrap();
-		println!("[ProtobufNetworkConnection] droping connection");
-	}
-
-	pub async fn send_connected(
-		mut self,
-		uuid: Uuid,
-	) -> (ProtobufClientWriter, ProtobufClientReader) {
-		let message = NetworkServerMessage {
-			message: Some(network_server_message::Message::Connected(Connected {})),
-		};
-
-		write_message(&mut self.stream, message).await.unwrap();
-
-		self.into(uuid)
-	}
-
-	fn into(self, uuid: Uuid) -> (ProtobufClientWriter, ProtobufClientReader) {
-		let (read, write) = split(self.stream);
-
-		let writer = ProtobufClientWriter::new(write, self.addr, uuid);
-		let reader = ProtobufClientReader::new(read, self.addr, uuid);
-		(writer, reader)
+		_ = write_message(&mut self.stream, message).await;
+		io::Error::new(io::ErrorKind::InvalidData, reason)
 	}
 }
 
 #[async_trait]
-impl NetworkConnection for ProtobufNetworkConnection {
+impl<S: NetworkStream> NetworkConnection for ProtobufNetworkConnection<S> {
+	fn protocol(&self) -> &'static str {
+		self.stream.protocol()
+	}
+
 	async fn get_request(&mut self) -> io::Result<ServerRequest> {
 		let message = NetworkServerMessage {
-			message: Some(network_server_message::Message::Request(Request {})),
+			message: Some(network_server_message::Message::Request(Request {
+				min_version: MIN_PROTOCOL_VERSION,
+				max_version: PROTOCOL_VERSION,
+				capabilities: capabilities(),
+			})),
 		};
 
-		println!("[ProtobufNetworkConnection] sending request");
-		write_message(&mut self.stream, message).await.unwrap();
+		debug!(addr:% = self.addr; "sending request");
+		write_message(&mut self.stream, message)
+			.await
+			.inspect_err(|_| METRICS.handshakes.inc("failed"))?;
 
-		println!("[ProtobufNetworkConnection] waiting for response");
-		let request =
-			read_message::<NetworkClientMessage, TcpStream>(&mut self.stream)
-				.await
-				.unwrap();
+		trace!(addr:% = self.addr; "waiting for response");
+		let request = read_message::<NetworkClientMessage, _>(&mut self.stream)
+			.await
+			.inspect_err(|_| METRICS.handshakes.inc("failed"))?;
 
-		println!("[ProtobufNetworkConnection] returning request");
 		match request {
 			NetworkClientMessage {
 				message: Some(network_client_message::Message::GetInfo(GetInfo {})),
@@ -133,42 +115,87 @@ impl NetworkConnection for ProtobufNetworkConnection {
 					Some(network_client_message::Message::Connect(Connect {
 						username,
 						uuid,
+						session_token,
+						version,
+						capabilities,
+						password,
+						register,
 					})),
-			} => Ok(ServerRequest::Connect {
-				username,
-				uuid: uuid.parse().unwrap(),
-				addr: self.addr,
-			}),
+			} => {
+				let Ok(uuid) = uuid.parse() else {
+					return Err(self.reject("invalid uuid".into()).await);
+				};
+				self.version = match check_version(version) {
+					Ok(version) => version,
+					Err(reason) => return Err(self.reject(reason).await),
+				};
+				self.capabilities = negotiate_capabilities(self.version, &capabilities);
+
+				Ok(ServerRequest::Connect(ConnectRequest {
+					username,
+					uuid,
+					addr: self.addr,
+					session_token: session_token.parse().ok(),
+					capabilities: self.capabilities.clone(),
+					credentials: (!password.is_empty()).then(|| Credentials {
+						password: password.into(),
+						register,
+					}),
+				}))
+			}
 			_ => Ok(ServerRequest::Ignore),
 		}
 	}
 
-	async fn send_info(mut self: Box<Self>, name: String, owner: String) {
+	async fn send_info(mut self: Box<Self>, info: Info) {
+		METRICS.handshakes.inc("info");
 		let message = NetworkServerMessage {
-			message: Some(network_server_message::Message::GotInfo(Info {
-				server_name: name,
-				owner,
-			})),
+			message: Some(network_server_message::Message::GotInfo(info)),
 		};
-		println!("[ProtobufNetworkConnection] Sending info to client");
-		write_message(&mut self.stream, message).await.unwrap();
-		println!("[ProtobufNetworkConnection] droping connection");
+		debug!(addr:% = self.addr; "sending info");
+		if let Err(e) = write_message(&mut self.stream, message).await {
+			debug!(addr:% = self.addr; "couldn't send info: {}", e);
+		}
+	}
+
+	async fn send_rejected(mut self: Box<Self>, reason: String) {
+		self.reject(reason).await;
 	}
 
 	async fn send_connected(
 		mut self: Box<Self>,
 		uuid: Uuid,
+		session_token: Uuid,
+		resumed: bool,
 	) -> (Box<dyn ClientWriter>, Box<dyn ClientReader>) {
+		METRICS
+			.handshakes
+			.inc(if resumed { "resumed" } else { "connected" });
 		let message = NetworkServerMessage {
-			message: Some(network_server_message::Message::Connected(Connected {})),
+			message: Some(network_server_message::Message::Connected(Connected {
+				session_token: session_token.to_string(),
+				resumed,
+				version: self.version,
+				capabilities: self.capabilities.clone(),
+				uuid: uuid.to_string(),
+			})),
 		};
 
-		write_message(&mut self.stream, message).await.unwrap();
+		// if this fails the reader finds the stream closed,
+		// and reports the client as disconnected
+		if let Err(e) = write_message(&mut self.stream, message).await {
+			debug!(addr:% = self.addr; "couldn't send connected: {}", e);
+		}
 
 		let (read, write) = split(self.stream);
 
 		let writer = Box::new(ProtobufClientWriter::new(write, self.addr, uuid));
-		let reader = Box::new(ProtobufClientReader::new(read, self.addr, uuid));
+		let reader = Box::new(ProtobufClientReader::new(
+			read,
+			self.addr,
+			uuid,
+			session_token,
+		));
 		(writer, reader)
 	}
 }
@@ -0,0 +1,223 @@
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use foundation::networking::{JSON_PREAMBLE, PROTOBUF_PREAMBLE};
+use log::{debug, trace, warn};
+use tokio::{
+	io::AsyncReadExt,
+	net::{TcpListener, TcpStream},
+	sync::mpsc::UnboundedSender,
+	task::JoinHandle,
+	time::{sleep, timeout},
+};
+
+use crate::{
+	metrics::METRICS,
+	network::{
+		connection_limiter::{ConnectionLimiter, ConnectionPermit},
+		websocket::websocket_listener::accept_websocket,
+		ConnectionType,
+		NetworkListener,
+	},
+	server_va::ServerMessages,
+};
+
+/// how long to wait for a preamble, before assuming protobuf.
+const SNIFF_TIMEOUT: Duration = Duration::from_millis(500);
+const SNIFF_RETRY: Duration = Duration::from_millis(10);
+
+/// The protocol a connection on the unified port was found to use.
+#[derive(Debug, PartialEq, Eq)]
+enum DetectedProtocol {
+	Json,
+	Protobuf,
+	WebSocket,
+}
+
+/// # UnifiedListener
+/// Accepts every protocol on a single port.
+/// The first bytes sent by the client decide the protocol:
+/// - a json or protobuf preamble picks that protocol.
+/// - `GET ` is a websocket upgrade request.
+/// - anything else is taken to be a protobuf length prefix.
+///
+/// Json and protobuf clients wait for the servers request,
+/// so json clients must send the preamble,
+/// and clients that send nothing are treated as protobuf.
+pub struct UnifiedListener {
+	listener: TcpListener,
+	sender: UnboundedSender<ServerMessages>,
+	limiter: Arc<ConnectionLimiter>,
+}
+
+#[async_trait]
+impl NetworkListener for UnifiedListener {
+	async fn new(
+		sender: UnboundedSender<ServerMessages>,
+		limiter: Arc<ConnectionLimiter>,
+	) -> Self {
+		let address = "0.0.0.0:5800";
+
+		debug!("setting up listener");
+		let listener = TcpListener::bind(address)
+			.await
+			.expect("[UnifiedListener] failed to bind to 0.0.0.0:5800");
+
+		Self {
+			listener,
+			sender,
+			limiter,
+		}
+	}
+
+	async fn run(&self) {
+		loop {
+			trace!("waiting for connection");
+			let accept = self.listener.accept().await;
+
+			let Ok((stream, addr)) = accept else {
+				warn!("accept failed");
+				continue;
+			};
+			METRICS.accepted.inc("unified");
+			let Some(permit) = self.limiter.admit(addr) else {
+				continue;
+			};
+
+			// detection waits on the client, so it can't block the accept loop
+			tokio::spawn(route_connection(stream, addr, permit, self.sender.clone()));
+		}
+	}
+
+	fn start_run(
+		sender: UnboundedSender<ServerMessages>,
+		limiter: Arc<ConnectionLimiter>,
+	) -> JoinHandle<()> {
+		tokio::spawn(async move {
+			UnifiedListener::new(sender, limiter).await.run().await;
+		})
+	}
+}
+
+async fn route_connection(
+	mut stream: TcpStream,
+	addr: SocketAddr,
+	permit: ConnectionPermit,
+	sender: UnboundedSender<ServerMessages>,
+) {
+	let protocol = match detect_protocol(&mut stream).await {
+		Ok(protocol) => protocol,
+		Err(e) => {
+			warn!(addr:% = addr; "detection failed: {}", e);
+			return;
+		}
+	};
+
+	debug!(addr:% = addr; "detected {:?}", protocol);
+	let connection = match protocol {
+		DetectedProtocol::Json => ConnectionType::JsonConnection(stream, addr),
+		DetectedProtocol::Protobuf => {
+			ConnectionType::ProtobufConnection(stream, addr)
+		}
+		DetectedProtocol::WebSocket => {
+			accept_websocket(stream, addr, permit, sender).await;
+			return;
+		}
+	};
+
+	_ = sender.send(ServerMessages::NewConnection { connection, permit });
+}
+
+/// Peeks at the first bytes of the stream to find its protocol.
+/// Preambles are consumed, everything else is left for the connection to read.
+async fn detect_protocol(
+	stream: &mut TcpStream,
+) -> io::Result<DetectedProtocol> {
+	let mut buffer = [0; 4];
+
+	let peeked = timeout(SNIFF_TIMEOUT, async {
+		loop {
+			let read = stream.peek(&mut buffer).await?;
+			if read == 0 {
+				return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+			}
+			if read == buffer.len() {
+				return Ok(());
+			}
+			// peek returns straight away, so wait for the rest to arrive
+			sleep(SNIFF_RETRY).await;
+		}
+	})
+	.await;
+
+	match peeked {
+		Err(_) => return Ok(DetectedProtocol::Protobuf),
+		Ok(result) => result?,
+	}
+
+	let protocol = match buffer {
+		JSON_PREAMBLE => {
+			stream.read_exact(&mut buffer).await?;
+			DetectedProtocol::Json
+		}
+		PROTOBUF_PREAMBLE => {
+			stream.read_exact(&mut buffer).await?;
+			DetectedProtocol::Protobuf
+		}
+		[b'G', b'E', b'T', b' '] => DetectedProtocol::WebSocket,
+		_ => DetectedProtocol::Protobuf,
+	};
+
+	Ok(protocol)
+}
+
+#[cfg(test)]
+mod tests {
+	use foundation::{
+		messages::network::{NetworkSockIn, NetworkSockOut},
+		networking::json::{self, read_message, write_message},
+	};
+	use tokio::sync::mpsc::unbounded_channel;
+
+	use super::*;
+	use crate::{
+		config::ConnectionConfig,
+		network::{
+			json::json_network_connection::JSONNetworkConnection,
+			metered_stream::MeteredStream,
+			NetworkConnection,
+			ServerRequest,
+		},
+	};
+
+	#[tokio::test]
+	async fn json_clients_are_detected() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let limiter = ConnectionLimiter::new(ConnectionConfig::default());
+		let (sender, mut receiver) = unbounded_channel();
+		tokio::spawn(async move {
+			let (stream, addr) = listener.accept().await.unwrap();
+			let permit = limiter.admit(addr).unwrap();
+			route_connection(stream, addr, permit, sender).await
+		});
+
+		let mut client = json::connect_unified(addr).await.unwrap();
+
+		let Some(ServerMessages::NewConnection {
+			connection: ConnectionType::JsonConnection(stream, addr),
+			permit,
+		}) = receiver.recv().await
+		else {
+			panic!("expected a json connection");
+		};
+		let stream = MeteredStream::new(stream, "json", permit);
+		let mut conn = JSONNetworkConnection::new(stream, addr);
+		let server = tokio::spawn(async move { conn.get_request().await });
+
+		let request = read_message::<_, NetworkSockOut>(&mut client).await;
+		assert!(matches!(request, Ok(NetworkSockOut::Request { .. })));
+		write_message(&mut client, NetworkSockIn::Info).await;
+		assert!(matches!(server.await.unwrap(), Ok(ServerRequest::GetInfo)));
+	}
+}
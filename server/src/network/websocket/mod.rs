@@ -0,0 +1,2 @@
+pub mod websocket_listener;
+pub mod websocket_stream;
@@ -0,0 +1,139 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use log::{debug, info, trace, warn};
+use tokio::{
+	net::{TcpListener, TcpStream},
+	sync::mpsc::UnboundedSender,
+	task::JoinHandle,
+	time::timeout,
+};
+use tokio_tungstenite::{
+	accept_hdr_async,
+	tungstenite::{
+		handshake::server::{ErrorResponse, Request, Response},
+		http::HeaderValue,
+	},
+};
+
+use crate::{
+	metrics::METRICS,
+	network::{
+		connection_limiter::{ConnectionLimiter, ConnectionPermit},
+		websocket::websocket_stream::{WebSocketByteStream, WebSocketProtocol},
+		ConnectionType,
+		NetworkListener,
+	},
+	server_va::ServerMessages,
+};
+
+/// how long a client has to finish the websocket upgrade.
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// # WebSocketListener
+/// Accepts websocket connections from browser clients.
+pub struct WebSocketListener {
+	listener: TcpListener,
+	sender: UnboundedSender<ServerMessages>,
+	limiter: Arc<ConnectionLimiter>,
+}
+
+#[async_trait]
+impl NetworkListener for WebSocketListener {
+	async fn new(
+		sender: UnboundedSender<ServerMessages>,
+		limiter: Arc<ConnectionLimiter>,
+	) -> Self {
+		let address = "0.0.0.0:5700";
+
+		debug!("setting up listener");
+		let listener = TcpListener::bind(address)
+			.await
+			.expect("[WebSocketListener] failed to bind to 0.0.0.0:5700");
+
+		Self {
+			listener,
+			sender,
+			limiter,
+		}
+	}
+
+	async fn run(&self) {
+		loop {
+			trace!("waiting for connection");
+			let accept = self.listener.accept().await;
+
+			let Ok((stream, addr)) = accept else {
+				warn!("accept failed");
+				continue;
+			};
+			METRICS.accepted.inc("websocket");
+			let Some(permit) = self.limiter.admit(addr) else {
+				continue;
+			};
+
+			// the upgrade is done in its own task, so slow clients don't block others
+			tokio::spawn(accept_websocket(stream, addr, permit, self.sender.clone()));
+		}
+	}
+
+	fn start_run(
+		sender: UnboundedSender<ServerMessages>,
+		limiter: Arc<ConnectionLimiter>,
+	) -> JoinHandle<()> {
+		tokio::spawn(async move {
+			WebSocketListener::new(sender, limiter).await.run().await;
+		})
+	}
+}
+
+/// Upgrades a tcp stream to a websocket, and passes it to the server.
+/// The protocol is chosen by the client with the Sec-WebSocket-Protocol header,
+/// defaulting to json if none is given.
+pub async fn accept_websocket(
+	stream: TcpStream,
+	addr: SocketAddr,
+	permit: ConnectionPermit,
+	sender: UnboundedSender<ServerMessages>,
+) {
+	let mut protocol = WebSocketProtocol::Json;
+
+	// the error type is set by tungstenite
+	#[allow(clippy::result_large_err)]
+	let select_protocol = |request: &Request, mut response: Response| {
+		let requested = request
+			.headers()
+			.get("Sec-WebSocket-Protocol")
+			.and_then(|h| h.to_str().ok())
+			.and_then(WebSocketProtocol::from_header);
+
+		if let Some(requested) = requested {
+			protocol = requested;
+			response.headers_mut().insert(
+				"Sec-WebSocket-Protocol",
+				HeaderValue::from_static(requested.name()),
+			);
+		}
+		Ok::<Response, ErrorResponse>(response)
+	};
+
+	let upgrade = accept_hdr_async(stream, select_protocol);
+	let websocket = match timeout(UPGRADE_TIMEOUT, upgrade).await {
+		Ok(Ok(websocket)) => websocket,
+		Ok(Err(e)) => {
+			warn!(addr:% = addr; "upgrade failed: {}", e);
+			return;
+		}
+		Err(_) => {
+			info!(addr:% = addr; "upgrade timed out");
+			return;
+		}
+	};
+
+	debug!(addr:% = addr; "accepted {:?} connection", protocol);
+	let stream = Box::new(WebSocketByteStream::new(websocket, protocol));
+	_ = sender.send(ServerMessages::NewConnection {
+		connection: ConnectionType::WebSocketConnection(stream, addr),
+		permit,
+	});
+}
@@ -0,0 +1,190 @@
+use std::{
+	collections::VecDeque,
+	io,
+	pin::Pin,
+	task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures::{SinkExt, StreamExt};
+use tokio::{
+	io::{AsyncRead, AsyncWrite, ReadBuf},
+	net::TcpStream,
+};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+/// # WebSocketProtocol
+/// The protocol a websocket client asked for during the upgrade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebSocketProtocol {
+	/// json messages, one per text frame
+	Json,
+	/// protobuf messages, one per binary frame
+	Protobuf,
+}
+
+impl WebSocketProtocol {
+	pub const JSON_NAME: &'static str = "chatkit.json";
+	pub const PROTOBUF_NAME: &'static str = "chatkit.protobuf";
+
+	/// Picks the protocol from a Sec-WebSocket-Protocol header.
+	pub fn from_header(header: &str) -> Option<Self> {
+		header.split(',').map(str::trim).find_map(|p| match p {
+			Self::JSON_NAME => Some(Self::Json),
+			Self::PROTOBUF_NAME => Some(Self::Protobuf),
+			_ => None,
+		})
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Json => Self::JSON_NAME,
+			Self::Protobuf => Self::PROTOBUF_NAME,
+		}
+	}
+}
+
+/// # WebSocketByteStream
+/// Adapts a websocket into a byte stream,
+/// so the json and protobuf connections can run over it unchanged.
+///
+/// Each frame carries exactly one message:
+/// - text frames hold a json message, and gain a newline when read.
+/// - binary frames hold a protobuf message, and gain a length prefix when read.
+///
+/// Writes are buffered until a whole message is available,
+/// then sent as a single frame of the negotiated type.
+pub struct WebSocketByteStream {
+	inner: WebSocketStream<TcpStream>,
+	protocol: WebSocketProtocol,
+	read_buffer: BytesMut,
+	write_buffer: BytesMut,
+	outgoing: VecDeque<Message>,
+}
+
+impl WebSocketByteStream {
+	pub fn new(
+		inner: WebSocketStream<TcpStream>,
+		protocol: WebSocketProtocol,
+	) -> Self {
+		Self {
+			inner,
+			protocol,
+			read_buffer: BytesMut::new(),
+			write_buffer: BytesMut::new(),
+			outgoing: VecDeque::new(),
+		}
+	}
+
+	pub fn get_protocol(&self) -> WebSocketProtocol {
+		self.protocol
+	}
+
+	/// Moves every complete message in the write buffer into the outgoing queue.
+	fn split_messages(&mut self) {
+		match self.protocol {
+			WebSocketProtocol::Json => {
+				while let Some(end) = self.write_buffer.iter().position(|b| *b == b'\n')
+				{
+					let line = self.write_buffer.split_to(end + 1);
+					let text = String::from_utf8_lossy(&line[..end]).into_owned();
+					self.outgoing.push_back(Message::Text(text));
+				}
+			}
+			WebSocketProtocol::Protobuf => {
+				while self.write_buffer.len() >= 4 {
+					let mut prefix = &self.write_buffer[..4];
+					let length = prefix.get_u32() as usize;
+					if self.write_buffer.len() < 4 + length {
+						break;
+					}
+					self.write_buffer.advance(4);
+					let body = self.write_buffer.split_to(length);
+					self.outgoing.push_back(Message::Binary(body.to_vec()));
+				}
+			}
+		}
+	}
+
+	/// Sends queued frames to the websocket, until it stops accepting them.
+	fn poll_send_outgoing(
+		&mut self,
+		cx: &mut Context<'_>,
+	) -> Poll<io::Result<()>> {
+		while !self.outgoing.is_empty() {
+			ready!(self.inner.poll_ready_unpin(cx)).map_err(into_io_error)?;
+			let message = self.outgoing.pop_front().unwrap();
+			self
+				.inner
+				.start_send_unpin(message)
+				.map_err(into_io_error)?;
+		}
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl AsyncRead for WebSocketByteStream {
+	fn poll_read(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		loop {
+			if !self.read_buffer.is_empty() {
+				let length = self.read_buffer.len().min(buf.remaining());
+				buf.put_slice(&self.read_buffer.split_to(length));
+				return Poll::Ready(Ok(()));
+			}
+
+			match ready!(self.inner.poll_next_unpin(cx)) {
+				Some(Ok(Message::Text(text))) => {
+					self.read_buffer.put_slice(text.as_bytes());
+					self.read_buffer.put_u8(b'\n');
+				}
+				Some(Ok(Message::Binary(data))) => {
+					self.read_buffer.put_u32(data.len() as u32);
+					self.read_buffer.put_slice(&data);
+				}
+				// control frames are handled by tungstenite
+				Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => {}
+				Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+				Some(Err(e)) => return Poll::Ready(Err(into_io_error(e))),
+			}
+		}
+	}
+}
+
+impl AsyncWrite for WebSocketByteStream {
+	fn poll_write(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		// don't accept more data while earlier frames are still waiting
+		ready!(self.poll_send_outgoing(cx))?;
+
+		self.write_buffer.put_slice(buf);
+		self.split_messages();
+		Poll::Ready(Ok(buf.len()))
+	}
+
+	fn poll_flush(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<io::Result<()>> {
+		ready!(self.poll_send_outgoing(cx))?;
+		self.inner.poll_flush_unpin(cx).map_err(into_io_error)
+	}
+
+	fn poll_shutdown(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<io::Result<()>> {
+		ready!(self.poll_send_outgoing(cx))?;
+		self.inner.poll_close_unpin(cx).map_err(into_io_error)
+	}
+}
+
+fn into_io_error(error: tokio_tungstenite::tungstenite::Error) -> io::Error {
+	io::Error::other(error)
+}
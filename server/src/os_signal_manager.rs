@@ -1,3 +1,4 @@
+use log::{debug, info};
 use tokio::sync::mpsc::UnboundedSender;
 
 use crate::server_va::ServerMessages;
@@ -15,9 +16,9 @@ impl OSSignalManager {
 
 	pub async fn run(&self) {
 		loop {
-			println!("[OSSignalManager] waiting for ctrl+c");
+			debug!("waiting for ctrl+c");
 			tokio::signal::ctrl_c().await.unwrap();
-			println!("[OSSignalManager] ctrl+c received, closing down server");
+			info!("ctrl+c received, closing down server");
 			self
 				.server_channel
 				.send(ServerMessages::Exit)
@@ -1,12 +1,16 @@
-mod plugin;
-mod plugin_details;
+mod plugin_api;
 mod plugin_entry;
-mod plugin_interface;
 mod plugin_manager;
 mod plugin_permissions;
 
-pub use plugin::{IPlugin, Plugin};
-pub use plugin_details::PluginDetails;
-pub(crate) use plugin_interface::PluginInterface;
-pub use plugin_interface::WeakPluginInterface;
-pub(crate) use plugin_manager::{PluginManager, PluginManagerMessage};
+pub use plugin_api::ServerPluginApi;
+pub use plugin_entry::{PluginEntry, PluginEntryObj, PluginExecutionState};
+pub use plugin_manager::{PluginError, PluginLoadError, PluginManager};
+pub use plugin_permissions::{
+	PermissionDenied,
+	PluginAccess,
+	PluginManifest,
+	PluginPermission,
+	PluginPermissions,
+	PluginScope,
+};
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use foundation::{
+	plugin::{async_trait, IPluginApi, PluginApiError, PluginDetails},
+	prelude::{ClientDetails, GlobalMessage},
+};
+use log::info;
+use tokio::{
+	sync::{mpsc::UnboundedSender, oneshot},
+	time::timeout,
+};
+use uuid::Uuid;
+
+use crate::{
+	connection::connection_manager::ConnectionManagerMessage,
+	plugin::{PermissionDenied, PluginAccess, PluginPermissions, PluginScope},
+	server_va::ServerMessages,
+};
+
+/// how long a query waits for the server to answer.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// the namespace plugin bot uuids are derived in.
+const BOT_NAMESPACE: Uuid =
+	Uuid::from_u128(0x6a1e_0b07_5c1d_4f3a_9e2b_7d4c_8f10_a2b3);
+
+impl From<PermissionDenied> for PluginApiError {
+	fn from(e: PermissionDenied) -> Self {
+		Self::PermissionDenied(e.to_string())
+	}
+}
+
+/// # ServerPluginApi
+/// The servers side of a plugins api handle.
+/// Every call is checked against the plugins permissions,
+/// then passed to the connection manager or server.
+#[derive(Debug)]
+pub struct ServerPluginApi {
+	plugin_id: String,
+	bot: ClientDetails,
+	permissions: PluginPermissions,
+	connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+	server_sender: UnboundedSender<ServerMessages>,
+}
+
+impl ServerPluginApi {
+	pub fn new(
+		details: &PluginDetails,
+		permissions: PluginPermissions,
+		connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+		server_sender: UnboundedSender<ServerMessages>,
+	) -> Self {
+		// the same plugin keeps the same bot across restarts
+		let bot = ClientDetails {
+			uuid: Uuid::new_v5(&BOT_NAMESPACE, details.id.as_bytes()).to_string(),
+			name: details.display_name.clone(),
+			address: "plugin".into(),
+			role: String::new(),
+		};
+
+		Self {
+			plugin_id: details.id.clone(),
+			bot,
+			permissions,
+			connection_manager_sender,
+			server_sender,
+		}
+	}
+
+	/// Replies to a user who ran one of the plugins commands.
+	/// Replies go to whoever ran the command, so need no permission.
+	pub fn reply(&self, to: Uuid, content: String) {
+		self.send_to_connection_manager(
+			ConnectionManagerMessage::BotPrivateMessage {
+				bot: self.bot.clone(),
+				to,
+				content,
+			},
+		);
+	}
+
+	fn check(
+		&self,
+		scope: PluginScope,
+		access: PluginAccess,
+	) -> Result<(), PluginApiError> {
+		Ok(self.permissions.check(&self.plugin_id, scope, access)?)
+	}
+
+	fn send_to_connection_manager(&self, message: ConnectionManagerMessage) {
+		_ = self.connection_manager_sender.send(message);
+	}
+
+	async fn query<T>(
+		&self,
+		reply: oneshot::Receiver<T>,
+	) -> Result<T, PluginApiError> {
+		match timeout(QUERY_TIMEOUT, reply).await {
+			Ok(Ok(value)) => Ok(value),
+			_ => Err(PluginApiError::Unavailable),
+		}
+	}
+}
+
+#[async_trait]
+impl IPluginApi for ServerPluginApi {
+	fn bot(&self) -> ClientDetails {
+		self.bot.clone()
+	}
+
+	async fn list_clients(&self) -> Result<Vec<ClientDetails>, PluginApiError> {
+		self.check(PluginScope::ClientManager, PluginAccess::Read)?;
+
+		let (tx, rx) = oneshot::channel();
+		self.send_to_connection_manager(ConnectionManagerMessage::ListClients {
+			reply: tx,
+		});
+		self.query(rx).await
+	}
+
+	async fn get_history(&self) -> Result<Vec<GlobalMessage>, PluginApiError> {
+		self.check(PluginScope::Server, PluginAccess::Read)?;
+
+		let (tx, rx) = oneshot::channel();
+		_ = self
+			.server_sender
+			.send(ServerMessages::GetGlobalMessages { reply: tx });
+		self.query(rx).await
+	}
+
+	async fn send_global_message(
+		&self,
+		content: String,
+	) -> Result<(), PluginApiError> {
+		self.check(PluginScope::Server, PluginAccess::Write)?;
+
+		self.send_to_connection_manager(
+			ConnectionManagerMessage::BotGlobalMessage {
+				bot: self.bot.clone(),
+				content,
+			},
+		);
+		Ok(())
+	}
+
+	async fn send_private_message(
+		&self,
+		to: Uuid,
+		content: String,
+	) -> Result<(), PluginApiError> {
+		self.check(PluginScope::Client, PluginAccess::Write)?;
+
+		self.reply(to, content);
+		Ok(())
+	}
+
+	async fn disconnect_user(&self, uuid: Uuid) -> Result<(), PluginApiError> {
+		self.check(PluginScope::ClientManager, PluginAccess::Write)?;
+
+		info!(
+			plugin = self.plugin_id.as_str(), uuid:% = uuid;
+			"disconnecting user"
+		);
+		self.send_to_connection_manager(ConnectionManagerMessage::DisconnectUser {
+			uuid,
+		});
+		Ok(())
+	}
+
+	async fn register_command(
+		&self,
+		name: String,
+		description: String,
+	) -> Result<(), PluginApiError> {
+		self.check(PluginScope::Server, PluginAccess::Write)?;
+
+		self.send_to_connection_manager(
+			ConnectionManagerMessage::RegisterCommand {
+				plugin_id: self.plugin_id.clone(),
+				name,
+				description,
+			},
+		);
+		Ok(())
+	}
+}
@@ -1,35 +1,43 @@
-use crate::plugin::plugin_interface::IPluginInterface;
-use crate::plugin::PluginInterface;
-use foundation::event::Event;
-
-use crate::event_type::EventType;
-
-use foundation::event::EventResult;
-use foundation::event::IResponder;
-use serde::{Deserialize, Serialize};
-use std::sync::Weak;
-
-use futures::channel::oneshot::Receiver;
-
-use crate::plugin::plugin::Plugin;
-use crate::plugin::plugin_entry::PluginExecutionState::{Paused, Running, Stopped};
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Mutex;
-use tokio::time::sleep;
-
-pub(crate) type PluginEntryObj = Arc<PluginEntry>;
-
-#[derive(Serialize, Deserialize, Debug, Ord, PartialOrd, Eq, PartialEq)]
-pub enum PluginPermission {
-	Read,
-	Write,
-	ReadWrite,
-	None,
-}
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use foundation::{
+	event::{Event, EventFilter, EventResult, IEventHandler},
+	plugin::{
+		async_trait,
+		CommandInvocation,
+		IPluginApi,
+		Plugin,
+		PluginApi,
+		PluginDetails,
+	},
+	prelude::ClientDetails,
+};
+use libloading::Library;
+use log::warn;
+use tokio::{sync::Mutex, task::JoinHandle, time::sleep};
+
+use crate::{
+	metrics::METRICS,
+	plugin::{
+		plugin_api::ServerPluginApi,
+		plugin_entry::PluginExecutionState::{Paused, Running, Stopped},
+		PermissionDenied,
+		PluginAccess,
+		PluginPermission,
+		PluginPermissions,
+		PluginScope,
+	},
+};
+
+/// how often a running plugins run method is called.
+const RUN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// # PluginEntryObj
+/// Type alias for shared plugin entries.
+pub type PluginEntryObj = Arc<PluginEntry>;
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
-pub(crate) enum PluginExecutionState {
+pub enum PluginExecutionState {
 	Running,
 	Paused,
 	Stopped,
@@ -40,132 +48,179 @@ pub(crate) enum PluginExecutionState {
 /// Used to provide an api for the plugin to use.
 /// Also acts as gatekeeper to server data with permissions.
 #[derive(Debug)]
-pub(crate) struct PluginEntry<T>
-where
-	T: Sync + Send,
-{
+pub struct PluginEntry {
 	server_permission: PluginPermission,
 	network_permission: PluginPermission,
 	client_manager_permission: PluginPermission,
 	client_permission: PluginPermission,
 
+	path: PathBuf,
 	state: Arc<Mutex<PluginExecutionState>>,
+	task: Mutex<Option<JoinHandle<()>>>,
 
-	plugin: Plugin<EventType<'static>>,
-}
+	api: Arc<ServerPluginApi>,
+	plugin: Plugin,
 
-impl<T> PluginEntry<T>
-where
-	T: Sync + Send,
-{
-	pub fn new(plugin: Plugin<EventType>) -> Arc<PluginEntry<T>> {
-		let entry = Arc::new(PluginEntry {
-			server_permission: PluginPermission::None,
-			network_permission: PluginPermission::None,
-			client_manager_permission: PluginPermission::None,
-			client_permission: PluginPermission::None,
+	/// the plugins code, which must outlive the plugin.
+	/// Fields are dropped in order, so this has to stay last.
+	/// Scripts have no library.
+	_library: Option<Library>,
+}
 
+impl PluginEntry {
+	pub fn new(
+		plugin: Plugin,
+		library: Option<Library>,
+		path: PathBuf,
+		permissions: PluginPermissions,
+		api: ServerPluginApi,
+	) -> Self {
+		Self {
+			server_permission: permissions.server,
+			network_permission: permissions.network,
+			client_manager_permission: permissions.client_manager,
+			client_permission: permissions.client,
+
+			path,
 			state: Arc::new(Mutex::new(Stopped)),
+			task: Mutex::new(None),
+
+			api: Arc::new(api),
+			plugin,
+			_library: library,
+		}
+	}
 
-			plugin: plugin.clone(),
-		});
+	pub fn details(&self) -> PluginDetails {
+		self.plugin.details()
+	}
 
-		let entry_ref = entry.clone() as PluginInterface<T>;
+	/// The identity the plugins messages are sent as.
+	pub fn bot(&self) -> ClientDetails {
+		self.api.bot()
+	}
 
-		plugin.set_interface(Arc::downgrade(&entry_ref));
-		entry
+	pub fn event_filter(&self) -> EventFilter {
+		self.plugin.event_filter()
 	}
 
-	pub(crate) async fn getState(&self) -> PluginExecutionState {
+	pub fn permissions(&self) -> PluginPermissions {
+		PluginPermissions {
+			server: self.server_permission,
+			network: self.network_permission,
+			client_manager: self.client_manager_permission,
+			client: self.client_permission,
+		}
+	}
+
+	pub fn check(
+		&self,
+		scope: PluginScope,
+		access: PluginAccess,
+	) -> Result<(), PermissionDenied> {
+		self
+			.permissions()
+			.check(&self.plugin.details().id, scope, access)
+	}
+
+	pub fn get_path(&self) -> PathBuf {
+		self.path.clone()
+	}
+
+	pub async fn get_state(&self) -> PluginExecutionState {
 		*self.state.lock().await
 	}
 
-	pub fn start(&self) {
-		let cont = self.plugin.clone();
-		let state = self.state.clone();
-		tokio::spawn(async move {
-			let local_state = state.clone();
-			let mut lock = local_state.lock().await;
-			match *lock {
-				Running => (),
-				Paused => {
-					*lock = Running;
-				}
-				Stopped => {
-					tokio::spawn(async move {
-						cont.init();
-						let mut lock = state.lock().await;
-						*lock = Running;
-						loop {
-							match *lock {
-								Running => cont.run().await,
-								Paused => sleep(Duration::new(1, 0)).await,
-								Stopped => break,
-							}
+	/// Starts the plugin, or resumes it if paused.
+	pub async fn start(&self) {
+		let mut state = self.state.lock().await;
+		match *state {
+			Running => (),
+			Paused => *state = Running,
+			Stopped => {
+				*state = Running;
+
+				let plugin = self.plugin.clone();
+				let api: PluginApi = self.api.clone();
+				let state = self.state.clone();
+				let task = tokio::spawn(async move {
+					plugin.init(api);
+					loop {
+						let current = *state.lock().await;
+						match current {
+							Running => plugin.run().await,
+							Paused => (),
+							Stopped => break,
 						}
-						cont.deinit()
-					});
-				}
+						sleep(RUN_INTERVAL).await;
+					}
+					plugin.deinit()
+				});
+				*self.task.lock().await = Some(task);
 			}
-		});
+		}
 	}
 
-	pub fn pause(&self) {
-		let state = self.state.clone();
-		tokio::spawn(async move {
-			let mut lock = state.lock().await;
-			match *lock {
-				Running => {
-					*lock = Paused;
-				}
-				Paused => (),
-				Stopped => (),
-			}
-		});
+	/// Runs one of the plugins commands, replying to the user who ran it.
+	pub async fn run_command(&self, command: CommandInvocation) {
+		if self.get_state().await != Running {
+			let reply = format!("/{} is unavailable right now", command.name);
+			self.api.reply(command.from, reply);
+			return;
+		}
+
+		if let Some(reply) = self.plugin.on_command(&command).await {
+			self.api.reply(command.from, reply);
+		}
 	}
 
-	pub fn stop(&self) {
-		let state = self.state.clone();
-		tokio::spawn(async move {
-			let mut lock = state.lock().await;
-			match *lock {
-				Running => {
-					*lock = Stopped;
-				}
-				Paused => {
-					*lock = Stopped;
-				}
-				Stopped => (),
-			}
-		});
+	pub async fn pause(&self) {
+		let mut state = self.state.lock().await;
+		if *state == Running {
+			*state = Paused;
+		}
 	}
-}
 
-impl<T> IPluginInterface<T> for PluginEntry {
-	fn send_event(&self, _event: Event<EventType>) -> Receiver<EventResult> {
-		todo!()
+	/// Stops the plugin, waiting for it to deinitialise.
+	pub async fn stop(&self) {
+		*self.state.lock().await = Stopped;
+
+		let task = self.task.lock().await.take();
+		if let Some(task) = task {
+			if let Err(e) = task.await {
+				warn!(
+					plugin = self.plugin.details().id.as_str();
+					"failed while stopping: {}", e
+				);
+				METRICS.plugin_errors.inc(&self.plugin.details().id);
+			}
+		}
 	}
 }
 
-impl IResponder<EventType<'_>> for PluginEntry {
-	fn on_event(&self, event: Event<EventType>) {
-		use EventType::{ClientAdded, Custom, NewConnection};
-		use PluginPermission::{None, Read, ReadWrite, Write};
-
-		match (
-			&event.r#type,
-			&self.network_permission,
-			&self.client_manager_permission,
-			&self.client_permission,
-			&self.server_permission,
-		) {
-			(NewConnection, Read | ReadWrite, _, _, _) => self.plugin.on_event(event),
-			(ClientAdded(id), _, Read | ReadWrite, _, _) => self.plugin.on_event(event),
-			(Custom("ping"), _, _, _, _) => println!("[PluginEntry:on_event] Ping!"),
-			_ => println!("[PluginEntry:on_event] not handled"),
-		};
-	}
-	fn get_next(&self) -> Option<Weak<dyn IResponder<EventType>>> {
-		todo!()
+/// Events are only given to running plugins allowed to read them,
+/// and only plugins allowed to write may veto or rewrite them.
+#[async_trait]
+impl IEventHandler for PluginEntry {
+	async fn on_event(&self, event: &Event) -> EventResult {
+		let scope = PluginScope::of_event(event.kind());
+		if self.get_state().await != Running
+			|| self.check(scope, PluginAccess::Read).is_err()
+		{
+			return EventResult::Continue;
+		}
+
+		let result = self.plugin.on_event(event).await;
+		if result == EventResult::Continue {
+			return result;
+		}
+
+		match self.check(scope, PluginAccess::Write) {
+			Ok(()) => result,
+			Err(e) => {
+				warn!("ignoring reply to {:?}: {}", event.kind(), e);
+				EventResult::Continue
+			}
+		}
 	}
 }
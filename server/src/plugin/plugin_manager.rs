@@ -1,19 +1,108 @@
-use std::fs::Metadata;
-use std::{io::Error, mem, sync::Arc};
+use std::{
+	collections::HashMap,
+	env::{consts::DLL_EXTENSION, temp_dir},
+	fmt,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
 
+use foundation::{
+	admin::PluginStatus,
+	plugin::{
+		ApiVersionFn,
+		CommandInvocation,
+		GetPluginFn,
+		Plugin,
+		SetLoggerFn,
+		API_VERSION_SYMBOL,
+		GET_PLUGIN_SYMBOL,
+		PLUGIN_API_VERSION,
+		SET_LOGGER_SYMBOL,
+	},
+};
 use libloading::Library;
+use log::{info, warn};
+use tokio::{
+	fs::{create_dir_all, read_dir},
+	sync::{mpsc::UnboundedSender, Mutex},
+};
+use uuid::Uuid;
 
-use tokio::fs::{create_dir, read_dir, DirEntry};
-use tokio::sync::mpsc::Sender;
-use tokio::sync::Mutex;
+use crate::{
+	config::{PluginConfig, ScriptConfig},
+	connection::connection_manager::ConnectionManagerMessage,
+	event_bus::EventBus,
+	metrics::METRICS,
+	plugin::{
+		plugin_entry::{PluginEntry, PluginEntryObj},
+		PluginManifest,
+		PluginPermissions,
+		ServerPluginApi,
+	},
+	scripting::{is_script, load_script},
+	server_va::ServerMessages,
+};
 
-use futures::future::join_all;
+/// # PluginLoadError
+/// Reasons a plugin library couldn't be loaded.
+#[derive(Debug)]
+pub enum PluginLoadError {
+	Library(libloading::Error),
+	MissingSymbol(&'static str, libloading::Error),
+	ApiVersion { expected: u32, found: u32 },
+	Manifest(std::io::Error),
+	Copy(std::io::Error),
+	Script(String),
+}
+
+impl fmt::Display for PluginLoadError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Library(e) => write!(f, "failed to open library: {}", e),
+			Self::MissingSymbol(symbol, e) => {
+				write!(f, "missing symbol {}: {}", symbol, e)
+			}
+			Self::ApiVersion { expected, found } => write!(
+				f,
+				"built for plugin api version {}, the server uses {}",
+				found, expected
+			),
+			Self::Manifest(e) => write!(f, "failed to read manifest: {}", e),
+			Self::Copy(e) => write!(f, "failed to copy library: {}", e),
+			Self::Script(e) => write!(f, "invalid script: {}", e),
+		}
+	}
+}
+
+/// # PluginError
+/// Reasons a plugin couldn't be managed.
+#[derive(Debug)]
+pub enum PluginError {
+	NotFound(String),
+	AlreadyLoaded(String),
+	Load(PluginLoadError),
+}
+
+impl From<PluginLoadError> for PluginError {
+	fn from(e: PluginLoadError) -> Self {
+		Self::Load(e)
+	}
+}
 
-use crate::plugin::plugin::GetPluginFn;
-use crate::plugin::plugin_entry::{PluginEntry, PluginEntryObj};
+impl fmt::Display for PluginError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::NotFound(id) => write!(f, "no plugin {}", id),
+			Self::AlreadyLoaded(id) => write!(f, "{} is already loaded", id),
+			Self::Load(e) => write!(f, "{}", e),
+		}
+	}
+}
 
-pub enum PluginManagerMessage {
-	None,
+/// A plugin along with its event subscription.
+struct LoadedPlugin {
+	entry: PluginEntryObj,
+	subscription: Uuid,
 }
 
 /// # PluginManager
@@ -21,89 +110,310 @@ pub enum PluginManagerMessage {
 ///
 /// ## Attributes
 /// - plugins: A [Vec] of all loaded plugins
-/// - server_channel: A [Sender]
-pub struct PluginManager<Out: 'static>
-where
-	Out: From<PluginManagerMessage> + Send,
-{
-	#[allow(dead_code)]
-	plugins: Mutex<Vec<PluginEntryObj>>,
-
-	#[allow(dead_code)]
-	server_channel: Mutex<Sender<Out>>,
+/// - directory: where plugin libraries and scripts are loaded from
+/// - scripts: limits for plugins written as scripts
+/// - approved: the permissions admins have approved, by file name
+/// - event_bus: where plugins are subscribed to events
+/// - connection_manager_sender, server_sender: where plugin api calls are sent
+pub struct PluginManager {
+	plugins: Mutex<Vec<LoadedPlugin>>,
+	directory: PathBuf,
+	scripts: ScriptConfig,
+	approved: HashMap<String, PluginPermissions>,
+	event_bus: Arc<EventBus>,
+	connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+	server_sender: UnboundedSender<ServerMessages>,
 }
 
-impl<Out: 'static> PluginManager<Out>
-where
-	Out: From<PluginManagerMessage> + Send,
-{
-	/// Creates a new plugin manager with sender.
-	pub fn new(channel: Sender<Out>) -> Arc<Self> {
+impl PluginManager {
+	/// Creates a new plugin manager for the configured directory.
+	pub fn new(
+		config: PluginConfig,
+		event_bus: Arc<EventBus>,
+		connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+		server_sender: UnboundedSender<ServerMessages>,
+	) -> Arc<Self> {
 		Arc::new(Self {
 			plugins: Mutex::new(Vec::new()),
-			server_channel: Mutex::new(channel),
+			directory: config.directory,
+			scripts: config.scripts,
+			approved: config.approved,
+			event_bus,
+			connection_manager_sender,
+			server_sender,
 		})
 	}
 
-	/// Starts loading plugins from the plugins directory.
-	/// If this directory isn't found then create it get created.
-	pub async fn load(&self) -> Result<(), Error> {
-		println!("[PluginManager]: loading plugins");
-		println!(
-			"[PluginManager]: from: {}",
-			std::env::current_dir().unwrap().to_string_lossy()
-		);
+	/// Loads and starts every plugin and script in the plugins directory.
+	/// If this directory isn't found then it gets created.
+	///
+	/// Plugins that fail to load are reported and skipped.
+	pub async fn load(&self) -> std::io::Result<()> {
+		info!(path:% = self.directory.display(); "loading plugins");
+
+		if !self.directory.exists() {
+			create_dir_all(&self.directory).await?;
+			return Ok(());
+		}
 
-		if let Ok(mut plugins) = read_dir("./plugins").await {
-			// Todo: - make this concurrent
-			let mut plugin_vec = vec![];
-			while let Some(next) = plugins.next_entry().await? {
-				println!("{:?}", next);
-				plugin_vec.push(next)
+		let mut entries = read_dir(&self.directory).await?;
+
+		while let Some(entry) = entries.next_entry().await? {
+			let path = entry.path();
+			let is_library = path.extension().is_some_and(|e| e == DLL_EXTENSION);
+
+			if !(is_library || is_script(&path)) || !entry.metadata().await?.is_file()
+			{
+				continue;
 			}
 
-			// get all entries by extension
-			let entries: Vec<DirEntry> = plugin_vec
-				.into_iter()
-				.filter(|item| item.path().extension().unwrap_or_default() == "dylib")
-				.collect();
-
-			// get entry metadata
-			#[allow(clippy::needless_collect)] // This is a false positive. Collect is needed here
-			let metadata: Vec<Metadata> = join_all(entries.iter().map(|item| item.metadata()))
-				.await
-				.into_iter()
-				.filter_map(|item| item.ok())
-				.collect();
-
-			// convert correct ones to plugins
-			let plugins: Vec<PluginEntryObj> = entries
-				.into_iter()
-				.zip(metadata.into_iter())
-				.filter(|(_item, meta)| meta.is_file())
-				.map(|item| item.0)
-				.map(|item| unsafe {
-					let lib = Library::new(item.path()).unwrap();
-					let plugin_fn = lib.get::<GetPluginFn<()>>("get_plugin".as_ref()).unwrap();
-					PluginEntry::new(plugin_fn())
-				})
-				.collect();
-
-			println!("[PluginManager:load] got plugins: {:?}", plugins);
-
-			let mut self_vec = self.plugins.lock().await;
-			let _ = mem::replace(&mut *self_vec, plugins);
-		} else {
-			create_dir("./plugins").await?;
+			if let Err(e) = self.load_path(&path).await {
+				warn!(path:% = path.display(); "failed to load plugin: {}", e);
+				// the id isn't known until the plugin loads
+				METRICS.plugin_errors.inc(&path.display().to_string());
+			}
+		}
+		Ok(())
+	}
+
+	/// Loads and starts a library or script from the plugins directory.
+	pub async fn load_file(&self, file: &str) -> Result<(), PluginError> {
+		// only the file name is used, so libraries can't be loaded from elsewhere
+		let Some(name) = Path::new(file).file_name() else {
+			return Err(PluginError::NotFound(file.to_string()));
+		};
+		let path = self.directory.join(name);
+		if !path.is_file() {
+			return Err(PluginError::NotFound(file.to_string()));
 		}
 
+		self.load_path(&path).await
+	}
+
+	/// Lists every loaded plugin with its state.
+	pub async fn list(&self) -> Vec<PluginStatus> {
+		let entries: Vec<PluginEntryObj> = self
+			.plugins
+			.lock()
+			.await
+			.iter()
+			.map(|p| p.entry.clone())
+			.collect();
+
+		let mut statuses = Vec::new();
+		for entry in entries {
+			statuses.push(PluginStatus {
+				details: entry.details(),
+				path: entry.get_path().display().to_string(),
+				state: format!("{:?}", entry.get_state().await),
+			});
+		}
+		statuses
+	}
+
+	/// Starts a stopped plugin, or resumes a paused one.
+	pub async fn start(&self, id: &str) -> Result<(), PluginError> {
+		self.get(id).await?.start().await;
+		Ok(())
+	}
+
+	pub async fn pause(&self, id: &str) -> Result<(), PluginError> {
+		self.get(id).await?.pause().await;
+		Ok(())
+	}
+
+	/// Stops a plugin, leaving it loaded so it can be started again.
+	pub async fn stop(&self, id: &str) -> Result<(), PluginError> {
+		self.get(id).await?.stop().await;
+		Ok(())
+	}
+
+	/// Unloads a plugin, then loads its library again.
+	/// Used to pick up a library replaced since it was loaded.
+	pub async fn reload(&self, id: &str) -> Result<(), PluginError> {
+		let path = self.get(id).await?.get_path();
+		self.unload(id).await?;
+		self.load_path(&path).await
+	}
+
+	/// Stops a plugin and unloads its library.
+	pub async fn unload(&self, id: &str) -> Result<(), PluginError> {
+		let plugin = {
+			let mut plugins = self.plugins.lock().await;
+			let Some(index) = plugins.iter().position(|p| p.entry.details().id == id)
+			else {
+				return Err(PluginError::NotFound(id.to_string()));
+			};
+			plugins.remove(index)
+		};
+
+		self.remove(plugin).await;
+		Ok(())
+	}
+
+	/// Stops every plugin, then unloads their libraries.
+	pub async fn unload_all(&self) {
+		let plugins = std::mem::take(&mut *self.plugins.lock().await);
+
+		for plugin in plugins {
+			self.remove(plugin).await;
+		}
+	}
+
+	/// Runs a command registered by the plugin with the given id.
+	pub async fn run_command(&self, plugin_id: &str, command: CommandInvocation) {
+		match self.get(plugin_id).await {
+			Ok(entry) => entry.run_command(command).await,
+			Err(e) => warn!("can't run command: {}", e),
+		}
+	}
+
+	async fn get(&self, id: &str) -> Result<PluginEntryObj, PluginError> {
 		self
 			.plugins
 			.lock()
 			.await
 			.iter()
-			.for_each(|item| item.start());
+			.find(|p| p.entry.details().id == id)
+			.map(|p| p.entry.clone())
+			.ok_or_else(|| PluginError::NotFound(id.to_string()))
+	}
+
+	/// Loads, starts and subscribes the plugin at the path.
+	async fn load_path(&self, path: &Path) -> Result<(), PluginError> {
+		let entry = Arc::new(self.load_plugin(path)?);
+		let id = entry.details().id;
 
+		let mut plugins = self.plugins.lock().await;
+		if plugins.iter().any(|p| p.entry.details().id == id) {
+			return Err(PluginError::AlreadyLoaded(id));
+		}
+
+		info!(plugin = id.as_str(), path:% = path.display(); "loaded plugin");
+		entry.start().await;
+		let subscription = self
+			.event_bus
+			.subscribe(id, entry.event_filter(), entry.clone())
+			.await;
+		plugins.push(LoadedPlugin {
+			entry,
+			subscription,
+		});
 		Ok(())
 	}
+
+	/// Stops the plugin and removes everything it registered.
+	/// Its library is unloaded once the last reference is dropped.
+	async fn remove(&self, plugin: LoadedPlugin) {
+		let details = plugin.entry.details();
+		info!(plugin = details.id.as_str(); "unloading plugin");
+
+		self.event_bus.unsubscribe(plugin.subscription).await;
+		plugin.entry.stop().await;
+		_ = self.connection_manager_sender.send(
+			ConnectionManagerMessage::PluginUnloaded {
+				plugin_id: details.id,
+				bot: plugin.entry.bot(),
+			},
+		);
+	}
+
+	/// Opens a plugin library or script.
+	/// The plugin is granted what its manifest asks for, up to what is approved.
+	fn load_plugin(&self, path: &Path) -> Result<PluginEntry, PluginLoadError> {
+		let manifest =
+			PluginManifest::load(path).map_err(PluginLoadError::Manifest)?;
+
+		let (plugin, library) = if is_script(path) {
+			let plugin =
+				load_script(path, &self.scripts).map_err(PluginLoadError::Script)?;
+			(plugin, None)
+		} else {
+			let (plugin, library) = Self::open_library(path)?;
+			(plugin, Some(library))
+		};
+
+		let details = plugin.details();
+		let id = &details.id;
+		// a plugin can report any id, so approvals go by the file it was loaded from
+		let file = path.file_name().unwrap_or_default().to_string_lossy();
+		let requested = manifest.permissions;
+		let granted = self
+			.approved
+			.get(file.as_ref())
+			.map(|approved| requested.intersect(approved))
+			.unwrap_or_default();
+
+		if granted != requested {
+			warn!(
+				plugin = id.as_str();
+				"asked for {:?} but is only approved for {:?}",
+				requested,
+				granted
+			);
+		}
+
+		let api = ServerPluginApi::new(
+			&details,
+			granted.clone(),
+			self.connection_manager_sender.clone(),
+			self.server_sender.clone(),
+		);
+
+		Ok(PluginEntry::new(
+			plugin,
+			library,
+			path.to_path_buf(),
+			granted,
+			api,
+		))
+	}
+
+	/// Opens a plugin library, checking its api version before constructing it.
+	///
+	/// The library is loaded from a copy,
+	/// so it can be replaced while loaded and reloaded with the new code.
+	fn open_library(path: &Path) -> Result<(Plugin, Library), PluginLoadError> {
+		let copy = temp_dir().join(format!(
+			"chatkit-plugin-{}.{}",
+			Uuid::new_v4(),
+			DLL_EXTENSION
+		));
+		std::fs::copy(path, &copy).map_err(PluginLoadError::Copy)?;
+
+		// safety: loading a library runs its initialisers,
+		// plugins are trusted to be well behaved rust libraries.
+		let library = unsafe { Library::new(&copy) };
+		// the library stays mapped once loaded, on windows this fails harmlessly
+		_ = std::fs::remove_file(&copy);
+		let library = library.map_err(PluginLoadError::Library)?;
+
+		// safety: the symbols are declared by [foundation::declare_plugin],
+		// so have the expected types if the api versions match.
+		unsafe {
+			let api_version = library
+				.get::<ApiVersionFn>(API_VERSION_SYMBOL)
+				.map_err(|e| PluginLoadError::MissingSymbol("plugin_api_version", e))?;
+
+			let found = api_version();
+			if found != PLUGIN_API_VERSION {
+				return Err(PluginLoadError::ApiVersion {
+					expected: PLUGIN_API_VERSION,
+					found,
+				});
+			}
+
+			let set_logger = library
+				.get::<SetLoggerFn>(SET_LOGGER_SYMBOL)
+				.map_err(|e| PluginLoadError::MissingSymbol("set_logger", e))?;
+			set_logger(log::logger(), log::max_level());
+
+			let get_plugin = library
+				.get::<GetPluginFn>(GET_PLUGIN_SYMBOL)
+				.map_err(|e| PluginLoadError::MissingSymbol("get_plugin", e))?;
+
+			let plugin = get_plugin();
+			Ok((plugin, library))
+		}
+	}
 }
@@ -0,0 +1,195 @@
+use std::{fmt, io, path::Path};
+
+use foundation::event::EventKind;
+use serde::{Deserialize, Serialize};
+
+/// # PluginPermission
+/// The access a plugin has to one area of the server.
+#[derive(
+	Serialize,
+	Deserialize,
+	Debug,
+	Default,
+	Clone,
+	Copy,
+	Ord,
+	PartialOrd,
+	Eq,
+	PartialEq,
+)]
+pub enum PluginPermission {
+	Read,
+	Write,
+	ReadWrite,
+	#[default]
+	None,
+}
+
+impl PluginPermission {
+	fn new(read: bool, write: bool) -> Self {
+		match (read, write) {
+			(true, true) => Self::ReadWrite,
+			(true, false) => Self::Read,
+			(false, true) => Self::Write,
+			(false, false) => Self::None,
+		}
+	}
+
+	pub fn can_read(self) -> bool {
+		matches!(self, Self::Read | Self::ReadWrite)
+	}
+
+	pub fn can_write(self) -> bool {
+		matches!(self, Self::Write | Self::ReadWrite)
+	}
+
+	pub fn allows(self, access: PluginAccess) -> bool {
+		match access {
+			PluginAccess::Read => self.can_read(),
+			PluginAccess::Write => self.can_write(),
+		}
+	}
+
+	/// The access granted by both permissions.
+	pub fn intersect(self, other: Self) -> Self {
+		Self::new(
+			self.can_read() && other.can_read(),
+			self.can_write() && other.can_write(),
+		)
+	}
+}
+
+/// # PluginScope
+/// The areas of the server a plugin can be given access to.
+///
+/// - Server: global messages, history and the servers own lifecycle.
+/// - Network: incoming connections.
+/// - ClientManager: the client list, and disconnecting users.
+/// - Client: private messages between users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginScope {
+	Server,
+	Network,
+	ClientManager,
+	Client,
+}
+
+impl PluginScope {
+	/// The scope an event belongs to.
+	pub fn of_event(kind: EventKind) -> Self {
+		match kind {
+			EventKind::ConnectionAccepted => Self::Network,
+			EventKind::ClientJoined | EventKind::ClientLeft => Self::ClientManager,
+			EventKind::GlobalMessage | EventKind::Shutdown => Self::Server,
+			EventKind::PrivateMessage => Self::Client,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginAccess {
+	Read,
+	Write,
+}
+
+/// # PermissionDenied
+/// Returned when a plugin uses access it wasn't granted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionDenied {
+	pub plugin: String,
+	pub scope: PluginScope,
+	pub access: PluginAccess,
+}
+
+impl fmt::Display for PermissionDenied {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} has no {:?} access to {:?}",
+			self.plugin, self.access, self.scope
+		)
+	}
+}
+
+/// # PluginPermissions
+/// A plugins access to each area of the server.
+/// Used both for what a plugin asks for, and what an admin approves.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct PluginPermissions {
+	pub server: PluginPermission,
+	pub network: PluginPermission,
+	pub client_manager: PluginPermission,
+	pub client: PluginPermission,
+}
+
+impl PluginPermissions {
+	pub fn get(&self, scope: PluginScope) -> PluginPermission {
+		match scope {
+			PluginScope::Server => self.server,
+			PluginScope::Network => self.network,
+			PluginScope::ClientManager => self.client_manager,
+			PluginScope::Client => self.client,
+		}
+	}
+
+	/// Checks the plugin was granted the access,
+	/// called before anything is done on the plugins behalf.
+	pub fn check(
+		&self,
+		plugin: &str,
+		scope: PluginScope,
+		access: PluginAccess,
+	) -> Result<(), PermissionDenied> {
+		if self.get(scope).allows(access) {
+			return Ok(());
+		}
+
+		Err(PermissionDenied {
+			plugin: plugin.to_string(),
+			scope,
+			access,
+		})
+	}
+
+	/// The permissions both requested and approved.
+	pub fn intersect(&self, other: &Self) -> Self {
+		Self {
+			server: self.server.intersect(other.server),
+			network: self.network.intersect(other.network),
+			client_manager: self.client_manager.intersect(other.client_manager),
+			client: self.client.intersect(other.client),
+		}
+	}
+}
+
+/// # PluginManifest
+/// Declares what a plugin needs,
+/// read from a toml file next to its library with the same name.
+///
+/// ```toml
+/// [permissions]
+/// server = "Read"
+/// client_manager = "ReadWrite"
+/// ```
+///
+/// Plugins without a manifest ask for nothing.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct PluginManifest {
+	pub permissions: PluginPermissions,
+}
+
+impl PluginManifest {
+	/// Reads the manifest for the library at the given path.
+	pub fn load(library: &Path) -> io::Result<Self> {
+		let path = library.with_extension("toml");
+		if !path.exists() {
+			return Ok(Self::default());
+		}
+
+		let contents = std::fs::read_to_string(path)?;
+		toml::from_str(&contents)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+	}
+}
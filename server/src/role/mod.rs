@@ -0,0 +1,149 @@
+//! What each user is allowed to do.
+//!
+//! Every user has a role, which grants a fixed set of permissions.
+//! Roles are ranked, so users can only manage those below them.
+
+mod role_store;
+
+use std::{fmt, str::FromStr};
+
+pub use role_store::RoleStore;
+use serde::{Deserialize, Serialize};
+
+/// # Permission
+/// Something a user may be allowed to do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Permission {
+	PostGlobal,
+	PrivateMessage,
+	CreateRoom,
+	/// kicking, banning and muting users.
+	Moderate,
+	/// changing other users roles.
+	ManageRoles,
+}
+
+impl fmt::Display for Permission {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let description = match self {
+			Self::PostGlobal => "post global messages",
+			Self::PrivateMessage => "send private messages",
+			Self::CreateRoom => "create rooms",
+			Self::Moderate => "moderate users",
+			Self::ManageRoles => "manage roles",
+		};
+		write!(f, "{}", description)
+	}
+}
+
+/// # Role
+/// A users standing on the server, from lowest to highest.
+#[derive(
+	Serialize,
+	Deserialize,
+	Debug,
+	Default,
+	Clone,
+	Copy,
+	Hash,
+	Ord,
+	PartialOrd,
+	Eq,
+	PartialEq,
+)]
+pub enum Role {
+	/// can only read.
+	Guest,
+	#[default]
+	Member,
+	Moderator,
+	Admin,
+	/// may do anything, including making admins.
+	Owner,
+}
+
+impl Role {
+	pub fn permissions(self) -> &'static [Permission] {
+		use Permission::*;
+		match self {
+			Self::Guest => &[],
+			Self::Member => &[PostGlobal, PrivateMessage, CreateRoom],
+			Self::Moderator => &[PostGlobal, PrivateMessage, CreateRoom, Moderate],
+			Self::Admin | Self::Owner => &[
+				PostGlobal,
+				PrivateMessage,
+				CreateRoom,
+				Moderate,
+				ManageRoles,
+			],
+		}
+	}
+
+	pub fn has(self, permission: Permission) -> bool {
+		self.permissions().contains(&permission)
+	}
+
+	/// Whether a user with this role may act against one with the other.
+	/// Owners may act against anyone, everyone else only against lower roles.
+	pub fn outranks(self, other: Self) -> bool {
+		self == Self::Owner || self > other
+	}
+}
+
+impl fmt::Display for Role {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			Self::Guest => "guest",
+			Self::Member => "member",
+			Self::Moderator => "moderator",
+			Self::Admin => "admin",
+			Self::Owner => "owner",
+		};
+		write!(f, "{}", name)
+	}
+}
+
+impl FromStr for Role {
+	type Err = String;
+
+	fn from_str(role: &str) -> Result<Self, Self::Err> {
+		match role.to_ascii_lowercase().as_str() {
+			"guest" => Ok(Self::Guest),
+			"member" => Ok(Self::Member),
+			"moderator" => Ok(Self::Moderator),
+			"admin" => Ok(Self::Admin),
+			"owner" => Ok(Self::Owner),
+			_ => Err(format!("unknown role {}", role)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn higher_roles_outrank_lower_ones() {
+		assert!(Role::Admin.outranks(Role::Moderator));
+		assert!(Role::Moderator.outranks(Role::Member));
+		assert!(!Role::Member.outranks(Role::Moderator));
+	}
+
+	#[test]
+	fn equal_roles_dont_outrank_each_other() {
+		assert!(!Role::Moderator.outranks(Role::Moderator));
+		assert!(!Role::Admin.outranks(Role::Admin));
+	}
+
+	#[test]
+	fn owners_outrank_everyone() {
+		assert!(Role::Owner.outranks(Role::Owner));
+		assert!(Role::Owner.outranks(Role::Guest));
+	}
+
+	#[test]
+	fn guests_can_only_read() {
+		assert!(!Role::Guest.has(Permission::PostGlobal));
+		assert!(Role::Member.has(Permission::PostGlobal));
+	}
+}
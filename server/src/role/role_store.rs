@@ -0,0 +1,76 @@
+use std::{collections::HashMap, fs, io, path::PathBuf};
+
+use log::error;
+use uuid::Uuid;
+
+use crate::{config::RoleConfig, role::Role};
+
+/// # RoleStore
+/// Each users role, saved as json whenever one changes.
+///
+/// Roles set in the config can't be changed while the server runs,
+/// so the owner can't be demoted by mistake.
+#[derive(Debug)]
+pub struct RoleStore {
+	path: PathBuf,
+	default: Role,
+	/// roles from the config.
+	fixed: HashMap<Uuid, Role>,
+	roles: HashMap<Uuid, Role>,
+}
+
+impl RoleStore {
+	/// Loads the roles from the configs file.
+	/// If the file doesn't exist everyone has the default role.
+	/// An invalid file is an error rather than being emptied,
+	/// as the next save would overwrite it.
+	pub fn load(config: &RoleConfig) -> Result<Self, String> {
+		let path = &config.file;
+		let roles = match fs::read_to_string(path) {
+			Ok(json) => serde_json::from_str(&json)
+				.map_err(|e| format!("invalid roles {}: {}", path.display(), e))?,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+			Err(e) => return Err(format!("can't read {}: {}", path.display(), e)),
+		};
+
+		Ok(Self {
+			path: path.clone(),
+			default: config.default,
+			fixed: config.users.clone(),
+			roles,
+		})
+	}
+
+	pub fn get(&self, uuid: Uuid) -> Role {
+		self
+			.fixed
+			.get(&uuid)
+			.or_else(|| self.roles.get(&uuid))
+			.copied()
+			.unwrap_or(self.default)
+	}
+
+	pub fn set(&mut self, uuid: Uuid, role: Role) -> Result<(), String> {
+		if self.fixed.contains_key(&uuid) {
+			return Err("that role is set in the config".into());
+		}
+
+		if role == self.default {
+			self.roles.remove(&uuid);
+		} else {
+			self.roles.insert(uuid, role);
+		}
+		self.save();
+		Ok(())
+	}
+
+	fn save(&self) {
+		let result = serde_json::to_string_pretty(&self.roles)
+			.map_err(|e| e.to_string())
+			.and_then(|json| fs::write(&self.path, json).map_err(|e| e.to_string()));
+
+		if let Err(e) = result {
+			error!(path:? = self.path; "failed to save roles: {}", e);
+		}
+	}
+}
@@ -0,0 +1,642 @@
+use std::{
+	cell::{Cell, RefCell},
+	collections::HashMap,
+	io,
+	path::{Path, PathBuf},
+	rc::Rc,
+};
+
+use foundation::{
+	event::{Event, EventFilter, EventResult},
+	plugin::{
+		async_trait,
+		CommandInvocation,
+		IPlugin,
+		PluginApi,
+		PluginApiError,
+		PluginDetails,
+	},
+	prelude::{ClientDetails, GlobalMessage},
+};
+use log::{error, info, warn};
+use mlua::{
+	FromLuaMulti,
+	Function,
+	HookTriggers,
+	IntoLuaMulti,
+	Lua,
+	LuaOptions,
+	LuaSerdeExt,
+	SerializeOptions,
+	StdLib,
+	Table,
+	Value,
+	Variadic,
+};
+use tokio::{runtime::Handle, sync::mpsc::UnboundedReceiver};
+use uuid::Uuid;
+
+use crate::{
+	config::ScriptConfig,
+	metrics::METRICS,
+	scripting::{
+		handler_name,
+		script_details,
+		ScriptMessage,
+		ScriptThread,
+		COMMAND_HANDLER,
+	},
+};
+
+/// how many instructions run between checks of the instruction limit.
+const HOOK_INTERVAL: u32 = 1000;
+
+/// Everything needed to start the script on its thread.
+#[derive(Debug, Clone)]
+struct Script {
+	details: PluginDetails,
+	source: String,
+	config: ScriptConfig,
+	/// where required scripts are found, if imports are allowed.
+	directory: PathBuf,
+	/// where the script's stored values are kept between runs.
+	store: PathBuf,
+}
+
+/// # LuaPlugin
+/// A plugin written in lua.
+///
+/// The script's top level is run when the plugin starts,
+/// and can use the `chat` table to register commands, send messages and list clients,
+/// and the `store` table to keep values between runs.
+/// Events are handled by global functions named after them, such as `on_global_message`,
+/// which are given the event as a table.
+/// Returning `false` vetoes the event, and returning a table rewrites it.
+/// Commands are handled by `on_command`, which may return a reply.
+///
+/// Handlers run as coroutines, so api calls wait for the server without blocking other plugins.
+#[derive(Debug)]
+pub struct LuaPlugin {
+	script: Script,
+	thread: ScriptThread,
+}
+
+impl LuaPlugin {
+	/// Reads and compiles the script, so errors are found when it's loaded.
+	pub fn load(path: &Path, config: &ScriptConfig) -> Result<Self, String> {
+		let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+		let script = Script {
+			details: script_details(path),
+			source,
+			config: config.clone(),
+			directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+			store: path.with_extension("data.json"),
+		};
+
+		Store::load(&script.store)?;
+
+		let lua = new_lua(&script).map_err(|e| e.to_string())?;
+		lua
+			.load(&script.source)
+			.set_name(&script.details.id)
+			.into_function()
+			.map_err(|e| e.to_string())?;
+
+		Ok(Self {
+			script,
+			thread: ScriptThread::default(),
+		})
+	}
+}
+
+#[async_trait]
+impl IPlugin for LuaPlugin {
+	fn details(&self) -> PluginDetails {
+		self.script.details.clone()
+	}
+
+	fn init(&self, api: PluginApi) {
+		let script = self.script.clone();
+		let handle = Handle::current();
+		self
+			.thread
+			.start(self.script.details.id.clone(), move |receiver| {
+				run_script(script, api, handle, receiver)
+			});
+	}
+
+	fn deinit(&self) {
+		self.thread.stop();
+	}
+
+	/// Handlers are only known once the script has run,
+	/// so every event is sent, and those without a handler are let through.
+	fn event_filter(&self) -> EventFilter {
+		EventFilter::All
+	}
+
+	async fn on_event(&self, event: &Event) -> EventResult {
+		self.thread.on_event(event).await
+	}
+
+	async fn on_command(&self, command: &CommandInvocation) -> Option<String> {
+		self.thread.on_command(command).await
+	}
+}
+
+/// # Store
+/// The values a script has stored, saved as json next to the script.
+/// The number of values and their size are limited by the script config.
+#[derive(Debug, Default)]
+struct Store {
+	values: HashMap<String, serde_json::Value>,
+	/// the size of every key and value, as json.
+	bytes: usize,
+	changed: bool,
+}
+
+impl Store {
+	/// Loads the values from the path, or none if it doesn't exist.
+	/// An invalid store is an error rather than being emptied,
+	/// as the next save would overwrite it.
+	fn load(path: &Path) -> Result<Self, String> {
+		let values: HashMap<String, serde_json::Value> =
+			match std::fs::read_to_string(path) {
+				Ok(json) => serde_json::from_str(&json)
+					.map_err(|e| format!("invalid store {}: {}", path.display(), e))?,
+				Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+				Err(e) => return Err(format!("can't read {}: {}", path.display(), e)),
+			};
+
+		let bytes = values.iter().map(|(k, v)| entry_size(k, v)).sum();
+		Ok(Self {
+			values,
+			bytes,
+			changed: false,
+		})
+	}
+
+	/// Stores the value, unless it would take the store over its limits.
+	fn set(
+		&mut self,
+		key: String,
+		value: serde_json::Value,
+		config: &ScriptConfig,
+	) -> Result<(), String> {
+		let old = self.values.get(&key).map(|v| entry_size(&key, v));
+		if old.is_none() && self.values.len() >= config.max_store_entries {
+			return Err(format!(
+				"the store is full, at {} values",
+				config.max_store_entries
+			));
+		}
+
+		let bytes = self.bytes - old.unwrap_or(0) + entry_size(&key, &value);
+		if bytes > config.max_store_bytes {
+			return Err(format!(
+				"the store would be over {} bytes",
+				config.max_store_bytes
+			));
+		}
+
+		self.bytes = bytes;
+		self.values.insert(key, value);
+		self.changed = true;
+		Ok(())
+	}
+
+	fn remove(&mut self, key: &str) {
+		if let Some(value) = self.values.remove(key) {
+			self.bytes -= entry_size(key, &value);
+			self.changed = true;
+		}
+	}
+
+	fn save(&mut self, path: &Path) {
+		if !self.changed {
+			return;
+		}
+		self.changed = false;
+
+		let result = serde_json::to_string_pretty(&self.values)
+			.map_err(|e| e.to_string())
+			.and_then(|json| std::fs::write(path, json).map_err(|e| e.to_string()));
+
+		if let Err(e) = result {
+			error!(path:? = path; "failed to save store: {}", e);
+		}
+	}
+}
+
+/// How much a value takes up in the store.
+fn entry_size(key: &str, value: &serde_json::Value) -> usize {
+	key.len() + value.to_string().len()
+}
+
+/// Runs the script's top level, then answers its messages until stopped.
+fn run_script(
+	script: Script,
+	api: PluginApi,
+	handle: Handle,
+	mut receiver: UnboundedReceiver<ScriptMessage>,
+) {
+	let id = script.details.id.clone();
+	let store = match Store::load(&script.store) {
+		Ok(store) => Rc::new(RefCell::new(store)),
+		Err(e) => {
+			error!(plugin = id.as_str(); "failed to load its store: {}", e);
+			METRICS.plugin_errors.inc(&id);
+			return;
+		}
+	};
+
+	// async functions need the coroutine library while they're created,
+	// so the globals are restricted after the api is registered.
+	let lua = match new_lua(&script).and_then(|lua| {
+		register_api(&lua, &script, api, store.clone())?;
+		restrict_globals(&lua, &script)?;
+		Ok(lua)
+	}) {
+		Ok(lua) => lua,
+		Err(e) => {
+			error!(plugin = id.as_str(); "failed to create its state: {}", e);
+			METRICS.plugin_errors.inc(&id);
+			return;
+		}
+	};
+
+	let main = lua.load(&script.source).set_name(&id).into_function();
+	let started = match main {
+		Ok(main) => handle.block_on(call::<()>(&lua, &script.config, main, ())),
+		Err(e) => Err(e),
+	};
+	if let Err(e) = started {
+		warn!(plugin = id.as_str(); "failed to start: {}", e);
+		METRICS.plugin_errors.inc(&id);
+	}
+	store.borrow_mut().save(&script.store);
+
+	while let Some(message) = receiver.blocking_recv() {
+		match message {
+			ScriptMessage::Event { event, reply } => {
+				let result = handle.block_on(handle_event(&lua, &script, &event));
+				_ = reply.send(result);
+			}
+			ScriptMessage::Command { command, reply } => {
+				let result = handle.block_on(handle_command(&lua, &script, &command));
+				_ = reply.send(result);
+			}
+		}
+		store.borrow_mut().save(&script.store);
+	}
+}
+
+async fn handle_event(
+	lua: &Lua,
+	script: &Script,
+	event: &Event,
+) -> EventResult {
+	let id = &script.details.id;
+	let name = handler_name(event.kind());
+	let handler = match lua.globals().get::<_, Option<Function>>(name) {
+		Ok(Some(handler)) => handler,
+		_ => return EventResult::Continue,
+	};
+
+	let result = match lua.to_value(event) {
+		Ok(value) => call::<Value>(lua, &script.config, handler, value).await,
+		Err(e) => Err(e),
+	};
+	let value = match result {
+		Ok(value) => value,
+		Err(e) => {
+			warn!(plugin = id; "failed in {}: {}", name, e);
+			METRICS.plugin_errors.inc(id);
+			return EventResult::Continue;
+		}
+	};
+
+	match value {
+		Value::Boolean(false) => EventResult::Veto,
+		Value::Table(_) => match lua.from_value::<Event>(value) {
+			Ok(event) => EventResult::Rewrite(event),
+			Err(e) => {
+				warn!(plugin = id; "returned an invalid event: {}", e);
+				METRICS.plugin_errors.inc(id);
+				EventResult::Continue
+			}
+		},
+		_ => EventResult::Continue,
+	}
+}
+
+async fn handle_command(
+	lua: &Lua,
+	script: &Script,
+	command: &CommandInvocation,
+) -> Option<String> {
+	let id = &script.details.id;
+	let handler = lua
+		.globals()
+		.get::<_, Option<Function>>(COMMAND_HANDLER)
+		.ok()
+		.flatten()?;
+
+	let result = match lua.to_value(command) {
+		Ok(value) => call::<Value>(lua, &script.config, handler, value).await,
+		Err(e) => Err(e),
+	};
+
+	match result {
+		Ok(Value::String(reply)) => reply.to_str().ok().map(String::from),
+		Ok(_) => None,
+		Err(e) => {
+			warn!(plugin = id; "failed in {}: {}", COMMAND_HANDLER, e);
+			METRICS.plugin_errors.inc(id);
+			None
+		}
+	}
+}
+
+/// Calls a function as a coroutine, stopping it if it runs too many instructions.
+/// Hooks only apply to the coroutine they are set on,
+/// which is why scripts aren't given the coroutine library.
+async fn call<'lua, R: FromLuaMulti<'lua>>(
+	lua: &'lua Lua,
+	config: &ScriptConfig,
+	function: Function<'lua>,
+	args: impl IntoLuaMulti<'lua>,
+) -> mlua::Result<R> {
+	let thread = lua.create_thread(function)?;
+	let max_checks = config.max_operations / u64::from(HOOK_INTERVAL);
+	let checks = Cell::new(0);
+
+	let triggers = HookTriggers::new().every_nth_instruction(HOOK_INTERVAL);
+	thread.set_hook(triggers, move |_, _| {
+		checks.set(checks.get() + 1);
+		if checks.get() > max_checks {
+			return Err(mlua::Error::runtime("too many instructions"));
+		}
+		Ok(())
+	});
+
+	thread.into_async(args).await
+}
+
+/// Creates a state with only the safe libraries loaded.
+/// Scripts are limited in how much they can allocate,
+/// and can't reach the filesystem unless imports are allowed.
+fn new_lua(script: &Script) -> mlua::Result<Lua> {
+	let config = &script.config;
+	let mut libs = StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8;
+	if config.allow_imports {
+		libs |= StdLib::PACKAGE;
+	}
+
+	let lua = Lua::new_with(libs, LuaOptions::default())?;
+	lua.set_memory_limit(config.max_memory)?;
+	Ok(lua)
+}
+
+/// Removes ways of loading code or escaping the instruction limit,
+/// and sends printing to the servers output.
+fn restrict_globals(lua: &Lua, script: &Script) -> mlua::Result<()> {
+	let globals = lua.globals();
+	for name in ["dofile", "loadfile", "load", "coroutine"].iter() {
+		globals.set(*name, Value::Nil)?;
+	}
+
+	if script.config.allow_imports {
+		let package: Table = globals.get("package")?;
+		let path = script.directory.join("?.lua");
+		package.set("path", path.to_string_lossy().to_string())?;
+		package.set("cpath", "")?;
+		package.set("loadlib", Value::Nil)?;
+		package
+			.get::<_, Table>("loaded")?
+			.set("coroutine", Value::Nil)?;
+	}
+
+	let id = script.details.id.clone();
+	let print = lua.create_function(move |_, values: Variadic<Value>| {
+		let values = values
+			.iter()
+			.map(|v| v.to_string().unwrap_or_default())
+			.collect::<Vec<_>>();
+		info!(plugin = id.as_str(); "{}", values.join("\t"));
+		Ok(())
+	})?;
+	globals.set("print", print)
+}
+
+/// Exposes the plugin api to the script as the `chat` table,
+/// and its stored values as the `store` table.
+fn register_api(
+	lua: &Lua,
+	script: &Script,
+	api: PluginApi,
+	store: Rc<RefCell<Store>>,
+) -> mlua::Result<()> {
+	let chat = lua.create_table()?;
+
+	let a = api.clone();
+	chat.set(
+		"send_global",
+		lua.create_async_function(move |_, content: String| {
+			let a = a.clone();
+			async move { a.send_global_message(content).await.map_err(api_error) }
+		})?,
+	)?;
+
+	let a = api.clone();
+	chat.set(
+		"send_private",
+		lua.create_async_function(move |_, (to, content): (String, String)| {
+			let a = a.clone();
+			async move {
+				a.send_private_message(parse_uuid(&to)?, content)
+					.await
+					.map_err(api_error)
+			}
+		})?,
+	)?;
+
+	let a = api.clone();
+	chat.set(
+		"disconnect",
+		lua.create_async_function(move |_, uuid: String| {
+			let a = a.clone();
+			async move {
+				a.disconnect_user(parse_uuid(&uuid)?)
+					.await
+					.map_err(api_error)
+			}
+		})?,
+	)?;
+
+	let a = api.clone();
+	chat.set(
+		"list_clients",
+		lua.create_async_function(move |lua, ()| {
+			let a = a.clone();
+			async move {
+				let clients = a.list_clients().await.map_err(api_error)?;
+				let clients = clients
+					.into_iter()
+					.map(|c| client_table(lua, c))
+					.collect::<mlua::Result<Vec<_>>>()?;
+				lua.create_sequence_from(clients)
+			}
+		})?,
+	)?;
+
+	let a = api.clone();
+	chat.set(
+		"history",
+		lua.create_async_function(move |lua, ()| {
+			let a = a.clone();
+			async move {
+				let messages = a.get_history().await.map_err(api_error)?;
+				let messages = messages
+					.into_iter()
+					.map(|m| message_table(lua, m))
+					.collect::<mlua::Result<Vec<_>>>()?;
+				lua.create_sequence_from(messages)
+			}
+		})?,
+	)?;
+
+	let a = api.clone();
+	chat.set(
+		"register_command",
+		lua.create_async_function(
+			move |_, (name, description): (String, String)| {
+				let a = a.clone();
+				async move {
+					a.register_command(name, description)
+						.await
+						.map_err(api_error)
+				}
+			},
+		)?,
+	)?;
+
+	chat.set(
+		"bot",
+		lua.create_function(move |lua, ()| client_table(lua, api.bot()))?,
+	)?;
+
+	lua.globals().set("chat", chat)?;
+	lua.globals().set("store", store_table(lua, script, store)?)
+}
+
+/// Values are kept as json, so only what json can hold is stored.
+fn store_table<'lua>(
+	lua: &'lua Lua,
+	script: &Script,
+	store: Rc<RefCell<Store>>,
+) -> mlua::Result<Table<'lua>> {
+	let table = lua.create_table()?;
+	let options = SerializeOptions::new()
+		.serialize_none_to_null(false)
+		.serialize_unit_to_null(false);
+
+	let s = store.clone();
+	table.set(
+		"get",
+		lua.create_function(move |lua, key: String| {
+			match s.borrow().values.get(&key) {
+				Some(value) => lua.to_value_with(value, options),
+				None => Ok(Value::Nil),
+			}
+		})?,
+	)?;
+
+	let id = script.details.id.clone();
+	let config = script.config.clone();
+	table.set(
+		"set",
+		lua.create_function(move |lua, (key, value): (String, Value)| {
+			let mut store = store.borrow_mut();
+			if value.is_nil() {
+				store.remove(&key);
+				return Ok(());
+			}
+
+			let error = |e: String| {
+				mlua::Error::runtime(format!("{} can't store {}: {}", id, key, e))
+			};
+			let value = lua.from_value(value).map_err(|e| error(e.to_string()))?;
+			store.set(key.clone(), value, &config).map_err(error)
+		})?,
+	)?;
+
+	Ok(table)
+}
+
+fn api_error(e: PluginApiError) -> mlua::Error {
+	mlua::Error::runtime(e)
+}
+
+fn parse_uuid(uuid: &str) -> mlua::Result<Uuid> {
+	uuid
+		.parse()
+		.map_err(|_| mlua::Error::runtime(format!("invalid uuid {}", uuid)))
+}
+
+fn client_table(lua: &Lua, client: ClientDetails) -> mlua::Result<Table<'_>> {
+	let table = lua.create_table()?;
+	table.set("uuid", client.uuid)?;
+	table.set("name", client.name)?;
+	table.set("address", client.address)?;
+	table.set("role", client.role)?;
+	Ok(table)
+}
+
+fn message_table(lua: &Lua, message: GlobalMessage) -> mlua::Result<Table<'_>> {
+	let table = lua.create_table()?;
+	table.set("uuid", message.uuid)?;
+	table.set("from", message.from)?;
+	table.set("content", message.content)?;
+	Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	fn config() -> ScriptConfig {
+		ScriptConfig {
+			max_store_entries: 2,
+			max_store_bytes: 32,
+			..ScriptConfig::default()
+		}
+	}
+
+	#[test]
+	fn stores_are_limited_in_entries() {
+		let mut store = Store::default();
+		store.set("a".into(), json!(1), &config()).unwrap();
+		store.set("b".into(), json!(2), &config()).unwrap();
+		assert!(store.set("c".into(), json!(3), &config()).is_err());
+
+		// existing values can still be replaced
+		store.set("a".into(), json!(4), &config()).unwrap();
+		store.remove("b");
+		store.set("c".into(), json!(3), &config()).unwrap();
+	}
+
+	#[test]
+	fn stores_are_limited_in_bytes() {
+		let mut store = Store::default();
+		let value = json!("x".repeat(20));
+		store.set("a".into(), value.clone(), &config()).unwrap();
+		assert!(store.set("b".into(), value.clone(), &config()).is_err());
+
+		store.remove("a");
+		assert_eq!(store.bytes, 0);
+		store.set("b".into(), value, &config()).unwrap();
+	}
+}
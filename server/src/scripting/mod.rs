@@ -0,0 +1,150 @@
+//! Plugins written as scripts.
+//!
+//! Scripts are loaded from the plugins directory like libraries,
+//! and are given the same permissions, events and api.
+//! Script engines can't be shared between threads,
+//! so each script runs on its own thread, fed events over a channel.
+
+pub mod lua_plugin;
+pub mod rhai_plugin;
+
+use std::{
+	path::Path,
+	sync::{Arc, Mutex},
+	thread,
+};
+
+use foundation::{
+	event::{Event, EventKind, EventResult},
+	plugin::{CommandInvocation, Plugin, PluginDetails},
+};
+use log::error;
+use tokio::sync::{
+	mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+	oneshot,
+};
+
+use crate::{
+	config::ScriptConfig,
+	metrics::METRICS,
+	scripting::{lua_plugin::LuaPlugin, rhai_plugin::RhaiPlugin},
+};
+
+/// Whether the file is a script the server can run.
+pub fn is_script(path: &Path) -> bool {
+	path.extension().is_some_and(|e| e == "rhai" || e == "lua")
+}
+
+/// Loads a script as a plugin, picking the language from its extension.
+pub fn load_script(
+	path: &Path,
+	config: &ScriptConfig,
+) -> Result<Plugin, String> {
+	match path.extension().and_then(|e| e.to_str()) {
+		Some("rhai") => Ok(Arc::new(RhaiPlugin::load(path, config)?)),
+		Some("lua") => Ok(Arc::new(LuaPlugin::load(path, config)?)),
+		_ => Err("not a script".into()),
+	}
+}
+
+/// The script function handling each kind of event.
+pub fn handler_name(kind: EventKind) -> &'static str {
+	match kind {
+		EventKind::ConnectionAccepted => "on_connection_accepted",
+		EventKind::ClientJoined => "on_client_joined",
+		EventKind::ClientLeft => "on_client_left",
+		EventKind::GlobalMessage => "on_global_message",
+		EventKind::PrivateMessage => "on_private_message",
+		EventKind::Shutdown => "on_shutdown",
+	}
+}
+
+/// The script function handling commands.
+pub const COMMAND_HANDLER: &str = "on_command";
+
+/// Scripts are named after their file, such as "script.greeter".
+pub fn script_details(path: &Path) -> PluginDetails {
+	let name = path
+		.file_stem()
+		.map(|s| s.to_string_lossy().to_string())
+		.unwrap_or_default();
+
+	PluginDetails {
+		id: format!("script.{}", name),
+		display_name: name,
+		version: "script".into(),
+		contacts: Vec::new(),
+	}
+}
+
+pub enum ScriptMessage {
+	Event {
+		event: Event,
+		reply: oneshot::Sender<EventResult>,
+	},
+	Command {
+		command: CommandInvocation,
+		reply: oneshot::Sender<Option<String>>,
+	},
+}
+
+/// # ScriptThread
+/// The thread a script runs on while its plugin is started.
+#[derive(Debug, Default)]
+pub struct ScriptThread {
+	sender: Mutex<Option<UnboundedSender<ScriptMessage>>>,
+}
+
+impl ScriptThread {
+	/// Starts the thread, which runs until stopped.
+	pub fn start<F>(&self, name: String, run: F)
+	where
+		F: FnOnce(UnboundedReceiver<ScriptMessage>) + Send + 'static,
+	{
+		let (tx, rx) = unbounded_channel();
+		let spawned = thread::Builder::new()
+			.name(name.clone())
+			.spawn(move || run(rx));
+
+		match spawned {
+			Ok(_) => *self.sender.lock().unwrap() = Some(tx),
+			Err(e) => {
+				error!(plugin = name.as_str(); "failed to start thread: {}", e);
+				METRICS.plugin_errors.inc(&name);
+			}
+		}
+	}
+
+	/// Closes the channel, so the thread exits after its current message.
+	pub fn stop(&self) {
+		self.sender.lock().unwrap().take();
+	}
+
+	/// Events are let through if the script isn't running.
+	pub async fn on_event(&self, event: &Event) -> EventResult {
+		let (reply, result) = oneshot::channel();
+		self.send(ScriptMessage::Event {
+			event: event.clone(),
+			reply,
+		});
+		result.await.unwrap_or(EventResult::Continue)
+	}
+
+	pub async fn on_command(
+		&self,
+		command: &CommandInvocation,
+	) -> Option<String> {
+		let (reply, result) = oneshot::channel();
+		self.send(ScriptMessage::Command {
+			command: command.clone(),
+			reply,
+		});
+		result.await.ok().flatten()
+	}
+
+	fn send(&self, message: ScriptMessage) {
+		if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+			_ = sender.send(message);
+		}
+	}
+}
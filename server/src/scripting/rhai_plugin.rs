@@ -0,0 +1,341 @@
+use std::path::{Path, PathBuf};
+
+use foundation::{
+	event::{Event, EventFilter, EventKind, EventResult},
+	plugin::{
+		async_trait,
+		CommandInvocation,
+		IPlugin,
+		PluginApi,
+		PluginApiError,
+		PluginDetails,
+	},
+	prelude::{ClientDetails, GlobalMessage},
+};
+use log::{debug, info, warn};
+use rhai::{
+	module_resolvers::{DummyModuleResolver, FileModuleResolver},
+	serde::{from_dynamic, to_dynamic},
+	Array,
+	CallFnOptions,
+	Dynamic,
+	Engine,
+	EvalAltResult,
+	Map,
+	Scope,
+	AST,
+};
+use tokio::{runtime::Handle, sync::mpsc::UnboundedReceiver};
+use uuid::Uuid;
+
+use crate::{
+	config::ScriptConfig,
+	metrics::METRICS,
+	scripting::{
+		handler_name,
+		script_details,
+		ScriptMessage,
+		ScriptThread,
+		COMMAND_HANDLER,
+	},
+};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Everything needed to start the script on its thread.
+#[derive(Debug, Clone)]
+struct Script {
+	details: PluginDetails,
+	source: String,
+	config: ScriptConfig,
+	/// where imported scripts are found, if imports are allowed.
+	directory: PathBuf,
+}
+
+/// # RhaiPlugin
+/// A plugin written in rhai.
+///
+/// The script's top level is run when the plugin starts,
+/// and can register commands with `register_command(name, description)`.
+/// Events are handled by functions named after them, such as `on_global_message`,
+/// which are given the event as a map.
+/// Returning `false` vetoes the event, and returning a map rewrites it.
+/// Commands are handled by `on_command`, which may return a reply.
+#[derive(Debug)]
+pub struct RhaiPlugin {
+	script: Script,
+	filter: EventFilter,
+	thread: ScriptThread,
+}
+
+impl RhaiPlugin {
+	/// Reads and compiles the script, so errors are found when it's loaded.
+	pub fn load(path: &Path, config: &ScriptConfig) -> Result<Self, String> {
+		let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+		let script = Script {
+			details: script_details(path),
+			source,
+			config: config.clone(),
+			directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+		};
+
+		let ast = new_engine(&script)
+			.compile(&script.source)
+			.map_err(|e| e.to_string())?;
+		let kinds = EventKind::ALL
+			.iter()
+			.copied()
+			.filter(|kind| has_function(&ast, handler_name(*kind)))
+			.collect();
+
+		Ok(Self {
+			script,
+			filter: EventFilter::Only(kinds),
+			thread: ScriptThread::default(),
+		})
+	}
+}
+
+#[async_trait]
+impl IPlugin for RhaiPlugin {
+	fn details(&self) -> PluginDetails {
+		self.script.details.clone()
+	}
+
+	fn init(&self, api: PluginApi) {
+		let script = self.script.clone();
+		let handle = Handle::current();
+		self
+			.thread
+			.start(self.script.details.id.clone(), move |receiver| {
+				run_script(script, api, handle, receiver)
+			});
+	}
+
+	fn deinit(&self) {
+		self.thread.stop();
+	}
+
+	fn event_filter(&self) -> EventFilter {
+		self.filter.clone()
+	}
+
+	async fn on_event(&self, event: &Event) -> EventResult {
+		self.thread.on_event(event).await
+	}
+
+	async fn on_command(&self, command: &CommandInvocation) -> Option<String> {
+		self.thread.on_command(command).await
+	}
+}
+
+/// Runs the script's top level, then answers its messages until stopped.
+fn run_script(
+	script: Script,
+	api: PluginApi,
+	handle: Handle,
+	mut receiver: UnboundedReceiver<ScriptMessage>,
+) {
+	let id = script.details.id.clone();
+	let mut engine = new_engine(&script);
+	register_api(&mut engine, api, handle);
+
+	let ast = match engine.compile(&script.source) {
+		Ok(ast) => ast,
+		Err(e) => {
+			warn!(plugin = id.as_str(); "failed to compile: {}", e);
+			METRICS.plugin_errors.inc(&id);
+			return;
+		}
+	};
+
+	if let Err(e) = engine.run_ast(&ast) {
+		warn!(plugin = id.as_str(); "failed to start: {}", e);
+		METRICS.plugin_errors.inc(&id);
+	}
+
+	while let Some(message) = receiver.blocking_recv() {
+		match message {
+			ScriptMessage::Event { event, reply } => {
+				_ = reply.send(handle_event(&engine, &ast, &id, &event));
+			}
+			ScriptMessage::Command { command, reply } => {
+				_ = reply.send(handle_command(&engine, &ast, &id, &command));
+			}
+		}
+	}
+}
+
+fn handle_event(
+	engine: &Engine,
+	ast: &AST,
+	id: &str,
+	event: &Event,
+) -> EventResult {
+	let name = handler_name(event.kind());
+	let value = match to_dynamic(event).and_then(|e| call(engine, ast, name, e)) {
+		Ok(value) => value,
+		Err(e) => {
+			warn!(plugin = id; "failed in {}: {}", name, e);
+			METRICS.plugin_errors.inc(id);
+			return EventResult::Continue;
+		}
+	};
+
+	if value.as_bool() == Ok(false) {
+		return EventResult::Veto;
+	}
+	if !value.is_map() {
+		return EventResult::Continue;
+	}
+
+	match from_dynamic::<Event>(&value) {
+		Ok(event) => EventResult::Rewrite(event),
+		Err(e) => {
+			warn!(plugin = id; "returned an invalid event: {}", e);
+			METRICS.plugin_errors.inc(id);
+			EventResult::Continue
+		}
+	}
+}
+
+fn handle_command(
+	engine: &Engine,
+	ast: &AST,
+	id: &str,
+	command: &CommandInvocation,
+) -> Option<String> {
+	let result =
+		to_dynamic(command).and_then(|c| call(engine, ast, COMMAND_HANDLER, c));
+
+	match result {
+		Ok(value) => value.into_string().ok(),
+		Err(e) => {
+			warn!(plugin = id; "failed in {}: {}", COMMAND_HANDLER, e);
+			METRICS.plugin_errors.inc(id);
+			None
+		}
+	}
+}
+
+/// Calls a function without running the script's top level again.
+fn call(
+	engine: &Engine,
+	ast: &AST,
+	name: &str,
+	arg: Dynamic,
+) -> ScriptResult<Dynamic> {
+	let options = CallFnOptions::new().eval_ast(false);
+	engine.call_fn_with_options(options, &mut Scope::new(), ast, name, (arg,))
+}
+
+fn has_function(ast: &AST, name: &str) -> bool {
+	ast.iter_functions().any(|f| f.name == name)
+}
+
+/// Creates a sandboxed engine.
+/// Scripts are limited in how much they can run and allocate,
+/// and can't reach the filesystem unless imports are allowed.
+fn new_engine(script: &Script) -> Engine {
+	let config = &script.config;
+	let mut engine = Engine::new();
+	engine
+		.set_max_operations(config.max_operations)
+		.set_max_string_size(config.max_string_size)
+		.set_max_array_size(config.max_collection_size)
+		.set_max_map_size(config.max_collection_size)
+		.set_max_call_levels(32)
+		.set_max_expr_depths(64, 32)
+		.disable_symbol("eval");
+
+	if config.allow_imports {
+		engine.set_module_resolver(FileModuleResolver::new_with_path(
+			&script.directory,
+		));
+	} else {
+		engine.set_module_resolver(DummyModuleResolver::new());
+	}
+
+	let id = script.details.id.clone();
+	engine.on_print(move |s| info!(plugin = id.as_str(); "{}", s));
+	let id = script.details.id.clone();
+	engine
+		.on_debug(move |s, _, pos| debug!(plugin = id.as_str(); "{}: {}", pos, s));
+
+	engine
+}
+
+/// Exposes the plugin api to the script.
+/// Calls block the script's thread until the server answers.
+fn register_api(engine: &mut Engine, api: PluginApi, handle: Handle) {
+	let (a, h) = (api.clone(), handle.clone());
+	engine.register_fn("send_global", move |content: &str| -> ScriptResult<()> {
+		h.block_on(a.send_global_message(content.into()))
+			.map_err(api_error)
+	});
+
+	let (a, h) = (api.clone(), handle.clone());
+	engine.register_fn(
+		"send_private",
+		move |to: &str, content: &str| -> ScriptResult<()> {
+			h.block_on(a.send_private_message(parse_uuid(to)?, content.into()))
+				.map_err(api_error)
+		},
+	);
+
+	let (a, h) = (api.clone(), handle.clone());
+	engine.register_fn("disconnect", move |uuid: &str| -> ScriptResult<()> {
+		h.block_on(a.disconnect_user(parse_uuid(uuid)?))
+			.map_err(api_error)
+	});
+
+	let (a, h) = (api.clone(), handle.clone());
+	engine.register_fn("list_clients", move || -> ScriptResult<Array> {
+		let clients = h.block_on(a.list_clients()).map_err(api_error)?;
+		Ok(clients.into_iter().map(client_map).collect())
+	});
+
+	let (a, h) = (api.clone(), handle.clone());
+	engine.register_fn("history", move || -> ScriptResult<Array> {
+		let messages = h.block_on(a.get_history()).map_err(api_error)?;
+		Ok(messages.into_iter().map(message_map).collect())
+	});
+
+	let (a, h) = (api.clone(), handle);
+	engine.register_fn(
+		"register_command",
+		move |name: &str, description: &str| -> ScriptResult<()> {
+			h.block_on(a.register_command(name.into(), description.into()))
+				.map_err(api_error)
+		},
+	);
+
+	engine.register_fn("bot", move || client_map(api.bot()));
+}
+
+fn api_error(e: PluginApiError) -> Box<EvalAltResult> {
+	e.to_string().into()
+}
+
+fn parse_uuid(uuid: &str) -> ScriptResult<Uuid> {
+	uuid
+		.parse()
+		.map_err(|_| format!("invalid uuid {}", uuid).into())
+}
+
+fn client_map(client: ClientDetails) -> Dynamic {
+	let mut map = Map::new();
+	map.insert("uuid".into(), client.uuid.into());
+	map.insert("name".into(), client.name.into());
+	map.insert("address".into(), client.address.into());
+	map.insert("role".into(), client.role.into());
+	map.into()
+}
+
+fn message_map(message: GlobalMessage) -> Dynamic {
+	let mut map = Map::new();
+	map.insert("uuid".into(), message.uuid.into());
+	map.insert("from".into(), message.from.into());
+	map.insert("content".into(), message.content.into());
+	map.into()
+}
@@ -1,34 +1,69 @@
-use foundation::prelude::GlobalMessage;
+use std::{
+	net::SocketAddr,
+	path::PathBuf,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use foundation::{
+	admin::{AdminRequest, AdminResponse, ServerStats},
+	event::Event,
+	plugin::CommandInvocation,
+	prelude::GlobalMessage,
+};
+use log::{debug, error, info};
 use tokio::{
 	sync::{
 		mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+		oneshot,
 		Mutex,
 	},
 	task::JoinHandle,
+	time::timeout,
 };
 use uuid::Uuid;
 
 use crate::{
+	admin::admin_listener::AdminListener,
 	chat::ChatManager,
+	config::{FederationConfig, ServerConfig},
 	connection::connection_manager::{
 		ConnectionManager,
 		ConnectionManagerMessage,
 	},
+	event_bus::EventBus,
+	federation::{
+		peer_connector::PeerConnector,
+		peer_link::PeerIdentity,
+		peer_listener::PeerListener,
+		peer_manager::PeerManager,
+	},
+	metrics::{metrics_listener::MetricsListener, METRICS},
+	moderation::parse_duration,
 	network::{
+		connection_limiter::{ConnectionLimiter, ConnectionPermit},
+		discovery_announcer::DiscoveryAnnouncer,
 		json::{
 			json_listener::JSONListener,
 			json_network_connection::JSONNetworkConnection,
 		},
+		metered_stream::MeteredStream,
 		protobuf::{
 			protobuf_listener::ProtobufListener,
 			protobuf_network_connection::ProtobufNetworkConnection,
 		},
+		unified_listener::UnifiedListener,
+		websocket::{
+			websocket_listener::WebSocketListener,
+			websocket_stream::WebSocketProtocol,
+		},
 		ConnectionType,
 		NetworkConnection,
 		NetworkListener,
 		ServerRequest,
 	},
 	os_signal_manager::OSSignalManager,
+	plugin::PluginManager,
 };
 
 /// # Server
@@ -36,19 +71,154 @@ use crate::{
 /// Main functions being the handling of new connections, and setting them up.
 pub struct Server {
 	connection_manager_sender: UnboundedSender<ConnectionManagerMessage>,
+	event_bus: Arc<EventBus>,
 
 	chat_manager: ChatManager,
+	/// checked before each new connection is handed a task.
+	limiter: Arc<ConnectionLimiter>,
+	/// where the config is read from again when reloaded.
+	config_path: PathBuf,
+	started: Instant,
+	/// only set if plugins are enabled.
+	plugin_manager: Option<Arc<PluginManager>>,
 
 	connection_manager_task: JoinHandle<()>,
 	listener_task: JoinHandle<()>,
 	json_listener_task: JoinHandle<()>,
+	websocket_listener_task: JoinHandle<()>,
+	unified_listener_task: JoinHandle<()>,
+	/// only running if discovery is enabled.
+	discovery_task: Option<JoinHandle<()>>,
+	/// the peer manager, listener and connectors, if federation is enabled.
+	federation_tasks: Vec<JoinHandle<()>>,
+	/// only running if the admin interface is enabled.
+	admin_task: Option<JoinHandle<()>>,
+	/// only running if metrics are enabled.
+	metrics_task: Option<JoinHandle<()>>,
 
 	os_event_manager_task: JoinHandle<()>,
 
 	receiver: Mutex<UnboundedReceiver<ServerMessages>>,
+	/// lets the server message itself, such as when an admin shuts it down.
+	sender: UnboundedSender<ServerMessages>,
 }
 
 impl Server {
+	/// Creates the server and starts all of its sub-tasks.
+	/// Fails if the connection manager can't load its files,
+	/// or federation is enabled without a secret.
+	pub fn new(
+		config: ServerConfig,
+		config_path: PathBuf,
+	) -> Result<Self, String> {
+		if config.federation.enabled && config.federation.secret.is_empty() {
+			return Err("federation needs a secret shared by the mesh".into());
+		}
+
+		let (tx, rx) = unbounded_channel();
+		let tx1 = tx.clone();
+		let tx2 = tx.clone();
+		let tx3 = tx.clone();
+		let tx4 = tx.clone();
+		let tx5 = tx.clone();
+		let tx6 = tx.clone();
+		let tx7 = tx.clone();
+		let tx8 = tx.clone();
+		let tx9 = tx.clone();
+
+		let event_bus = EventBus::new();
+
+		let server_name = config.info.name.clone();
+		let mut connection_manager =
+			ConnectionManager::new(tx4, event_bus.clone(), &config)?;
+		let connection_manager_sender = connection_manager.get_sender();
+
+		let os_event_manager_task = tokio::spawn(async move {
+			OSSignalManager::new(tx1).run().await;
+		});
+
+		let limiter = ConnectionLimiter::new(config.connections);
+		let listener_task = ProtobufListener::start_run(tx2, limiter.clone());
+		let json_listener_task = JSONListener::start_run(tx3, limiter.clone());
+		let websocket_listener_task =
+			WebSocketListener::start_run(tx5, limiter.clone());
+		let unified_listener_task =
+			UnifiedListener::start_run(tx6, limiter.clone());
+
+		let federation_tasks = if config.federation.enabled {
+			Self::start_federation(
+				config.federation,
+				server_name,
+				&mut connection_manager,
+			)
+		} else {
+			Vec::new()
+		};
+
+		let connection_manager_task = tokio::spawn(async move {
+			connection_manager.run().await;
+		});
+
+		let discovery = config.discovery;
+		let discovery_task = discovery.enabled.then(|| {
+			DiscoveryAnnouncer::start_run(
+				discovery,
+				connection_manager_sender.clone(),
+			)
+		});
+
+		let plugins = config.plugins;
+		let plugin_manager = plugins.enabled.then(|| {
+			let plugin_manager = PluginManager::new(
+				plugins,
+				event_bus.clone(),
+				connection_manager_sender.clone(),
+				tx7,
+			);
+			let loader = plugin_manager.clone();
+			tokio::spawn(async move {
+				if let Err(e) = loader.load().await {
+					error!("failed to load plugins: {}", e);
+				}
+			});
+			plugin_manager
+		});
+
+		let admin = config.admin;
+		let admin_task =
+			admin.enabled.then(|| AdminListener::start_run(admin, tx8));
+
+		let metrics = config.metrics;
+		let metrics_task =
+			metrics.enabled.then(|| MetricsListener::start_run(metrics));
+
+		let chat_manager = ChatManager::new();
+
+		Ok(Self {
+			event_bus,
+			chat_manager,
+			limiter,
+			config_path,
+			started: Instant::now(),
+			sender: tx9,
+			plugin_manager,
+
+			os_event_manager_task,
+			connection_manager_task,
+			connection_manager_sender,
+
+			json_listener_task,
+			websocket_listener_task,
+			unified_listener_task,
+			discovery_task,
+			federation_tasks,
+			admin_task,
+			metrics_task,
+			receiver: Mutex::new(rx),
+			listener_task,
+		})
+	}
+
 	/// Loops the future, reading messages from the servers channel.
 	/// if exit is received, deconstructs all sub-tasks and exits the loop.
 	pub async fn run(&mut self) {
@@ -59,117 +229,386 @@ impl Server {
 
 			match msg {
 				Some(ServerMessages::Exit) | None => {
-					println!("[Server] Shutting down");
-					self.shutdown();
+					info!("shutting down");
+					self.event_bus.publish(Event::Shutdown).await;
+					self.shutdown().await;
 					return;
 				}
-				Some(ServerMessages::NewConnection(
-					ConnectionType::ProtobufConnection(stream, addr),
-				)) => {
-					let conn = Box::new(ProtobufNetworkConnection::new(stream, addr));
-					println!("[Server] New protobuf connection");
-					self.handle_protobuf_connection(conn).await;
-				}
-				Some(ServerMessages::NewConnection(
-					ConnectionType::JsonConnection(stream, addr),
-				)) => {
-					let conn = Box::new(JSONNetworkConnection::new(stream, addr));
-					println!("[Server] New json connection");
-					self.handle_protobuf_connection(conn).await;
+				Some(ServerMessages::NewConnection { connection, permit }) => {
+					self.handle_new_connection(connection, permit);
 				}
-				Some(ServerMessages::SendGlobalMessages(uuid)) => {
+				Some(ServerMessages::SendGlobalMessages { uuid, session }) => {
 					let messages = self.chat_manager.get_messages();
-					println!("[Server] Sending Global Messages");
+					debug!(uuid:% = uuid; "sending global messages");
 					_ = self.connection_manager_sender.send(
-						ConnectionManagerMessage::SendGlobalMessagesTo { uuid, messages },
+						ConnectionManagerMessage::SendGlobalMessagesTo {
+							uuid,
+							session,
+							messages,
+						},
 					);
 				}
 				Some(ServerMessages::AddGlobalMessage(message)) => {
 					self.chat_manager.add_message(message);
 				}
+				Some(ServerMessages::GetGlobalMessages { reply }) => {
+					_ = reply.send(self.chat_manager.get_messages());
+				}
+				Some(ServerMessages::Admin { request, reply }) => {
+					self.handle_admin(request, reply)
+				}
+				Some(ServerMessages::RunPluginCommand { plugin_id, command }) => {
+					// commands are run in their own task so plugins can't stall the server
+					if let Some(plugin_manager) = self.plugin_manager.clone() {
+						tokio::spawn(async move {
+							plugin_manager.run_command(&plugin_id, command).await;
+						});
+					}
+				}
 			};
 		}
 	}
 
-	async fn handle_protobuf_connection(
-		&self,
-		mut conn: Box<dyn NetworkConnection>,
-	) {
-		println!("[Server] Getting request");
-		let req = conn.get_request().await;
-
-		let Ok(req) = req else {
-			println!("[Server] Got invalid request");
-			return;
+	/// Starts linking with other servers,
+	/// and connects the connection manager to the mesh.
+	fn start_federation(
+		config: FederationConfig,
+		server_name: String,
+		connection_manager: &mut ConnectionManager,
+	) -> Vec<JoinHandle<()>> {
+		info!(server_id:% = config.server_id; "starting federation");
+		let identity = PeerIdentity {
+			server_id: config.server_id,
+			server_name,
+			secret: Arc::new(config.secret.clone()),
 		};
+		let mut peer_manager =
+			PeerManager::new(config.server_id, connection_manager.get_sender());
+		let peer_sender = peer_manager.get_sender();
+		connection_manager.set_peer_sender(peer_sender.clone());
 
-		match req {
-			ServerRequest::GetInfo => {
-				conn
-					.send_info("test server".into(), "mickyb18a@gmail.com".into())
-					.await
+		let mut tasks = vec![tokio::spawn(async move {
+			peer_manager.run().await;
+		})];
+
+		tasks.extend(config.peers.iter().map(|address| {
+			PeerConnector::new(
+				address.clone(),
+				identity.clone(),
+				config.reconnect_delay(),
+				peer_sender.clone(),
+			)
+			.start_run()
+		}));
+
+		tasks.push(PeerListener::start_run(config, identity, peer_sender));
+		tasks
+	}
+
+	/// Answers an admin request.
+	/// Requests wait on other components, so are answered from their own task.
+	fn handle_admin(
+		&self,
+		request: AdminRequest,
+		reply: oneshot::Sender<AdminResponse>,
+	) {
+		let connection_manager = self.connection_manager_sender.clone();
+		let (server, response) = match request {
+			AdminRequest::ListClients => {
+				let (tx, rx) = oneshot::channel();
+				_ = connection_manager
+					.send(ConnectionManagerMessage::ListClientStatuses { reply: tx });
+				tokio::spawn(async move {
+					_ = reply.send(match rx.await {
+						Ok(clients) => AdminResponse::Clients { clients },
+						Err(_) => closed(),
+					});
+				});
+				return;
+			}
+			AdminRequest::Broadcast { message } => {
+				_ = connection_manager
+					.send(ConnectionManagerMessage::Notice { content: message });
+				(None, AdminResponse::Ok)
 			}
-			ServerRequest::Connect {
-				username,
-				uuid,
-				addr,
+			AdminRequest::Kick { user, reason } => {
+				let (tx, rx) = oneshot::channel();
+				_ = connection_manager.send(ConnectionManagerMessage::AdminKick {
+					user,
+					reason,
+					reply: tx,
+				});
+				tokio::spawn(async move {
+					_ = reply.send(match rx.await {
+						Ok(Ok(())) => AdminResponse::Ok,
+						Ok(Err(reason)) => AdminResponse::Error { reason },
+						Err(_) => closed(),
+					});
+				});
+				return;
+			}
+			AdminRequest::Ban {
+				target,
+				duration,
+				reason,
 			} => {
-				println!("[Server] sending connectionn and info to conneciton manager");
-				_ = self.connection_manager_sender.send(
-					ConnectionManagerMessage::AddClient {
-						conn,
-						uuid,
-						username,
-						addr,
+				let duration = match duration.as_deref().map(parse_duration) {
+					Some(None) => {
+						_ = reply.send(AdminResponse::Error {
+							reason: "durations look like 30m, 2h or 7d".into(),
+						});
+						return;
+					}
+					duration => duration.flatten(),
+				};
+				let (tx, rx) = oneshot::channel();
+				_ = connection_manager.send(ConnectionManagerMessage::AdminBan {
+					target,
+					duration,
+					reason,
+					reply: tx,
+				});
+				tokio::spawn(async move {
+					_ = reply.send(match rx.await {
+						Ok(()) => AdminResponse::Ok,
+						Err(_) => closed(),
+					});
+				});
+				return;
+			}
+			AdminRequest::ReloadConfig => match ServerConfig::load(&self.config_path)
+			{
+				Ok(config) => {
+					self.limiter.configure(config.connections.clone());
+					_ = connection_manager
+						.send(ConnectionManagerMessage::ReloadConfig(Box::new(config)));
+					(None, AdminResponse::Ok)
+				}
+				Err(e) => (
+					None,
+					AdminResponse::Error {
+						reason: format!("failed to load config: {}", e),
 					},
-				);
+				),
+			},
+			AdminRequest::Stats => {
+				let (tx, rx) = oneshot::channel();
+				_ = connection_manager
+					.send(ConnectionManagerMessage::GetStats { reply: tx });
+				let server_stats = self.stats();
+				let plugin_manager = self.plugin_manager.clone();
+				tokio::spawn(async move {
+					let Ok(mut stats) = rx.await else {
+						_ = reply.send(closed());
+						return;
+					};
+					stats.uptime_secs = server_stats.uptime_secs;
+					stats.global_messages = server_stats.global_messages;
+					if let Some(plugin_manager) = plugin_manager {
+						stats.plugins = plugin_manager.list().await.len();
+					}
+					_ = reply.send(AdminResponse::Stats { stats });
+				});
+				return;
+			}
+			AdminRequest::Shutdown => (Some(ServerMessages::Exit), AdminResponse::Ok),
+			request => {
+				self.handle_plugin_admin(request, reply);
+				return;
 			}
-			ServerRequest::Ignore => todo!(),
+		};
+
+		_ = reply.send(response);
+		if let Some(message) = server {
+			// answered first, so the admin hears back before the server stops
+			_ = self.sender.send(message);
+		}
+	}
+
+	/// Answers a request to manage plugins.
+	fn handle_plugin_admin(
+		&self,
+		request: AdminRequest,
+		reply: oneshot::Sender<AdminResponse>,
+	) {
+		let Some(plugin_manager) = self.plugin_manager.clone() else {
+			_ = reply.send(AdminResponse::Error {
+				reason: "plugins are disabled".into(),
+			});
+			return;
+		};
+
+		tokio::spawn(async move {
+			let result = match request {
+				AdminRequest::ListPlugins => {
+					let plugins = plugin_manager.list().await;
+					_ = reply.send(AdminResponse::Plugins { plugins });
+					return;
+				}
+				AdminRequest::LoadPlugin { file } => {
+					plugin_manager.load_file(&file).await
+				}
+				AdminRequest::EnablePlugin { id }
+				| AdminRequest::ResumePlugin { id } => plugin_manager.start(&id).await,
+				AdminRequest::DisablePlugin { id } => plugin_manager.stop(&id).await,
+				AdminRequest::PausePlugin { id } => plugin_manager.pause(&id).await,
+				AdminRequest::ReloadPlugin { id } => plugin_manager.reload(&id).await,
+				AdminRequest::UnloadPlugin { id } => plugin_manager.unload(&id).await,
+				_ => return,
+			};
+
+			_ = reply.send(match result {
+				Ok(()) => AdminResponse::Ok,
+				Err(e) => AdminResponse::Error {
+					reason: e.to_string(),
+				},
+			});
+		});
+	}
+
+	/// What the server itself knows about what it's doing.
+	fn stats(&self) -> ServerStats {
+		ServerStats {
+			uptime_secs: self.started.elapsed().as_secs(),
+			global_messages: self.chat_manager.message_count(),
+			..Default::default()
 		}
 	}
 
-	fn shutdown(&self) {
+	/// Runs the connections handshake in its own task,
+	/// so a slow client can't hold up anyone else.
+	/// The permit was taken by the listener that accepted it.
+	fn handle_new_connection(
+		&self,
+		connection: ConnectionType,
+		permit: ConnectionPermit,
+	) {
+		let addr = connection.addr();
+		let event_bus = self.event_bus.clone();
+		let connection_manager = self.connection_manager_sender.clone();
+		let handshake_timeout = self.limiter.config().handshake_timeout();
+		tokio::spawn(async move {
+			if !accept_connection(&event_bus, addr).await {
+				return;
+			}
+
+			let conn: Box<dyn NetworkConnection> = match connection {
+				ConnectionType::ProtobufConnection(stream, _) => {
+					let stream = MeteredStream::new(stream, "protobuf", permit);
+					Box::new(ProtobufNetworkConnection::new(stream, addr))
+				}
+				ConnectionType::JsonConnection(stream, _) => {
+					let stream = MeteredStream::new(stream, "json", permit);
+					Box::new(JSONNetworkConnection::new(stream, addr))
+				}
+				ConnectionType::WebSocketConnection(stream, _) => {
+					let protocol = stream.get_protocol();
+					let stream = MeteredStream::new(stream, "websocket", permit);
+					match protocol {
+						WebSocketProtocol::Json => {
+							Box::new(JSONNetworkConnection::new(stream, addr))
+						}
+						WebSocketProtocol::Protobuf => {
+							Box::new(ProtobufNetworkConnection::new(stream, addr))
+						}
+					}
+				}
+			};
+			debug!(addr:% = addr; "new {} connection", conn.protocol());
+
+			handshake(conn, addr, handshake_timeout, connection_manager).await;
+		});
+	}
+
+	async fn shutdown(&self) {
+		// plugins are given the chance to deinitialise
+		if let Some(plugin_manager) = &self.plugin_manager {
+			plugin_manager.unload_all().await;
+		}
+
 		self.os_event_manager_task.abort();
 		self.connection_manager_task.abort();
 		self.json_listener_task.abort();
+		self.websocket_listener_task.abort();
+		self.unified_listener_task.abort();
+		if let Some(discovery_task) = &self.discovery_task {
+			discovery_task.abort();
+		}
+		for task in &self.federation_tasks {
+			task.abort();
+		}
+		if let Some(admin_task) = &self.admin_task {
+			admin_task.abort();
+		}
+		if let Some(metrics_task) = &self.metrics_task {
+			metrics_task.abort();
+		}
 		self.listener_task.abort();
 	}
 }
 
 impl Default for Server {
 	fn default() -> Self {
-		let (tx, rx) = unbounded_channel();
-		let tx1 = tx.clone();
-		let tx2 = tx.clone();
-		let tx3 = tx.clone();
-		let tx4 = tx.clone();
-
-		let os_event_manager_task = tokio::spawn(async move {
-			OSSignalManager::new(tx1).run().await;
-		});
-
-		let listener_task = ProtobufListener::start_run(tx2);
-		let json_listener_task = JSONListener::start_run(tx3);
-
-		let mut connection_manager = ConnectionManager::new(tx4);
-		let connection_manager_sender = connection_manager.get_sender();
-		let connection_manager_task = tokio::spawn(async move {
-			connection_manager.run().await;
-		});
-
-		let chat_manager = ChatManager::new();
+		Self::new(ServerConfig::default(), PathBuf::from("server.toml"))
+			.expect("failed to create the server")
+	}
+}
 
-		Self {
-			chat_manager,
+/// Asks subscribers whether to accept a new connection.
+/// Vetoed connections are dropped before their request is read.
+async fn accept_connection(event_bus: &EventBus, addr: SocketAddr) -> bool {
+	let event = Event::ConnectionAccepted { addr };
+	if event_bus.publish(event).await.is_none() {
+		debug!(addr:% = addr; "dropping connection");
+		return false;
+	}
+	true
+}
 
-			os_event_manager_task,
-			connection_manager_task,
-			connection_manager_sender,
+/// Reads the clients request, then passes the connection on to be answered.
+/// Clients that don't send one in time are dropped.
+async fn handshake(
+	mut conn: Box<dyn NetworkConnection>,
+	addr: SocketAddr,
+	limit: Duration,
+	connection_manager: UnboundedSender<ConnectionManagerMessage>,
+) {
+	let req = match timeout(limit, conn.get_request()).await {
+		Ok(Ok(req)) => req,
+		Ok(Err(e)) => {
+			info!(addr:% = addr; "got invalid request: {}", e);
+			return;
+		}
+		Err(_) => {
+			info!(addr:% = addr; "handshake timed out");
+			METRICS.handshakes.inc("timed_out");
+			return;
+		}
+	};
 
-			json_listener_task,
-			receiver: Mutex::new(rx),
-			listener_task,
+	match req {
+		ServerRequest::GetInfo => {
+			// the connection manager knows who is connected
+			_ = connection_manager.send(ConnectionManagerMessage::SendInfo { conn });
+		}
+		ServerRequest::Connect(request) => {
+			debug!(
+				uuid:% = request.uuid, addr:% = request.addr;
+				"passing connection to the connection manager"
+			);
+			_ = connection_manager
+				.send(ConnectionManagerMessage::AddClient { conn, request });
 		}
+		ServerRequest::Ignore => {
+			conn.send_rejected("expected a request".into()).await;
+		}
+	}
+}
+
+/// The response when a component stopped before answering.
+fn closed() -> AdminResponse {
+	AdminResponse::Error {
+		reason: "the server is shutting down".into(),
 	}
 }
 
@@ -178,6 +617,27 @@ impl Default for Server {
 pub enum ServerMessages {
 	Exit,
 	AddGlobalMessage(GlobalMessage),
-	SendGlobalMessages(Uuid),
-	NewConnection(ConnectionType),
+	SendGlobalMessages {
+		uuid: Uuid,
+		session: Uuid,
+	},
+	NewConnection {
+		connection: ConnectionType,
+		permit: ConnectionPermit,
+	},
+
+	// plugin api messages
+	GetGlobalMessages {
+		reply: oneshot::Sender<Vec<GlobalMessage>>,
+	},
+	RunPluginCommand {
+		plugin_id: String,
+		command: CommandInvocation,
+	},
+
+	// admin messages
+	Admin {
+		request: AdminRequest,
+		reply: oneshot::Sender<AdminResponse>,
+	},
 }